@@ -0,0 +1 @@
+ALTER TABLE "speciess" DROP COLUMN "updated_at";
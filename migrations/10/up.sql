@@ -0,0 +1 @@
+ALTER TABLE "speciess" ADD "updated_at" TIMESTAMP WITH TIME ZONE DEFAULT NULL;
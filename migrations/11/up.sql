@@ -0,0 +1 @@
+ALTER TABLE "medias" ADD "sha256" VARCHAR DEFAULT NULL;
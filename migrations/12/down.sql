@@ -0,0 +1 @@
+ALTER TABLE "ignored_speciess" DROP COLUMN "canonical_species_key";
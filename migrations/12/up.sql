@@ -0,0 +1 @@
+ALTER TABLE "ignored_speciess" ADD "canonical_species_key" BIGINT DEFAULT NULL;
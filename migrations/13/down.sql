@@ -0,0 +1,2 @@
+ALTER TABLE "occurrences" DROP COLUMN "longitude";
+ALTER TABLE "occurrences" DROP COLUMN "latitude";
@@ -0,0 +1,2 @@
+ALTER TABLE "occurrences" ADD "latitude" DOUBLE PRECISION DEFAULT NULL;
+ALTER TABLE "occurrences" ADD "longitude" DOUBLE PRECISION DEFAULT NULL;
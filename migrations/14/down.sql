@@ -0,0 +1,46 @@
+-- The sub-families kept by the migration are stored as genus again, and the cache of the species follows.
+UPDATE "speciess" SET "genus" = "speciess_sub_families"."genus"
+FROM "speciess_sub_families"
+WHERE "speciess"."id" = "speciess_sub_families"."id";
+
+UPDATE "ignored_speciess" SET "genus" = "ignored_speciess_sub_families"."genus"
+FROM "ignored_speciess_sub_families"
+WHERE "ignored_speciess"."id" = "ignored_speciess_sub_families"."id";
+
+DROP TABLE "speciess_sub_families";
+DROP TABLE "ignored_speciess_sub_families";
+
+UPDATE "species_metadatas" SET "genus" = "speciess"."genus"
+FROM "speciess"
+WHERE "species_metadatas"."species" = "speciess"."valid_name";
+
+DELETE FROM "species_metadatas" WHERE "species" IS NULL AND "genus" IS NOT NULL;
+
+INSERT INTO "species_metadatas"(
+    "reign",
+    "phylum",
+    "class",
+    "order",
+    "family",
+    "genus",
+    "example_media_path",
+    "species_count",
+    "medias_count",
+    "medias_downloaded_count",
+    "medias_cropped_count"
+)
+SELECT
+    "reign",
+    "phylum",
+    "class",
+    "order",
+    "family",
+    "genus",
+    (ARRAY_AGG("example_media_path") FILTER (WHERE "example_media_path" IS NOT NULL))[1],
+    SUM("species_count"),
+    SUM("medias_count"),
+    SUM("medias_downloaded_count"),
+    SUM("medias_cropped_count")
+FROM "species_metadatas"
+WHERE "species" IS NOT NULL
+GROUP BY "reign", "phylum", "class", "order", "family", "genus";
@@ -0,0 +1,52 @@
+-- The genus used to be the sub-family of taxref, it is now taken from the valid name. The sub-families are kept so
+-- that the migration can be reverted.
+CREATE TABLE "speciess_sub_families" AS SELECT "id", "genus" FROM "speciess";
+CREATE TABLE "ignored_speciess_sub_families" AS SELECT "id", "genus" FROM "ignored_speciess";
+
+-- The first word of the valid name, with the hybrid sign of the intergeneric hybrids such as "× Triticosecale".
+UPDATE "speciess" SET "genus" = CASE
+    WHEN "valid_name" ~ '^(×|x\s)' THEN '× ' || split_part(regexp_replace("valid_name", '^(×|x)\s*', ''), ' ', 1)
+    ELSE split_part("valid_name", ' ', 1)
+END;
+
+UPDATE "ignored_speciess" SET "genus" = CASE
+    WHEN "valid_name" ~ '^(×|x\s)' THEN '× ' || split_part(regexp_replace("valid_name", '^(×|x)\s*', ''), ' ', 1)
+    ELSE split_part("valid_name", ' ', 1)
+END;
+
+-- The cache of the species follows, and its genus level is computed again from its species level, like regen-cache
+-- does. The levels above the genus are computed from the species level, so they don't change.
+UPDATE "species_metadatas" SET "genus" = "speciess"."genus"
+FROM "speciess"
+WHERE "species_metadatas"."species" = "speciess"."valid_name";
+
+DELETE FROM "species_metadatas" WHERE "species" IS NULL AND "genus" IS NOT NULL;
+
+INSERT INTO "species_metadatas"(
+    "reign",
+    "phylum",
+    "class",
+    "order",
+    "family",
+    "genus",
+    "example_media_path",
+    "species_count",
+    "medias_count",
+    "medias_downloaded_count",
+    "medias_cropped_count"
+)
+SELECT
+    "reign",
+    "phylum",
+    "class",
+    "order",
+    "family",
+    "genus",
+    (ARRAY_AGG("example_media_path") FILTER (WHERE "example_media_path" IS NOT NULL))[1],
+    SUM("species_count"),
+    SUM("medias_count"),
+    SUM("medias_downloaded_count"),
+    SUM("medias_cropped_count")
+FROM "species_metadatas"
+WHERE "species" IS NOT NULL
+GROUP BY "reign", "phylum", "class", "order", "family", "genus";
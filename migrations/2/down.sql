@@ -0,0 +1,2 @@
+ALTER TABLE "medias" DROP COLUMN "final_url";
+ALTER TABLE "medias" DROP COLUMN "content_type";
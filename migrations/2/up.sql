@@ -0,0 +1,2 @@
+ALTER TABLE "medias" ADD "final_url" VARCHAR DEFAULT NULL;
+ALTER TABLE "medias" ADD "content_type" VARCHAR DEFAULT NULL;
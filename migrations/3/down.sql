@@ -0,0 +1 @@
+DROP TABLE "media_feedbacks" CASCADE;
\ No newline at end of file
@@ -0,0 +1,8 @@
+CREATE TABLE "media_feedbacks" (
+    "id" SERIAL PRIMARY KEY,
+    "media" INT NOT NULL REFERENCES medias (id) ON DELETE CASCADE,
+    "crop_ok" BOOL NOT NULL,
+    "note" VARCHAR,
+    "created_at" TIMESTAMP WITH TIME ZONE NOT NULL,
+    "ip" VARCHAR NOT NULL
+);
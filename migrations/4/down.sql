@@ -0,0 +1 @@
+DROP TABLE "datasets" CASCADE;
\ No newline at end of file
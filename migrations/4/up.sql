@@ -0,0 +1,6 @@
+CREATE TABLE "datasets" (
+    "id" SERIAL PRIMARY KEY,
+    "key" UUID NOT NULL UNIQUE,
+    "title" VARCHAR NOT NULL,
+    "publisher" VARCHAR
+);
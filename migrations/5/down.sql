@@ -0,0 +1 @@
+ALTER TABLE "speciess" DROP COLUMN "vernacular_name";
\ No newline at end of file
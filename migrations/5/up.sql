@@ -0,0 +1 @@
+ALTER TABLE "speciess" ADD "vernacular_name" VARCHAR DEFAULT NULL;
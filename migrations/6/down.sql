@@ -0,0 +1 @@
+ALTER TABLE "occurrences" DROP COLUMN "country";
\ No newline at end of file
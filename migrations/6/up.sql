@@ -0,0 +1 @@
+ALTER TABLE "occurrences" ADD "country" VARCHAR DEFAULT NULL;
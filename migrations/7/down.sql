@@ -0,0 +1 @@
+DROP TABLE "name_resolutions" CASCADE;
\ No newline at end of file
@@ -0,0 +1,6 @@
+CREATE TABLE "name_resolutions" (
+    "id" SERIAL PRIMARY KEY,
+    "valid_name" VARCHAR NOT NULL UNIQUE,
+    "species_key" BIGINT,
+    "resolved_at" TIMESTAMP WITH TIME ZONE NOT NULL
+);
@@ -0,0 +1,3 @@
+ALTER TABLE "medias" DROP COLUMN "etag";
+ALTER TABLE "medias" DROP COLUMN "last_modified";
+ALTER TABLE "medias" DROP COLUMN "last_attempt";
@@ -0,0 +1,3 @@
+ALTER TABLE "medias" ADD "etag" VARCHAR DEFAULT NULL;
+ALTER TABLE "medias" ADD "last_modified" VARCHAR DEFAULT NULL;
+ALTER TABLE "medias" ADD "last_attempt" TIMESTAMP WITH TIME ZONE DEFAULT NULL;
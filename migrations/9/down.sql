@@ -0,0 +1,5 @@
+ALTER TABLE "medias" DROP COLUMN "captured_at";
+ALTER TABLE "medias" DROP COLUMN "camera_make";
+ALTER TABLE "medias" DROP COLUMN "camera_model";
+ALTER TABLE "medias" DROP COLUMN "gps_latitude";
+ALTER TABLE "medias" DROP COLUMN "gps_longitude";
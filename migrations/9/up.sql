@@ -0,0 +1,5 @@
+ALTER TABLE "medias" ADD "captured_at" TIMESTAMP DEFAULT NULL;
+ALTER TABLE "medias" ADD "camera_make" VARCHAR DEFAULT NULL;
+ALTER TABLE "medias" ADD "camera_model" VARCHAR DEFAULT NULL;
+ALTER TABLE "medias" ADD "gps_latitude" DOUBLE PRECISION DEFAULT NULL;
+ALTER TABLE "medias" ADD "gps_longitude" DOUBLE PRECISION DEFAULT NULL;
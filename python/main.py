@@ -24,6 +24,31 @@ def eprint(*args, **kwargs):
     print(*args, file=sys.stderr, **kwargs, flush=True)
 
 
+def read_message(framing):
+    """Reads the next message from stdin, or returns None at the end of stdin."""
+    if framing == 'length_prefixed':
+        header = sys.stdin.buffer.read(4)
+        if len(header) < 4:
+            return None
+        length = int.from_bytes(header, 'big')
+        return sys.stdin.buffer.read(length).decode('utf-8')
+
+    try:
+        return input()
+    except EOFError:
+        return None
+
+
+def write_message(message, framing):
+    """Writes a message on stdout."""
+    if framing == 'length_prefixed':
+        data = message.encode('utf-8')
+        sys.stdout.buffer.write(len(data).to_bytes(4, 'big') + data)
+        sys.stdout.buffer.flush()
+    else:
+        print(message, flush=True)
+
+
 class Request:
     @staticmethod
     def parse(json_data):
@@ -72,8 +97,11 @@ class Response:
 
 
 class Ready:
+    def __init__(self, framing):
+        self.framing = framing
+
     def to_json(self):
-        return '{"type": "ready"}'
+        return json.dumps({'type': 'ready', 'framing': self.framing})
 
 
 class Batch:
@@ -105,7 +133,7 @@ class FileCropFailure(Response):
         self.path = path
 
 
-def main(tmp_root):
+def main(tmp_root, framing):
     # Download YOLO weights if not present
     if not os.path.exists(WEIGHTS_PATH):
         eprint('weights not found, downloading')
@@ -130,12 +158,12 @@ def main(tmp_root):
     batch = []
 
     eprint('python is ready')
-    print(Ready().to_json())
+    # The ready message is always on its own line, and tells the framing of the next messages
+    print(Ready(framing).to_json(), flush=True)
 
     while True:
-        try:
-            request_str = input()
-        except EOFError:
+        request_str = read_message(framing)
+        if request_str is None:
             return
 
         try:
@@ -163,7 +191,7 @@ def main(tmp_root):
                     eprint(f"python error: failed to crop batch: {e}")
 
                     # Send empty batch
-                    print(batch_response.to_json(), flush=True)
+                    write_message(batch_response.to_json(), framing)
                     batch = []
                     batch_counter += 1
                     continue
@@ -188,7 +216,7 @@ def main(tmp_root):
 
                     batch_response.files.append(response)
 
-                print(batch_response.to_json(), flush=True)
+                write_message(batch_response.to_json(), framing)
 
                 batch = []
                 batch_counter += 1
@@ -198,4 +226,8 @@ def main(tmp_root):
 
 
 if __name__ == '__main__':
-    main(sys.argv[1])
+    framing = 'newline'
+    for arg in sys.argv[2:]:
+        if arg == '--framing=length_prefixed':
+            framing = 'length_prefixed'
+    main(sys.argv[1], framing)
@@ -1,6 +1,7 @@
 //! This module contains the struct useful for the configuration.
 
-use std::path::PathBuf;
+use std::fs::{self, File};
+use std::path::{Component, PathBuf};
 
 use serde::{Deserialize, Serialize};
 
@@ -9,10 +10,15 @@ use uuid::{Uuid, uuid};
 use rocket::Phase;
 use rocket::figment::Figment;
 
-use crate::db::SpeciesTrait;
-use crate::utils::pretty_name;
+use crate::cropper::Framing;
+use crate::db::{ConvertFormat, ExampleMediaStrategy, RepresentativeMedia, SpeciesTrait};
+use crate::gbif::{GBIF_ROOT, OccurrenceFilters};
+use crate::logger::{DEFAULT_TIMESTAMP_FORMAT, LogColor, LogFormat};
+use crate::taxref::TaxrefFilter;
+use crate::utils::{pretty_name, sanitize_dirname};
+use crate::{Error, Result};
 
-/// Blacklisted dataset.
+/// Dataset blacklisted by default.
 ///
 /// It only contains pictures of labels, not animals.
 pub const BLACKLISTED_DATASET: Uuid = uuid!("aae308f4-9f9c-4cdd-b4ef-c026f48be551");
@@ -44,11 +50,249 @@ pub struct Config {
     /// Number of parallel downloads.
     pub jobs: usize,
 
+    /// Number of species whose occurrences are scraped in parallel.
+    #[serde(default = "default_scrap_jobs")]
+    pub scrap_jobs: usize,
+
     /// Number of images to put in a batch for cropping.
     pub batch_size: usize,
 
+    /// The number of downloaded medias that can wait to be cropped, after which downloads wait for the cropping.
+    #[serde(default = "default_crop_queue_capacity")]
+    pub crop_queue_capacity: usize,
+
+    /// How the messages exchanged with the python cropper are delimited.
+    #[serde(default)]
+    pub cropper_framing: Framing,
+
+    /// How the example media of each species is chosen.
+    #[serde(default)]
+    pub example_media_strategy: ExampleMediaStrategy,
+
+    /// How the media downloaded for each occurrence is chosen, when not every media of a species is downloaded.
+    #[serde(default)]
+    pub representative_media: RepresentativeMedia,
+
+    /// The maximum number of medias of a species that are downloaded, or no maximum if 0.
+    ///
+    /// The media chosen for each occurrence are kept first. The species with less than `min_occurrences` occurrences,
+    /// whose medias are all downloaded, are limited too.
+    #[serde(default)]
+    pub max_medias_per_species: usize,
+
     /// Url of the databases.
     pub databases: Databases,
+
+    /// The root of the GBIF API, which can point to a mirror or to a mock server.
+    #[serde(default = "default_gbif_root")]
+    pub gbif_root: String,
+
+    /// The number of requests per second sent to GBIF and to the hosts of the medias, without limit if none.
+    #[serde(default)]
+    pub gbif_requests_per_second: Option<f64>,
+
+    /// The format to which the downloaded medias are converted, if any.
+    #[serde(default)]
+    pub convert_to: Option<ConvertFormat>,
+
+    /// The quality of the medias converted to JPEG, from 1 to 100.
+    #[serde(default = "default_convert_quality")]
+    pub convert_quality: u8,
+
+    /// The minimum width in pixels of the downloaded medias, smaller ones are deleted.
+    #[serde(default)]
+    pub min_width: u32,
+
+    /// The minimum height in pixels of the downloaded medias, smaller ones are deleted.
+    #[serde(default)]
+    pub min_height: u32,
+
+    /// The number of seconds after which connecting to GBIF or to the host of a media fails.
+    #[serde(default = "default_connect_timeout_secs")]
+    pub connect_timeout_secs: u64,
+
+    /// The number of seconds after which a request to GBIF or to the host of a media fails, body included.
+    #[serde(default = "default_request_timeout_secs")]
+    pub request_timeout_secs: u64,
+
+    /// The maximum number of attempts to download a media, including the first one.
+    #[serde(default = "default_media_download_attempts")]
+    pub media_download_attempts: u32,
+
+    /// The maximum number of redirects followed when downloading a media.
+    #[serde(default = "default_max_redirects")]
+    pub max_redirects: usize,
+
+    /// The mime types of the medias that are kept, such as `image/avif`, or every image if empty.
+    ///
+    /// The detected type of the files is checked, not the type announced by the servers.
+    #[serde(default)]
+    pub allowed_mime_types: Vec<String>,
+
+    /// The tokens that give access to the admin routes, either as a bearer token or as a basic auth password.
+    ///
+    /// If there is no token, the admin routes can't be accessed at all.
+    #[serde(default)]
+    pub admin_tokens: Vec<String>,
+
+    /// The datasets whose occurrences are neither scraped nor shown.
+    #[serde(default = "default_blacklisted_datasets")]
+    pub blacklisted_datasets: Vec<Uuid>,
+
+    /// The only datasets whose occurrences are scraped and shown, or all datasets if empty.
+    ///
+    /// The blacklist takes precedence: a dataset that is both allowed and blacklisted is excluded.
+    #[serde(default)]
+    pub allowed_datasets: Vec<Uuid>,
+
+    /// The hosts whose medias are never downloaded, along with their subdomains.
+    #[serde(default)]
+    pub blacklisted_media_hosts: Vec<String>,
+
+    /// Filters on the quality of the occurrences that are scraped.
+    #[serde(default)]
+    pub occurrence_filters: OccurrenceFilters,
+
+    /// The number of days during which the species key found on GBIF for a species is reused without searching again.
+    #[serde(default = "default_name_resolution_ttl_days")]
+    pub name_resolution_ttl_days: i64,
+
+    /// The GBIF account used for asynchronous downloads.
+    #[serde(default)]
+    pub gbif_download: GbifDownloadConfig,
+
+    /// The ISO 639-2 code of the language of the vernacular names of the species, such as `fra` or `eng`.
+    #[serde(default = "default_vernacular_language")]
+    pub vernacular_language: String,
+
+    /// The number of requests that each client can make on the server per minute, without limit if none.
+    #[serde(default)]
+    pub rate_limit_per_minute: Option<u32>,
+
+    /// Whether the server is behind a reverse proxy whose `X-Forwarded-For` header can be trusted.
+    #[serde(default)]
+    pub trust_proxy: bool,
+
+    /// The number of seconds the server waits for the requests in flight to complete after a ctrl-c.
+    #[serde(default = "default_shutdown_grace_secs")]
+    pub shutdown_grace_secs: u32,
+
+    /// The maximum level of the logs (`error`, `warn`, `info`, `debug`, `trace` or `off`).
+    #[serde(default = "default_log_level")]
+    pub log_level: String,
+
+    /// The maximum level of the logs of some modules, such as `scraper::gbif=trace,scraper::server=info`.
+    ///
+    /// The other modules are still logged with `log_level`, unless they are given the `off` level.
+    #[serde(default)]
+    pub log_modules: String,
+
+    /// The format of the log file, stderr always getting human readable lines.
+    #[serde(default)]
+    pub log_format: LogFormat,
+
+    /// When the logs written on stderr are colored (`auto`, `always` or `never`).
+    #[serde(default)]
+    pub log_color: LogColor,
+
+    /// The strftime format of the timestamps of the logs, such as `%Y-%m-%dT%H:%M:%S%:z`.
+    #[serde(default = "default_log_timestamp_format")]
+    pub log_timestamp_format: String,
+
+    /// Whether the timestamps of the logs are in UTC instead of local time.
+    #[serde(default)]
+    pub log_utc: bool,
+
+    /// The size in bytes after which a new log file is started, if any.
+    #[serde(default)]
+    pub log_max_bytes: Option<u64>,
+
+    /// Whether a new log file is started every day at midnight.
+    #[serde(default)]
+    pub log_rotate_daily: bool,
+
+    /// The number of old log files to keep, older ones are deleted when a new log file is started.
+    #[serde(default = "default_log_keep_files")]
+    pub log_keep_files: usize,
+
+    /// Where to download taxref and which of its entries are scraped.
+    #[serde(default)]
+    pub taxref: TaxrefConfig,
+}
+
+/// The number of species scraped in parallel when none is specified.
+fn default_scrap_jobs() -> usize {
+    1
+}
+
+/// The number of medias waiting to be cropped when none is specified.
+fn default_crop_queue_capacity() -> usize {
+    1000
+}
+
+/// The grace period of the server shutdown when none is specified, long enough for most exports to complete.
+fn default_shutdown_grace_secs() -> u32 {
+    30
+}
+
+/// The root of the GBIF API when none is specified.
+fn default_gbif_root() -> String {
+    String::from(GBIF_ROOT)
+}
+
+/// The quality of the converted medias when none is specified.
+fn default_convert_quality() -> u8 {
+    90
+}
+
+/// The connection timeout when none is specified, short so that unreachable hosts fail fast.
+fn default_connect_timeout_secs() -> u64 {
+    5
+}
+
+/// The request timeout when none is specified, long enough for big medias.
+fn default_request_timeout_secs() -> u64 {
+    120
+}
+
+/// The number of attempts to download a media when none is specified.
+fn default_media_download_attempts() -> u32 {
+    4
+}
+
+/// The number of redirects followed when none is specified.
+fn default_max_redirects() -> usize {
+    10
+}
+
+/// The datasets blacklisted when none is specified.
+fn default_blacklisted_datasets() -> Vec<Uuid> {
+    vec![BLACKLISTED_DATASET]
+}
+
+/// The number of days species keys are cached when none is specified.
+fn default_name_resolution_ttl_days() -> i64 {
+    30
+}
+
+/// The language of the vernacular names when none is specified.
+fn default_vernacular_language() -> String {
+    String::from("fra")
+}
+
+/// The log level used when none is specified.
+fn default_log_level() -> String {
+    String::from("info")
+}
+
+/// The format of the timestamps of the logs when none is specified.
+fn default_log_timestamp_format() -> String {
+    String::from(DEFAULT_TIMESTAMP_FORMAT)
+}
+
+/// The number of old log files kept when none is specified.
+fn default_log_keep_files() -> usize {
+    10
 }
 
 impl Config {
@@ -61,6 +305,63 @@ impl Config {
     pub fn from_figment(figment: &Figment) -> Config {
         figment.extract().expect("Failed to parse config")
     }
+
+    /// Whether a dataset is in the whitelist, which is the case of every dataset if the whitelist is empty.
+    pub fn is_dataset_whitelisted(&self, dataset_key: &Uuid) -> bool {
+        self.allowed_datasets.is_empty() || self.allowed_datasets.contains(dataset_key)
+    }
+
+    /// Whether the occurrences of a dataset are scraped and shown, i.e. it is whitelisted and not blacklisted.
+    pub fn is_dataset_allowed(&self, dataset_key: &Uuid) -> bool {
+        self.is_dataset_whitelisted(dataset_key) && !self.blacklisted_datasets.contains(dataset_key)
+    }
+}
+
+/// The config for downloading and filtering taxref.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TaxrefConfig {
+    /// The url from which taxref is downloaded.
+    pub url: String,
+
+    /// The expected SHA-256 of the downloaded file, in hexadecimal, if known.
+    pub sha256: Option<String>,
+
+    /// The minimum size in bytes of the downloaded file, smaller files are considered truncated.
+    pub min_size: u64,
+
+    /// The number of threads parsing taxref, 1 to parse it on the current thread and 0 for one thread per core.
+    pub threads: usize,
+
+    /// Filter that tells which taxref entries are scraped.
+    #[serde(flatten)]
+    pub filter: TaxrefFilter,
+}
+
+impl Default for TaxrefConfig {
+    fn default() -> TaxrefConfig {
+        TaxrefConfig {
+            url: String::from("https://storage.tforgione.fr/TAXREFv17.txt"),
+            sha256: None,
+            min_size: 10_000_000,
+            threads: 1,
+            filter: TaxrefFilter::default(),
+        }
+    }
+}
+
+/// The GBIF account used for asynchronous downloads.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GbifDownloadConfig {
+    /// The GBIF user name.
+    pub user: Option<String>,
+
+    /// The GBIF password.
+    pub password: Option<String>,
+
+    /// The address that GBIF notifies when a download is ready, if any.
+    pub email: Option<String>,
 }
 
 /// Struct to help us deal with storage paths.
@@ -71,6 +372,23 @@ pub struct Storage {
 }
 
 impl Storage {
+    /// Checks that the data can be written, by creating and removing a file in the data directory.
+    ///
+    /// The data directory is created if it doesn't exist.
+    pub fn check_writable(&self) -> Result<()> {
+        let not_writable = |e| Error::NotWritable(self.data_path.clone(), e);
+
+        fs::create_dir_all(&self.data_path).map_err(not_writable)?;
+
+        let probe = self
+            .data_path
+            .join(format!(".write-probe-{}", std::process::id()));
+        File::create(&probe).map_err(not_writable)?;
+        fs::remove_file(&probe).map_err(not_writable)?;
+
+        Ok(())
+    }
+
     /// Returns the path to the species directory.
     pub fn species_dir(&self) -> PathBuf {
         self.data_path.join("species")
@@ -93,21 +411,53 @@ impl Storage {
             .join(&self.medias_dir_local(species))
     }
 
+    /// Returns the path to the directory where the archives of GBIF downloads are stored.
+    pub fn downloads_dir(&self) -> PathBuf {
+        self.data_path.join("downloads")
+    }
+
     /// Returns the path to the temporary directory where the python can move cropped images.
     pub fn tmp_dir(&self) -> PathBuf {
         self.data_path.join("tmp")
     }
 
+    /// Returns the path to the journal of the batch being cropped, which lists the ids of its medias.
+    pub fn crop_journal_path(&self) -> PathBuf {
+        self.data_path.join("crop_journal.json")
+    }
+
+    /// Returns the path to the summary of the last scrap.
+    pub fn last_scrap_path(&self) -> PathBuf {
+        self.data_path.join("last_scrap.json")
+    }
+
     /// Returns the cropped media path for a species.
     pub fn cropped_medias_dir<S: SpeciesTrait>(&self, species: &S) -> PathBuf {
         self.cropped_root().join(&self.medias_dir_local(species))
     }
 
     /// Returns the part of the media path after medias.
+    ///
+    /// The directories used to be named after the pretty name as is, such as `Apis mellifera`. Such a directory is
+    /// still used as long as the sanitized one doesn't exist, so that the medias it holds are not downloaded again.
     pub fn medias_dir_local<S: SpeciesTrait>(&self, species: &S) -> PathBuf {
-        PathBuf::from(format!(
-            "{}",
-            pretty_name(species.valid_name()).unwrap().to_lowercase()
-        ))
+        let name = pretty_name(species.valid_name());
+        let sanitized = PathBuf::from(sanitize_dirname(&name));
+        let legacy = PathBuf::from(name);
+
+        // The old name is only trusted if it can't point outside of the medias.
+        let is_plain = matches!(
+            legacy.components().collect::<Vec<_>>().as_slice(),
+            [Component::Normal(_)]
+        );
+
+        if is_plain
+            && !self.medias_root().join(&sanitized).exists()
+            && self.medias_root().join(&legacy).is_dir()
+        {
+            legacy
+        } else {
+            sanitized
+        }
     }
 }
@@ -1,19 +1,95 @@
 //! This module helps us run python cropper.
 
+use std::io;
 use std::process::Stdio;
 
 use serde::{Deserialize, Serialize};
 
-use tokio::fs::{remove_dir_all, rename};
-use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::fs::{read, remove_dir_all, remove_file, rename, write};
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
 use tokio::process::{ChildStdin, ChildStdout, Command};
-use tokio::sync::mpsc::UnboundedReceiver;
+use tokio::sync::mpsc::Receiver;
 use tokio::task::JoinHandle;
 
 use crate::config::Config;
 use crate::db::Media;
+use crate::utils::create_dir_all;
 use crate::{Db, Error, Result};
 
+/// The maximum size of a length prefixed message, so that a corrupted length doesn't make us allocate gigabytes.
+const MAX_MESSAGE_LEN: usize = 64 * 1024 * 1024;
+
+/// How the JSON messages exchanged with python are delimited.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Framing {
+    /// Each message is on its own line.
+    #[default]
+    Newline,
+
+    /// Each message is preceded by its length in bytes, as a 4 bytes big endian integer.
+    ///
+    /// Messages can then contain new lines, for example in paths or error messages.
+    LengthPrefixed,
+}
+
+impl Framing {
+    /// Encodes a message with this framing.
+    pub fn encode(self, message: &str) -> io::Result<Vec<u8>> {
+        match self {
+            Framing::Newline => Ok(format!("{}\n", message).into_bytes()),
+
+            Framing::LengthPrefixed => {
+                let len = u32::try_from(message.len())
+                    .ok()
+                    .filter(|&x| x as usize <= MAX_MESSAGE_LEN)
+                    .ok_or_else(|| {
+                        io::Error::new(io::ErrorKind::InvalidInput, "message too long")
+                    })?;
+
+                let mut bytes = len.to_be_bytes().to_vec();
+                bytes.extend_from_slice(message.as_bytes());
+                Ok(bytes)
+            }
+        }
+    }
+
+    /// Reads the next message with this framing, or returns none if the reader reached its end.
+    pub async fn read<R: AsyncBufRead + Unpin>(self, reader: &mut R) -> Result<Option<String>> {
+        match self {
+            Framing::Newline => {
+                let mut line = String::new();
+                match reader.read_line(&mut line).await? {
+                    0 => Ok(None),
+                    _ => Ok(Some(line)),
+                }
+            }
+
+            Framing::LengthPrefixed => {
+                let mut len = [0; 4];
+                match reader.read_exact(&mut len).await {
+                    Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+                    result => result?,
+                };
+
+                let len = u32::from_be_bytes(len) as usize;
+                if len > MAX_MESSAGE_LEN {
+                    return Err(
+                        io::Error::new(io::ErrorKind::InvalidData, "message too long").into(),
+                    );
+                }
+
+                let mut message = vec![0; len];
+                reader.read_exact(&mut message).await?;
+
+                String::from_utf8(message)
+                    .map(Some)
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e).into())
+            }
+        }
+    }
+}
+
 /// A message that can be sent to python.
 #[derive(Serialize, Deserialize)]
 #[serde(tag = "type", rename_all = "snake_case")]
@@ -55,7 +131,14 @@ pub struct AddFileRequest {
 #[serde(tag = "type", rename_all = "snake_case")]
 pub enum Response {
     /// Python is ready to receive requests.
-    Ready,
+    ///
+    /// This message is always on its own line, and tells which framing python uses for the next messages. Older
+    /// versions of the python don't know about framings, and send no framing, which means new lines.
+    Ready {
+        /// The framing of the next messages.
+        #[serde(default)]
+        framing: Framing,
+    },
 
     /// Python sent a batch that was finished.
     Batch(Batch),
@@ -136,17 +219,22 @@ pub struct Cropper {
     /// The python command's stdout.
     pub stdout: BufReader<ChildStdout>,
 
-    /// The number of files in the batch.
-    pub batch_size: usize,
+    /// The ids of the medias of the current batch.
+    pub batch: Vec<i32>,
 
     /// The number of files to put in each batch.
     pub batch_capacity: usize,
+
+    /// How the messages exchanged with python are delimited.
+    pub framing: Framing,
 }
 
 impl Cropper {
     /// Creates a new cropper.
     pub async fn new(batch_capacity: usize, config: Config, db: Db) -> Result<Cropper> {
         let tmp_dir = config.storage.tmp_dir();
+        create_dir_all(&tmp_dir).await?;
+
         let tmp_dir = tmp_dir
             .to_str()
             .expect("Failed to convert tmp dir to string");
@@ -154,6 +242,13 @@ impl Cropper {
         let mut command = Command::new("python");
         command.arg("python/main.py");
         command.arg(&tmp_dir);
+
+        // The python is only asked for the framing when it is not the default one, so that older versions of the
+        // python still work with the default config.
+        if config.cropper_framing == Framing::LengthPrefixed {
+            command.arg("--framing=length_prefixed");
+        }
+
         command.stdin(Stdio::piped());
         command.stderr(Stdio::piped());
         command.stdout(Stdio::piped());
@@ -184,15 +279,58 @@ impl Cropper {
             stdout: BufReader::new(stdout),
             config,
             db,
-            batch_size: 0,
+            batch: vec![],
             batch_capacity,
+            framing: Framing::Newline,
         };
 
         cropper.wait_python().await?;
 
+        if cropper.framing != cropper.config.cropper_framing {
+            warn!(
+                "python doesn't support the {:?} framing, using {:?} instead",
+                cropper.config.cropper_framing, cropper.framing
+            );
+        }
+
+        cropper.resume().await?;
+
         Ok(cropper)
     }
 
+    /// Crops again the medias of the batch that was being cropped when a previous cropper stopped, if any.
+    ///
+    /// The boxes found by python for these medias were lost, so they are cropped again right away, before any other
+    /// media. The medias that were cropped in the meantime are skipped.
+    async fn resume(&mut self) -> Result<()> {
+        let journal = match read(self.config.storage.crop_journal_path()).await {
+            Ok(journal) => journal,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e.into()),
+        };
+
+        let ids: Vec<i32> = serde_json::from_slice(&journal)?;
+        info!("resuming an interrupted batch of {} medias", ids.len());
+
+        for id in ids {
+            match Media::get_by_id(id, &self.db).await? {
+                Some(media) if !media.cropped => self.add_media(&media).await?,
+                _ => (),
+            }
+        }
+
+        // Python doesn't answer a run request with an empty batch, and the journal may already be removed if the
+        // batch was full.
+        if !self.batch.is_empty() {
+            self.run_batch(Request::Run).await?;
+        }
+
+        match remove_file(self.config.storage.crop_journal_path()).await {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+
     /// Asks python to crop a media, and trigger if batch size is reached..
     pub async fn add_media(&mut self, media: &Media) -> Result<()> {
         if let Some(path) = &media.path {
@@ -214,12 +352,11 @@ impl Cropper {
             return Ok(());
         }
 
-        self.batch_size += 1;
+        self.batch.push(media.id);
 
-        if self.batch_size >= self.batch_capacity {
+        if self.batch.len() >= self.batch_capacity {
             info!("Batch full: asking for python to run cropping");
-            self.send_request(Request::Run).await?;
-            self.batch_size = 0;
+            self.run_batch(Request::Run).await?;
         }
 
         Ok(())
@@ -227,15 +364,37 @@ impl Cropper {
 
     /// Sends the end request to the cropper.
     pub async fn end(&mut self) -> Result<()> {
-        self.send_request(Request::End).await?;
+        self.run_batch(Request::End).await
+    }
+
+    /// Asks python to crop the current batch, with a run or end request.
+    ///
+    /// The ids of the medias of the batch are written in the journal until their crops are saved in the database, so
+    /// that a cropper that stops in the meantime can resume them.
+    async fn run_batch(&mut self, request: Request) -> Result<()> {
+        let journal = self.config.storage.crop_journal_path();
+
+        if !self.batch.is_empty() {
+            write(&journal, serde_json::to_vec(&self.batch)?).await?;
+        }
+
+        // Python writes the crops of the batch in the tmp directory, which may have been removed in the meantime.
+        create_dir_all(&self.config.storage.tmp_dir()).await?;
+
+        self.send_request(request).await?;
+
+        if !self.batch.is_empty() {
+            remove_file(&journal).await?;
+            self.batch.clear();
+        }
+
         Ok(())
     }
 
     /// Sends a json request to the python.
     pub async fn send_request(&mut self, request: Request) -> Result<()> {
-        self.stdin
-            .write(format!("{}\n", serde_json::to_string(&request)?).as_bytes())
-            .await?;
+        let message = self.framing.encode(&serde_json::to_string(&request)?)?;
+        self.stdin.write_all(&message).await?;
 
         if request.should_wait_python() {
             self.stdin.flush().await?;
@@ -247,17 +406,17 @@ impl Cropper {
 
     /// Waits for the python response.
     pub async fn wait_python(&mut self) -> Result<()> {
-        let mut line = String::new();
-        let bytes = self.stdout.read_line(&mut line).await?;
-
-        if bytes == 0 {
-            info!("Received EOF from python");
-            return Ok(());
-        }
+        let message = match self.framing.read(&mut self.stdout).await? {
+            Some(message) => message,
+            None => {
+                info!("Received EOF from python");
+                return Ok(());
+            }
+        };
 
         info!("Received response from python");
 
-        let response: Response = match serde_json::from_str(&line) {
+        let response: Response = match serde_json::from_str(&message) {
             Ok(r) => r,
             Err(e) => {
                 error!("failed to parse message from python: {}", e);
@@ -266,8 +425,9 @@ impl Cropper {
         };
 
         let batch = match response {
-            Response::Ready => {
+            Response::Ready { framing } => {
                 info!("Python is ready");
+                self.framing = framing;
                 return Ok(());
             }
 
@@ -326,8 +486,11 @@ impl Cropper {
             )
         }
 
-        // Clean batch tmp files
-        remove_dir_all(self.config.storage.tmp_dir().join(format!("{}", batch.id))).await?;
+        // Clean batch tmp files, python doesn't create the directory when every crop failed
+        match remove_dir_all(self.config.storage.tmp_dir().join(format!("{}", batch.id))).await {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e.into()),
+            _ => (),
+        }
 
         info!(
             "Python successfully cropped {} out of {} images",
@@ -342,7 +505,7 @@ impl Cropper {
     ///
     /// It will receive the ids of the medias to crop via the mscp channel.
     /// None means that we need to crop the remaining files and exit.
-    pub fn run(self, receiver: UnboundedReceiver<Option<i32>>) -> JoinHandle<()> {
+    pub fn run(self, receiver: Receiver<Option<i32>>) -> JoinHandle<()> {
         tokio::spawn(async move {
             let mut cropper = self;
             let mut receiver = receiver;
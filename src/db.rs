@@ -1,15 +1,16 @@
 //! This module helps us deal with the database.
 
+use std::collections::HashMap;
 use std::fs::File;
-use std::io::Write;
-use std::path::PathBuf;
+use std::io::{BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
 use std::time::Duration;
 
-use serde::Serialize;
+use chrono::{DateTime, NaiveDateTime, TimeDelta, Utc};
 
-use serde_json::{Value, json};
+use serde::{Deserialize, Serialize};
 
-use tokio::time::sleep;
+use serde_json::{Value, json};
 
 use ergol::prelude::*;
 use ergol::tokio_postgres::GenericClient;
@@ -18,18 +19,35 @@ use uuid::Uuid;
 
 use futures_util::StreamExt;
 
+use tokio::task::spawn_blocking;
+
 use reqwest::Client;
+use reqwest::header::{
+    CONTENT_TYPE, ETAG, HeaderValue, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED,
+};
+use reqwest::redirect::Policy;
 
 use infer::MatcherType;
 
-use crate::config::Storage;
-use crate::gbif::{MAX_LIMIT_OCCURRENCES, OccurrencesResponse, search_occurrences, search_species};
+use image::codecs::jpeg::JpegEncoder;
+use image::{ImageFormat, ImageReader};
+
+use crate::config::{Config, Storage};
+use crate::dwca::Table;
+use crate::exif;
+use crate::gbif::{
+    MAX_LIMIT_OCCURRENCES, OccurrencesResponse, fetch_dataset, fetch_organization, fetch_species,
+    fetch_vernacular, search_occurrences, search_species,
+};
 use crate::taxref::Entry;
-use crate::utils::{pretty_finder, pretty_name};
+use crate::utils::{
+    RATE_LIMITER, RetryPolicy, is_transient, pretty_finder, pretty_name, retry, sha256_file,
+};
 use crate::{Db, Error, Result};
 
 /// A species that is ignored because we already have another species with the same species key in the database.
 #[ergol]
+#[derive(Serialize)]
 pub struct IgnoredSpecies {
     /// Id of the row in the database.
     #[id]
@@ -50,7 +68,7 @@ pub struct IgnoredSpecies {
     /// Family of the species.
     pub family: String,
 
-    /// Genus of the species (sub-family).
+    /// Genus of the species.
     pub genus: String,
 
     /// Valid name of the specie.
@@ -59,6 +77,9 @@ pub struct IgnoredSpecies {
 
     /// The species key of the species on GBIF.
     pub species_key: Option<i64>,
+
+    /// The species key of the species this one is a synonym of, once the species is linked to it.
+    pub canonical_species_key: Option<i64>,
 }
 
 impl IgnoredSpecies {
@@ -73,8 +94,197 @@ impl IgnoredSpecies {
             genus: entry.genus,
             valid_name: entry.valid_name,
             species_key,
+            canonical_species_key: None,
+        }
+    }
+
+    /// Links the ignored species to the species that have the same species key, of which they are synonyms.
+    ///
+    /// Returns the number of ignored species that were linked.
+    pub async fn link_canonical<T: Queryable<impl GenericClient>>(db: &T) -> Result<u64> {
+        let sql = r#"
+            UPDATE ignored_speciess
+            SET canonical_species_key = speciess.species_key
+            FROM speciess
+            WHERE
+                ignored_speciess.species_key = speciess.species_key AND
+                ignored_speciess.canonical_species_key IS DISTINCT FROM speciess.species_key
+        "#;
+
+        Ok(db.client().execute(sql, &[]).await?)
+    }
+
+    /// Lists the ignored species, ordered by valid name.
+    pub async fn list<T: Queryable<impl GenericClient>>(db: &T) -> Result<Vec<IgnoredSpecies>> {
+        let sql = r#"SELECT * FROM ignored_speciess ORDER BY valid_name"#;
+
+        let rows = db.client().query(sql, &[]).await?;
+        Ok(rows.iter().map(IgnoredSpecies::from_row).collect())
+    }
+
+    /// Removes the ignored species with a valid name, or all of them if there is none, so that the next scrap
+    /// evaluates them again.
+    ///
+    /// Returns the number of ignored species removed.
+    pub async fn clear<T: Queryable<impl GenericClient>>(
+        valid_name: Option<&str>,
+        db: &T,
+    ) -> Result<u64> {
+        let removed = match valid_name {
+            Some(valid_name) => {
+                let sql = r#"DELETE FROM ignored_speciess WHERE valid_name = $1"#;
+                db.client().execute(sql, &[&valid_name]).await?
+            }
+            None => {
+                let sql = r#"DELETE FROM ignored_speciess"#;
+                db.client().execute(sql, &[]).await?
+            }
+        };
+
+        Ok(removed)
+    }
+}
+
+/// How the media downloaded for each occurrence is chosen, when not every media of the species is downloaded.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RepresentativeMedia {
+    /// The media that was scraped first, i.e. the first one given by GBIF.
+    ///
+    /// The medias are chosen before being downloaded, so their resolution can't be taken into account yet.
+    #[default]
+    First,
+
+    /// The media that was downloaded by a previous scrap, or the first one if none was, so that scraping again
+    /// doesn't download other medias of the occurrence.
+    Downloaded,
+
+    /// A media picked at random, which changes each time the medias are marked, so that scraping again downloads
+    /// other medias of the occurrences.
+    Random,
+}
+
+impl RepresentativeMedia {
+    /// Returns the SQL ordering of the medias of an occurrence, the first one being chosen.
+    fn order_by(self) -> &'static str {
+        match self {
+            RepresentativeMedia::First => "medias.id",
+            RepresentativeMedia::Downloaded => "medias.path IS NULL, medias.id",
+            RepresentativeMedia::Random => "random()",
+        }
+    }
+}
+
+/// How the example media of a species, shown as its thumbnail in the gallery, is chosen.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ExampleMediaStrategy {
+    /// The media with the most trustworthy crop: manual crops first, then the highest confidence.
+    #[default]
+    HighestConfidence,
+
+    /// The media that was scraped first.
+    FirstDownloaded,
+
+    /// A media picked at random, which changes each time the example medias are chosen.
+    Random,
+}
+
+impl ExampleMediaStrategy {
+    /// Returns the SQL ordering of the medias, the first one being the example.
+    fn order_by(self) -> &'static str {
+        match self {
+            ExampleMediaStrategy::HighestConfidence => {
+                "medias.manual_x IS NOT NULL DESC, medias.confidence DESC NULLS LAST, medias.id"
+            }
+            ExampleMediaStrategy::FirstDownloaded => "medias.id",
+            ExampleMediaStrategy::Random => "random()",
+        }
+    }
+}
+
+/// The format to which the downloaded medias are converted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ConvertFormat {
+    /// Lossy JPEG, with the quality of the config.
+    Jpeg,
+
+    /// Lossless PNG.
+    Png,
+
+    /// Lossless WebP.
+    Webp,
+}
+
+impl ConvertFormat {
+    /// Returns the extension of the files of this format, as detected when downloading.
+    pub fn extension(self) -> &'static str {
+        match self {
+            ConvertFormat::Jpeg => "jpg",
+            ConvertFormat::Png => "png",
+            ConvertFormat::Webp => "webp",
+        }
+    }
+
+    /// Returns the MIME type of the files of this format.
+    pub fn mime_type(self) -> &'static str {
+        match self {
+            ConvertFormat::Jpeg => "image/jpeg",
+            ConvertFormat::Png => "image/png",
+            ConvertFormat::Webp => "image/webp",
+        }
+    }
+}
+
+/// A media file written by a download, with what was read from it before it was converted.
+struct DownloadedFile {
+    /// The path of the file, relative to the medias root.
+    path: PathBuf,
+
+    /// The hash of the downloaded file.
+    sha256: Option<String>,
+
+    /// The EXIF metadata of the downloaded file, which are lost when it is converted.
+    exif: Option<exif::Exif>,
+}
+
+/// Converts an image to another format, next to it with the extension of the format, and removes the original.
+///
+/// The quality, from 1 to 100, is only used by JPEG. If the conversion fails, the original is kept and nothing else is
+/// left on the disk. Returns the path of the converted image.
+pub fn convert_image(path: &Path, format: ConvertFormat, quality: u8) -> Result<PathBuf> {
+    let target = path.with_extension(format.extension());
+
+    if target == path {
+        return Ok(target);
+    }
+
+    let convert = || -> std::result::Result<(), image::ImageError> {
+        let image = ImageReader::open(path)?.with_guessed_format()?.decode()?;
+        let mut writer = BufWriter::new(File::create(&target)?);
+
+        match format {
+            // JPEG has no alpha channel.
+            ConvertFormat::Jpeg => {
+                let encoder = JpegEncoder::new_with_quality(&mut writer, quality.clamp(1, 100));
+                image.to_rgb8().write_with_encoder(encoder)?;
+            }
+            ConvertFormat::Png => image.write_to(&mut writer, ImageFormat::Png)?,
+            ConvertFormat::Webp => image.to_rgba8().write_to(&mut writer, ImageFormat::WebP)?,
         }
+
+        writer.flush()?;
+        Ok(())
+    };
+
+    if let Err(e) = convert() {
+        let _ = std::fs::remove_file(&target);
+        return Err(Error::IoError(std::io::Error::other(e)));
     }
+
+    std::fs::remove_file(path)?;
+    Ok(target)
 }
 
 /// A species that is registered in the database.
@@ -100,7 +310,7 @@ pub struct Species {
     /// Family of the species.
     pub family: String,
 
-    /// Genus of the species (sub-family).
+    /// Genus of the species.
     pub genus: String,
 
     /// Valid name of the specie.
@@ -119,6 +329,12 @@ pub struct Species {
 
     /// An example media if available.
     pub example_media_path: Option<String>,
+
+    /// The common name of the species in the language of the config, if GBIF knows one.
+    pub vernacular_name: Option<String>,
+
+    /// When the scraping of the species last finished, none if it never did.
+    pub updated_at: Option<DateTime<Utc>>,
 }
 
 /// This trait is used for shared attributes between db::Species and taxref::Entry.
@@ -146,10 +362,46 @@ impl Species {
             "valid_name": self.valid_name,
             "pretty_name": pretty_name(&self.valid_name),
             "pretty_finder": pretty_finder(&self.valid_name),
+            "vernacular_name": self.vernacular_name,
+            "display_name": self.display_name(),
             "occurrences": self.occurrences(db).await?,
             "species_key": self.species_key,
+            "dataset_count": self.dataset_count(db).await?,
+            "synonyms": self
+                .synonyms(db)
+                .await?
+                .iter()
+                .map(|x| pretty_name(&x.valid_name))
+                .collect::<Vec<_>>(),
         }))
     }
+
+    /// Returns the ignored species that are linked to this species as its synonyms.
+    pub async fn synonyms<T: Queryable<impl GenericClient>>(
+        &self,
+        db: &T,
+    ) -> Result<Vec<IgnoredSpecies>> {
+        let Some(species_key) = self.species_key else {
+            return Ok(vec![]);
+        };
+
+        let sql = r#"
+            SELECT * FROM ignored_speciess
+            WHERE canonical_species_key = $1
+            ORDER BY valid_name
+        "#;
+
+        let rows = db.client().query(sql, &[&species_key]).await?;
+        Ok(rows.iter().map(IgnoredSpecies::from_row).collect())
+    }
+
+    /// Returns the number of distinct datasets the occurrences of the species come from.
+    pub async fn dataset_count<T: Queryable<impl GenericClient>>(&self, db: &T) -> Result<i64> {
+        let sql = r#"SELECT COUNT(DISTINCT dataset_key) FROM occurrences WHERE species = $1"#;
+        let row = db.client().query_one(sql, &[&self.id]).await?;
+        Ok(row.get(0))
+    }
+
     /// Prepares a species without id from its taxref entry.
     pub fn from_taxref(
         entry: Entry,
@@ -168,23 +420,282 @@ impl Species {
             available_occurrences,
             done: false,
             example_media_path: None,
+            vernacular_name: None,
+            updated_at: None,
+        }
+    }
+
+    /// Marks the scraping of a species as done, so that it is skipped by the next scraps.
+    ///
+    /// Returns false if there is no such species.
+    pub async fn mark_done<T: Queryable<impl GenericClient>>(
+        valid_name: &str,
+        db: &T,
+    ) -> Result<bool> {
+        let Some(mut species) = Species::get_by_valid_name(valid_name, db).await? else {
+            return Ok(false);
+        };
+
+        species.done = true;
+        species.updated_at = Some(Utc::now());
+        species.save(db).await?;
+
+        Ok(true)
+    }
+
+    /// Marks the scraping of a species as pending, so that it is scraped again by the next scraps.
+    ///
+    /// Returns false if there is no such species.
+    pub async fn mark_pending<T: Queryable<impl GenericClient>>(
+        valid_name: &str,
+        db: &T,
+    ) -> Result<bool> {
+        let Some(mut species) = Species::get_by_valid_name(valid_name, db).await? else {
+            return Ok(false);
+        };
+
+        species.done = false;
+        species.save(db).await?;
+
+        Ok(true)
+    }
+
+    /// Chooses the example media of the species and saves its path, or removes it if no media was downloaded.
+    ///
+    /// Only the downloaded medias of datasets that are not blacklisted are considered. Returns the new path.
+    pub async fn choose_example_media<T: Queryable<impl GenericClient>>(
+        &mut self,
+        strategy: ExampleMediaStrategy,
+        blacklist: &[Uuid],
+        db: &T,
+    ) -> Result<Option<String>> {
+        // The ordering comes from type ExampleMediaStrategy, so we can safely format it into the SQL query.
+        let sql = format!(
+            r#"
+            SELECT medias.path
+            FROM medias, occurrences
+            WHERE
+                medias.occurrence = occurrences.id AND
+                occurrences.species = $1 AND
+                occurrences.dataset_key <> ALL($2) AND
+                medias.path IS NOT NULL AND
+                200 <= medias.status_code AND medias.status_code < 400
+            ORDER BY {}
+            LIMIT 1
+            "#,
+            strategy.order_by()
+        );
+
+        let row = db.client().query_opt(&sql, &[&self.id, &blacklist]).await?;
+        let path = row.map(|x| x.get::<_, String>(0));
+
+        if self.example_media_path != path {
+            self.example_media_path = path.clone();
+            self.save(db).await?;
+        }
+
+        Ok(path)
+    }
+
+    /// Counts the occurrences and downloaded medias of the species from each dataset, the datasets with the most medias
+    /// first.
+    ///
+    /// The datasets that are not whitelisted are left out, and so are the blacklisted ones unless `include_blacklisted`
+    /// is true.
+    pub async fn dataset_counts<T: Queryable<impl GenericClient>>(
+        &self,
+        include_blacklisted: bool,
+        config: &Config,
+        db: &T,
+    ) -> Result<Vec<DatasetCount>> {
+        let counts = self
+            .count_by_dataset(include_blacklisted, false, config, db)
+            .await?;
+
+        Ok(counts.into_iter().map(|(dataset, _)| dataset).collect())
+    }
+
+    /// Counts the medias of the species, only the uncropped ones if `only_uncropped` is true, leaving out the datasets
+    /// that are blacklisted or not whitelisted.
+    pub async fn media_stats<T: Queryable<impl GenericClient>>(
+        &self,
+        only_uncropped: bool,
+        config: &Config,
+        db: &T,
+    ) -> Result<MediaStats> {
+        let counts = self
+            .count_by_dataset(false, only_uncropped, config, db)
+            .await?;
+
+        Ok(counts
+            .into_iter()
+            .fold(MediaStats::default(), |acc, (_, stats)| MediaStats {
+                total: acc.total + stats.total,
+                downloaded: acc.downloaded + stats.downloaded,
+                cropped: acc.cropped + stats.cropped,
+            }))
+    }
+
+    /// Counts the occurrences and medias of the species from each dataset, only the uncropped medias if
+    /// `only_uncropped` is true, the datasets with the most downloaded medias first.
+    ///
+    /// The datasets that are not whitelisted are left out, and so are the blacklisted ones unless `include_blacklisted`
+    /// is true.
+    async fn count_by_dataset<T: Queryable<impl GenericClient>>(
+        &self,
+        include_blacklisted: bool,
+        only_uncropped: bool,
+        config: &Config,
+        db: &T,
+    ) -> Result<Vec<(DatasetCount, MediaStats)>> {
+        let sql = r#"
+            SELECT
+                occurrences.dataset_key,
+                datasets.title,
+                datasets.publisher,
+                occurrences.dataset_key = ANY($2),
+                COUNT(DISTINCT occurrences.id),
+                COUNT(medias.id),
+                COUNT(medias.id) FILTER (
+                    WHERE
+                        200 <= medias.status_code AND medias.status_code < 400 AND
+                        medias.path IS NOT NULL
+                ) AS downloaded,
+                COUNT(medias.id) FILTER (
+                    WHERE
+                        200 <= medias.status_code AND medias.status_code < 400 AND
+                        medias.path IS NOT NULL AND
+                        (medias.x IS NOT NULL OR medias.manual_x IS NOT NULL)
+                )
+            FROM
+                occurrences
+                LEFT JOIN medias ON
+                    occurrences.id = medias.occurrence AND
+                    (NOT $5 OR (medias.x IS NULL AND medias.manual_x IS NULL))
+                LEFT JOIN datasets ON occurrences.dataset_key = datasets.key
+            WHERE
+                occurrences.species = $1 AND
+                ($3 OR occurrences.dataset_key <> ALL($2)) AND
+                (cardinality($4::uuid[]) = 0 OR occurrences.dataset_key = ANY($4))
+            GROUP BY
+                occurrences.dataset_key,
+                datasets.title,
+                datasets.publisher
+            ORDER BY
+                downloaded DESC,
+                occurrences.dataset_key
+            ;
+        "#;
+
+        let rows = db
+            .client()
+            .query(
+                sql,
+                &[
+                    &self.id,
+                    &config.blacklisted_datasets,
+                    &include_blacklisted,
+                    &config.allowed_datasets,
+                    &only_uncropped,
+                ],
+            )
+            .await?;
+
+        Ok(rows
+            .iter()
+            .map(|row| {
+                let dataset = DatasetCount {
+                    key: row.get(0),
+                    title: row.get(1),
+                    publisher: row.get(2),
+                    blacklisted: row.get(3),
+                    occurrences: row.get(4),
+                    medias: row.get(6),
+                };
+
+                let stats = MediaStats {
+                    total: row.get(5),
+                    downloaded: row.get(6),
+                    cropped: row.get(7),
+                };
+
+                (dataset, stats)
+            })
+            .collect())
+    }
+
+    /// Chooses the example media of the species that have none, although some of their medias were downloaded.
+    ///
+    /// Returns the number of species that got an example media.
+    pub async fn backfill_example_medias<T: Queryable<impl GenericClient>>(
+        strategy: ExampleMediaStrategy,
+        blacklist: &[Uuid],
+        db: &T,
+    ) -> Result<u64> {
+        let sql = r#"
+            SELECT DISTINCT speciess.id
+            FROM speciess, occurrences, medias
+            WHERE
+                speciess.example_media_path IS NULL AND
+                occurrences.species = speciess.id AND
+                medias.occurrence = occurrences.id AND
+                occurrences.dataset_key <> ALL($1) AND
+                medias.path IS NOT NULL AND
+                200 <= medias.status_code AND medias.status_code < 400
+            ORDER BY speciess.id
+        "#;
+
+        let ids = db
+            .client()
+            .query(sql, &[&blacklist])
+            .await?
+            .into_iter()
+            .map(|x| x.get::<_, i32>(0))
+            .collect::<Vec<_>>();
+
+        let mut backfilled = 0;
+
+        for id in ids {
+            let Some(mut species) = Species::get_by_id(id, db).await? else {
+                continue;
+            };
+
+            if species
+                .choose_example_media(strategy, blacklist, db)
+                .await?
+                .is_some()
+            {
+                backfilled += 1;
+            }
+        }
+
+        Ok(backfilled)
+    }
+
+    /// Returns the vernacular name of the species, or its name without the author if it has none.
+    pub fn display_name(&self) -> String {
+        match &self.vernacular_name {
+            Some(name) => name.clone(),
+            None => pretty_name(&self.valid_name),
         }
     }
 
     /// Searches the species on gbif and scrap its occurrences.
+    ///
+    /// The blacklisted datasets, the occurrence filters and the language of vernacular names are taken from the config.
     pub async fn scrap_occurrences<T: Queryable<impl GenericClient>>(
         species: Entry,
         max_occurrences: usize,
-        blacklist: &Uuid,
-        storage: &Storage,
+        config: &Config,
         db: &T,
     ) -> Result<Species> {
         // Check if species is already in the db.
         let db_species = Species::get_by_valid_name(&species.valid_name, db).await?;
+        let in_db = db_species.is_some();
 
-        let species_key = match db_species {
+        let species_key = match &db_species {
             // If scraping is already finished, early return.
-            Some(x) if x.done => return Ok(x),
+            Some(x) if x.done => return Ok(x.clone()),
 
             // If not, continue scraping from species_key in db.
             Some(x) => x.species_key,
@@ -204,37 +715,99 @@ impl Species {
         let species_key = if let Some(species_key) = species_key {
             species_key
         } else {
-            let pretty = pretty_name(&species.valid_name).expect(&format!(
-                "Failed to extract pretty name from \"{}\"",
-                species.valid_name,
-            ));
-            let gbif_response = search_species(&pretty).await?;
-
-            if let Some(r) = gbif_response.results.first() {
-                r.species_key
+            let max_age = TimeDelta::days(config.name_resolution_ttl_days);
+
+            if let Some(species_key) = NameResolution::resolve(&species, max_age, db).await? {
+                species_key
             } else {
                 warn!("species {} not found", species.valid_name);
+                let pretty = pretty_name(&species.valid_name);
+
+                // Save species with no species key, unless it was saved by a previous scrap.
+                if !in_db {
+                    Species::from_taxref(species, None, 0).save(db).await?;
+                }
 
-                // Save species with no species key.
-                Species::from_taxref(species, None, 0).save(db).await?;
                 return Err(Error::SpeciesNotFound(pretty));
             }
         };
 
         // Look if there already is a species with the same species key in the database.
-        let duplicate = Species::get_by_species_key(species_key, db).await?;
+        let duplicate = Species::get_by_species_key(species_key, db)
+            .await?
+            .filter(|x| x.valid_name != species.valid_name);
 
         if let Some(duplicate) = duplicate {
-            IgnoredSpecies::from_taxref(species, Some(species_key))
-                .save(db)
-                .await?;
+            let mut ignored = IgnoredSpecies::from_taxref(species, Some(species_key));
+            ignored.canonical_species_key = duplicate.species_key;
+            ignored.save(db).await?;
 
             return Ok(duplicate);
         }
 
+        Species::scrap_species_key(
+            species,
+            species_key,
+            db_species,
+            max_occurrences,
+            config,
+            db,
+        )
+        .await
+    }
+
+    /// Scraps the occurrences of a species from its GBIF species key, without looking for it in taxref.
+    ///
+    /// The taxonomy of the species is fetched from GBIF the first time, and the species is then scraped like the ones
+    /// found in taxref.
+    pub async fn scrap_occurrences_by_key<T: Queryable<impl GenericClient>>(
+        species_key: i64,
+        max_occurrences: usize,
+        config: &Config,
+        db: &T,
+    ) -> Result<Species> {
+        let db_species = Species::get_by_species_key(species_key, db).await?;
+
+        if let Some(db_species) = &db_species
+            && db_species.done
+        {
+            return Ok(db_species.clone());
+        }
+
+        let species = fetch_species(species_key).await?.into_entry();
+
+        // The species may have been scraped from taxref without finding its species key.
+        let db_species = match db_species {
+            Some(db_species) => Some(db_species),
+            None => Species::get_by_valid_name(&species.valid_name, db).await?,
+        };
+
+        Species::scrap_species_key(
+            species,
+            species_key,
+            db_species,
+            max_occurrences,
+            config,
+            db,
+        )
+        .await
+    }
+
+    /// Scraps the occurrences of a species whose species key is known, and saves it with its occurrences and medias.
+    async fn scrap_species_key<T: Queryable<impl GenericClient>>(
+        species: Entry,
+        species_key: i64,
+        db_species: Option<Species>,
+        max_occurrences: usize,
+        config: &Config,
+        db: &T,
+    ) -> Result<Species> {
+        let filters = &config.occurrence_filters;
+        let storage = &config.storage;
+
         // Start scraping occurrences.
         let mut json_occurrences =
-            search_occurrences(species_key, 0, MAX_LIMIT_OCCURRENCES).await?;
+            search_occurrences(species_key, 0, MAX_LIMIT_OCCURRENCES, filters).await?;
 
         let mut parsed_occurrences: OccurrencesResponse =
             serde_json::from_value(json_occurrences.clone())?;
@@ -242,31 +815,42 @@ impl Species {
         let mut count = parsed_occurrences.results.len();
 
         // Now that we know the total number of occurrences available, we can store the species in the database.
-        let mut db_species =
-            Species::from_taxref(species, Some(species_key), parsed_occurrences.count)
-                .save(db)
-                .await?;
+        // A species scraped again is updated rather than inserted.
+        let mut db_species = match db_species {
+            Some(mut x) => {
+                x.species_key = Some(species_key);
+                x.available_occurrences = parsed_occurrences.count;
+                x.save(db).await?;
+                x
+            }
+            None => {
+                Species::from_taxref(species, Some(species_key), parsed_occurrences.count)
+                    .save(db)
+                    .await?
+            }
+        };
 
-        // Count non blacklisted occurrences that have medias.
+        // Count allowed occurrences that have medias.
         let mut scraped = parsed_occurrences
             .results
             .iter()
-            .filter(|x| x.dataset_key != *blacklist)
+            .filter(|x| config.is_dataset_allowed(&x.dataset_key))
             .filter(|x| !x.medias.is_empty())
             .count();
 
         // If we don't have enough occurrences, fetch more.
         while scraped < max_occurrences && count < parsed_occurrences.count as usize {
-            let current = search_occurrences(species_key, count, MAX_LIMIT_OCCURRENCES).await?;
+            let current =
+                search_occurrences(species_key, count, MAX_LIMIT_OCCURRENCES, filters).await?;
 
             let parsed: OccurrencesResponse = serde_json::from_value(current.clone())?;
             count += parsed.results.len();
 
-            // Count non blacklisted occurrences that have medias.
+            // Count allowed occurrences that have medias.
             scraped += &parsed
                 .results
                 .iter()
-                .filter(|x| x.dataset_key != *blacklist)
+                .filter(|x| config.is_dataset_allowed(&x.dataset_key))
                 .filter(|x| !x.medias.is_empty())
                 .count();
 
@@ -291,6 +875,11 @@ impl Species {
 
         // Save occurrences and media in db.
         'outer: for result in &parsed_occurrences.results {
+            // Occurrences from datasets that are not whitelisted are never shown, there is no need to keep them.
+            if !config.is_dataset_whitelisted(&result.dataset_key) {
+                continue;
+            }
+
             // We only want to save occurrences that do not have media already present in the database (GBIF contains
             // duplicates that we want to avoid).
             for media in &result.medias {
@@ -303,9 +892,16 @@ impl Species {
             }
 
             // We have no longer duplicates: insert occurrences and medias in database.
-            let occurrence = Occurrence::create(result.key, result.dataset_key, &db_species)
-                .save(db)
-                .await?;
+            let occurrence = Occurrence::create(
+                result.key,
+                result.dataset_key,
+                &db_species,
+                result.country.clone(),
+                result.latitude,
+                result.longitude,
+            )
+            .save(db)
+            .await?;
 
             for media in &result.medias {
                 let url = if let Some(url) = media.url.as_ref() {
@@ -318,11 +914,172 @@ impl Species {
             }
         }
 
+        // A missing vernacular name is not worth failing the whole species.
+        db_species.vernacular_name =
+            match fetch_vernacular(species_key, &config.vernacular_language).await {
+                Ok(name) => name,
+                Err(e) => {
+                    warn!("failed to fetch vernacular name of {}: {}", species_key, e);
+                    None
+                }
+            };
+
         db_species.done = true;
+        db_species.updated_at = Some(Utc::now());
         db_species.save(db).await?;
 
         Ok(db_species)
     }
+
+    /// Imports the occurrences and medias of the species from the tables of a GBIF download archive.
+    ///
+    /// The directory must hold the `occurrence.txt` and `multimedia.txt` tables extracted from the archive. As when
+    /// scraping, occurrences with a media that is already in the database are skipped. Returns the number of imported
+    /// occurrences.
+    pub async fn import_archive<Q: Queryable<impl GenericClient>>(
+        &self,
+        dir: &Path,
+        db: &Q,
+    ) -> Result<usize> {
+        // Medias are in their own table, indexed by the GBIF id of their occurrence.
+        let mut medias: HashMap<i64, Vec<String>> = HashMap::new();
+        let multimedia = BufReader::new(File::open(dir.join("multimedia.txt"))?);
+
+        for row in Table::new(multimedia, &["gbifID", "type", "identifier"])? {
+            let row = row?;
+
+            if let (Ok(key), "StillImage", false) =
+                (row[0].parse(), row[1].as_str(), row[2].is_empty())
+            {
+                medias.entry(key).or_default().push(row[2].clone());
+            }
+        }
+
+        let occurrences = BufReader::new(File::open(dir.join("occurrence.txt"))?);
+        let mut imported = 0;
+
+        'outer: for row in Table::new(
+            occurrences,
+            &[
+                "gbifID",
+                "datasetKey",
+                "countryCode",
+                "decimalLatitude",
+                "decimalLongitude",
+            ],
+        )? {
+            let row = row?;
+
+            let (key, dataset_key) = match (row[0].parse::<i64>(), row[1].parse::<Uuid>()) {
+                (Ok(key), Ok(dataset_key)) => (key, dataset_key),
+                _ => {
+                    warn!("skipping invalid occurrence {:?}", row);
+                    continue;
+                }
+            };
+
+            let urls = match medias.get(&key) {
+                Some(urls) => urls,
+                None => continue,
+            };
+
+            if Occurrence::get_by_key(key, db).await?.is_some() {
+                continue;
+            }
+
+            // GBIF contains duplicates that we want to avoid.
+            for url in urls {
+                if Media::get_by_url(url, db).await?.is_some() {
+                    continue 'outer;
+                }
+            }
+
+            let country = Some(row[2].clone()).filter(|x| !x.is_empty());
+            let (latitude, longitude) = (row[3].parse().ok(), row[4].parse().ok());
+            let occurrence =
+                Occurrence::create(key, dataset_key, self, country, latitude, longitude)
+                    .save(db)
+                    .await?;
+
+            for url in urls {
+                Media::new(url, &occurrence).save(db).await?;
+            }
+
+            imported += 1;
+        }
+
+        Ok(imported)
+    }
+}
+
+/// The species key found on GBIF for a valid name, cached to avoid searching GBIF again at each scrap.
+///
+/// Valid names that were not found on GBIF are cached too, with no species key.
+#[ergol]
+#[derive(Serialize)]
+pub struct NameResolution {
+    /// Id of the row in the database.
+    #[id]
+    pub id: i32,
+
+    /// The valid name of the species, with its author.
+    #[unique]
+    pub valid_name: String,
+
+    /// The key of the taxon on GBIF, if it was found.
+    pub species_key: Option<i64>,
+
+    /// When the name was searched on GBIF.
+    pub resolved_at: DateTime<Utc>,
+}
+
+impl NameResolution {
+    /// Returns the key of a taxref entry on GBIF, searching GBIF only if the cached key is older than `max_age`.
+    pub async fn resolve<Q: Queryable<impl GenericClient>>(
+        species: &Entry,
+        max_age: TimeDelta,
+        db: &Q,
+    ) -> Result<Option<i64>> {
+        let cached = NameResolution::get_by_valid_name(&species.valid_name, db).await?;
+
+        if let Some(cached) = &cached
+            && Utc::now() - cached.resolved_at < max_age
+        {
+            trace!("using cached species key for {}", species.valid_name);
+            return Ok(cached.species_key);
+        }
+
+        let gbif_response = search_species(&pretty_name(&species.valid_name)).await?;
+
+        // For infraspecific taxa, we want the result that matches the taxon itself rather than its species.
+        let result = if species.is_species() {
+            gbif_response.results.first()
+        } else {
+            gbif_response
+                .results
+                .iter()
+                .find(|x| x.is_infraspecific())
+                .or(gbif_response.results.first())
+        };
+
+        let species_key = result.map(|x| x.taxon_key());
+
+        match cached {
+            Some(mut cached) => {
+                cached.species_key = species_key;
+                cached.resolved_at = Utc::now();
+                cached.save(db).await?;
+            }
+
+            None => {
+                NameResolution::create(species.valid_name.clone(), species_key, Utc::now())
+                    .save(db)
+                    .await?;
+            }
+        }
+
+        Ok(species_key)
+    }
 }
 
 /// An occurrence of a species.
@@ -344,6 +1101,50 @@ pub struct Occurrence {
     #[many_to_one(occurrences)]
     #[serde(skip)]
     pub species: Species,
+
+    /// The ISO 3166-1 alpha-2 code of the country where the occurrence was observed, if known.
+    pub country: Option<String>,
+
+    /// The latitude where the occurrence was observed, in degrees, if known.
+    pub latitude: Option<f64>,
+
+    /// The longitude where the occurrence was observed, in degrees, if known.
+    pub longitude: Option<f64>,
+}
+
+/// The number of occurrences and downloaded medias of a species from a dataset.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DatasetCount {
+    /// UUID of the dataset in GBIF.
+    pub key: Uuid,
+
+    /// The title of the dataset, if it was fetched.
+    pub title: Option<String>,
+
+    /// The name of the organization that publishes the dataset, if it is known.
+    pub publisher: Option<String>,
+
+    /// Whether the dataset is blacklisted.
+    pub blacklisted: bool,
+
+    /// The number of occurrences of the species from the dataset.
+    pub occurrences: i64,
+
+    /// The number of downloaded medias of these occurrences.
+    pub medias: i64,
+}
+
+/// The numbers of medias of a species, leaving out the blacklisted datasets.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct MediaStats {
+    /// The number of medias, downloaded or not.
+    pub total: i64,
+
+    /// The number of medias that were successfully downloaded.
+    pub downloaded: i64,
+
+    /// The number of downloaded medias that have a crop box.
+    pub cropped: i64,
 }
 
 /// A media of an occurrence.
@@ -374,10 +1175,13 @@ pub struct Media {
     /// If this value is true, and x, y, width and height are still none, it means that the cropping has failed.
     pub cropped: bool,
 
-    /// x coordinate of the bounding box of the cropping.
+    /// x coordinate of the center of the bounding box of the cropping.
+    ///
+    /// Like every bounding box coordinate of a media, this is in pixels of the original image, from its top left
+    /// corner.
     pub x: Option<f64>,
 
-    /// y coordinate of the bounding box of the cropping.
+    /// y coordinate of the center of the bounding box of the cropping.
     pub y: Option<f64>,
 
     /// Width of the bounding box of the cropping.
@@ -394,10 +1198,10 @@ pub struct Media {
     #[serde(skip)]
     pub occurrence: Occurrence,
 
-    /// x coordinate of the manual bounding box.
+    /// x coordinate of the center of the manual bounding box.
     pub manual_x: Option<f64>,
 
-    /// y coordinate of the manual bounding box.
+    /// y coordinate of the center of the manual bounding box.
     pub manual_y: Option<f64>,
 
     /// Width of the manual bounding box.
@@ -405,9 +1209,96 @@ pub struct Media {
 
     /// Height of the manual bounding box.
     pub manual_height: Option<f64>,
+
+    /// The URL that was finally downloaded, after following redirects.
+    pub final_url: Option<String>,
+
+    /// The content type announced by the server when the media was downloaded.
+    pub content_type: Option<String>,
+
+    /// The `ETag` header of the last successful download, sent back as `If-None-Match` when downloading again.
+    pub etag: Option<String>,
+
+    /// The `Last-Modified` header of the last successful download, sent back as `If-Modified-Since` when downloading
+    /// again.
+    pub last_modified: Option<String>,
+
+    /// When the last download attempt was made.
+    pub last_attempt: Option<DateTime<Utc>>,
+
+    /// When the photo was taken according to its EXIF metadata, in the local time of the camera.
+    pub captured_at: Option<NaiveDateTime>,
+
+    /// The maker of the camera according to the EXIF metadata.
+    pub camera_make: Option<String>,
+
+    /// The model of the camera according to the EXIF metadata.
+    pub camera_model: Option<String>,
+
+    /// The latitude where the photo was taken according to the EXIF metadata.
+    pub gps_latitude: Option<f64>,
+
+    /// The longitude where the photo was taken according to the EXIF metadata.
+    pub gps_longitude: Option<f64>,
+
+    /// The SHA-256 hash of the downloaded file, in hexadecimal.
+    pub sha256: Option<String>,
+}
+
+/// The bounding box of the crop of a media, in pixels of the original image.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct CropBox {
+    /// x coordinate of the center of the box.
+    pub x: f64,
+
+    /// y coordinate of the center of the box.
+    pub y: f64,
+
+    /// Width of the box.
+    pub width: f64,
+
+    /// Height of the box.
+    pub height: f64,
+
+    /// Confidence of the model, none if the box was drawn manually.
+    pub confidence: Option<f64>,
+
+    /// Whether the box was drawn manually.
+    pub manual: bool,
 }
 
 impl Media {
+    /// Returns the bounding box of the crop, the manual one if there is one, or none if the media is not cropped.
+    pub fn crop_box(&self) -> Option<CropBox> {
+        let manual = (
+            self.manual_x,
+            self.manual_y,
+            self.manual_width,
+            self.manual_height,
+        );
+        let model = (self.x, self.y, self.width, self.height);
+
+        match (manual, model) {
+            ((Some(x), Some(y), Some(width), Some(height)), _) => Some(CropBox {
+                x,
+                y,
+                width,
+                height,
+                confidence: None,
+                manual: true,
+            }),
+            (_, (Some(x), Some(y), Some(width), Some(height))) => Some(CropBox {
+                x,
+                y,
+                width,
+                height,
+                confidence: self.confidence,
+                manual: false,
+            }),
+            _ => None,
+        }
+    }
+
     /// Creates a media with good default values.
     pub fn new(url: &str, occurrence: &Occurrence) -> MediaWithoutId {
         Media::create(
@@ -426,6 +1317,17 @@ impl Media {
             None,
             None,
             None,
+            None,
+            None,
+            None,
+            None,
+            None,
+            None,
+            None,
+            None,
+            None,
+            None,
+            None,
         )
     }
 
@@ -433,7 +1335,7 @@ impl Media {
     pub async fn download<Q: Queryable<impl GenericClient>>(
         &mut self,
         client: &Client,
-        storage: &Storage,
+        config: &Config,
         db: &Q,
     ) -> Result<i32> {
         if let Some(status_code) = self.status_code {
@@ -443,7 +1345,7 @@ impl Media {
 
         let occurrence = self.occurrence(db).await?;
         let species = occurrence.species(db).await?;
-        self.download_with_info(&occurrence, &species, client, storage, db)
+        self.download_with_info(&occurrence, &species, client, config, db)
             .await
     }
 
@@ -470,41 +1372,66 @@ impl Media {
         occurrence: &Occurrence,
         species: &Species,
         client: &Client,
-        storage: &Storage,
+        config: &Config,
         db: &Q,
     ) -> Result<i32> {
-        let mut retries = 0;
+        // A connection reset in the middle of the body fails the attempt, and the next one starts from scratch.
+        let policy = RetryPolicy::new(config.media_download_attempts.max(1), MEDIA_RETRY_BACKOFF);
+
+        let download = retry(
+            policy,
+            |attempt| {
+                trace!("downloading {} {} (attempt={})", self.id, self.url, attempt);
+                self.download_dirty_with_info(occurrence, species, client, config)
+            },
+            |result| match result {
+                // Too many requests, wait a little bit, and try again
+                Ok((download, _)) => download.code == 429,
+                Err(e) => is_transient(e),
+            },
+        )
+        .await;
 
-        let (code, target_local) = loop {
-            let download = self
-                .download_dirty_with_info(occurrence, species, client, storage)
-                .await;
+        self.last_attempt = Some(Utc::now());
 
-            let (code, extension) = download.ok().unwrap_or((600, None));
+        let (code, file) = match download {
+            // The file we already have is still up to date, keep it as it is.
+            Ok((download, _)) if download.code == NOT_MODIFIED && self.path.is_some() => {
+                debug!("{} {} was not modified", self.id, self.url);
+                self.save(db).await?;
+                return Ok(self.status_code.unwrap_or(200));
+            }
 
-            if code == 429 && retries > 0 {
-                // Too many requests, wait a little bit, and try again
-                trace!(
-                    "Received 429 for {} {}, waiting a little bit (attempt={})",
-                    self.id,
-                    self.url,
-                    4 - retries,
-                );
-                retries -= 1;
-                sleep(Duration::from_secs(10)).await;
-            } else {
-                break (code, extension);
+            Ok((download, file)) => {
+                self.final_url = Some(download.final_url);
+                self.content_type = download.content_type;
+                if file.is_some() {
+                    self.etag = download.etag;
+                    self.last_modified = download.last_modified;
+                }
+                (download.code, file)
             }
+            Err(_) => (600, None),
         };
 
         self.status_code = Some(code);
-        if let Some(target_local) = target_local {
+        if let Some(file) = file {
             self.path = Some(
-                target_local
+                file.path
                     .to_str()
                     .expect("Failed to convert path to str, this should never happen")
                     .to_string(),
             );
+
+            self.sha256 = file.sha256;
+
+            if let Some(exif) = file.exif {
+                self.captured_at = exif.captured_at;
+                self.camera_make = exif.camera_make;
+                self.camera_model = exif.camera_model;
+                self.gps_latitude = exif.latitude;
+                self.gps_longitude = exif.longitude;
+            }
         }
         self.save(db).await?;
 
@@ -516,8 +1443,24 @@ impl Media {
         occurrence: &Occurrence,
         species: &Species,
         client: &Client,
-        storage: &Storage,
-    ) -> Result<(i32, Option<PathBuf>)> {
+        config: &Config,
+    ) -> Result<(ImageDownload, Option<DownloadedFile>)> {
+        let storage = &config.storage;
+
+        // Some hosts never answer with an image, it is no use asking them.
+        if is_host_blacklisted(&self.url, &config.blacklisted_media_hosts) {
+            debug!("{} {} is on a blacklisted host", self.id, self.url);
+            let download = ImageDownload {
+                code: HOST_BLACKLISTED,
+                extension: None,
+                final_url: self.url.clone(),
+                content_type: None,
+                etag: None,
+                last_modified: None,
+            };
+            return Ok((download, None));
+        }
+
         // No longer needed since we use the names for data dir.
         // let species_key = if let Some(species_key) = species.species_key {
         //     species_key
@@ -530,66 +1473,803 @@ impl Media {
             .medias_dir_local(species)
             .join(format!("{}_{:04}", occurrence.key, self.id));
 
-        let mut target = storage
+        let target = storage
             .medias_dir(species)
             .join(format!("{}_{:04}", occurrence.key, self.id));
 
-        // Start downloading.
-        let req = client.get(&self.url).send().await?;
-        let status = req.status();
-        let code = status.as_u16() as i32;
+        // Only ask whether the media changed if we still have the file we would keep.
+        let has_file = match &self.path {
+            Some(path) => storage.medias_root().join(path).exists(),
+            None => false,
+        };
 
-        let target = if status.is_success() {
-            let mut byte_stream = req.bytes_stream();
+        let mut download = if has_file {
+            download_image_if_modified(
+                client,
+                &self.url,
+                &target,
+                self.etag.as_deref(),
+                self.last_modified.as_deref(),
+                &config.allowed_mime_types,
+            )
+            .await?
+        } else {
+            download_image_if_modified(
+                client,
+                &self.url,
+                &target,
+                None,
+                None,
+                &config.allowed_mime_types,
+            )
+            .await?
+        };
 
-            // Download first chunk to find magic numbers, mime type and extension.
-            let chunk = byte_stream.next().await;
+        // Icons and thumbnails are of no use, only their header is read to know their size.
+        if let Some(extension) = download.extension
+            && (config.min_width > 0 || config.min_height > 0)
+        {
+            let path = target.with_extension(extension);
+            let (min_width, min_height) = (config.min_width, config.min_height);
+
+            let too_small = spawn_blocking(move || is_too_small(&path, min_width, min_height))
+                .await
+                .unwrap_or(false);
+
+            if too_small {
+                debug!("{} {} is too small", self.id, self.url);
+                tokio::fs::remove_file(target.with_extension(extension)).await?;
+                download.code = TOO_SMALL;
+                download.extension = None;
+            }
+        }
 
-            let bytes = if let Some(chunk) = chunk {
-                chunk
-            } else {
-                return Err(Error::DownloadFailed(self.url.clone()));
-            }?;
+        // Hashing the file and reading its metadata need to read the whole file, and are done before it is converted.
+        let (sha256, exif) = match download.extension {
+            Some(extension) => {
+                let path = target.with_extension(extension);
+                spawn_blocking(move || (sha256_file(&path).ok(), exif::read(&path)))
+                    .await
+                    .unwrap_or_default()
+            }
+            None => (None, None),
+        };
 
-            // Find mime type and extension.
-            let ty = infer::get(&bytes).ok_or(Error::UnknownMediaType(self.url.clone()))?;
+        // Convert the media if it is not already in the format we want, keeping it as is if it can't be converted.
+        if let (Some(extension), Some(format)) = (download.extension, config.convert_to)
+            && extension != format.extension()
+        {
+            let path = target.with_extension(extension);
+            let quality = config.convert_quality;
+
+            let converted = spawn_blocking(move || convert_image(&path, format, quality))
+                .await
+                .map_err(|e| Error::IoError(std::io::Error::other(e)))
+                .and_then(|x| x);
+
+            match converted {
+                Ok(_) => {
+                    download.extension = Some(format.extension());
+                    download.content_type = Some(format.mime_type().to_owned());
+                }
+                Err(e) => warn!("failed to convert {} to {:?}: {}", self.url, format, e),
+            }
+        }
 
-            if ty.matcher_type() != MatcherType::Image {
-                return Err(Error::UnknownMediaType(self.url.clone()));
+        let file = download.extension.map(|extension| {
+            target_local.set_extension(extension);
+            DownloadedFile {
+                path: target_local,
+                sha256,
+                exif,
             }
+        });
 
-            // Set the extension accordingly.
-            target.set_extension(ty.extension());
-            target_local.set_extension(ty.extension());
+        Ok((download, file))
+    }
 
-            let mut file = File::create(&target)?;
+    /// Returns true if a media was successfully downloaded.
+    ///
+    /// A successful response that was not an image has no file, so it is not downloaded.
+    pub fn is_downloaded(&self) -> bool {
+        match self.status_code {
+            Some(i) if 200 <= i && i < 300 => self.path.is_some(),
+            _ => false,
+        }
+    }
 
-            // Write first chunk.
-            file.write_all(&bytes)?;
+    /// Deletes the media from the database, along with its original and cropped files.
+    ///
+    /// The files are removed first, and files that are already missing are not an error. If a file can't be removed,
+    /// the error is returned and the row is kept, so that we never have files that no row references, and the
+    /// deletion can simply be attempted again. The feedbacks on the media are deleted with it.
+    pub async fn delete_with_files<Q: Queryable<impl GenericClient>>(
+        self,
+        storage: &Storage,
+        db: &Q,
+    ) -> Result<()> {
+        // Deduplicated medias share their files, which are only removed with the last of them.
+        let sql = r#"SELECT COUNT(*) FROM medias WHERE path = $1 AND id <> $2"#;
+        let shared = match &self.path {
+            Some(path) => {
+                let row = db.client().query_one(sql, &[path, &self.id]).await?;
+                row.get::<_, i64>(0) > 0
+            }
+            None => false,
+        };
+
+        if let Some(path) = self.path.as_ref().filter(|_| !shared) {
+            for root in [storage.cropped_root(), storage.medias_root()] {
+                match tokio::fs::remove_file(root.join(path)).await {
+                    Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+                    _ => (),
+                }
+            }
+        }
+
+        self.delete(db).await?;
+        Ok(())
+    }
+
+    /// Computes the hashes of the downloaded medias that don't have one yet, and returns how many were computed.
+    pub async fn hash_missing<Q: Queryable<impl GenericClient>>(
+        storage: &Storage,
+        db: &Q,
+    ) -> Result<usize> {
+        let sql = r#"SELECT * FROM medias WHERE path IS NOT NULL AND sha256 IS NULL ORDER BY id"#;
+        let rows = db.client().query(sql, &[]).await?;
+        let mut hashed = 0;
+
+        for row in rows {
+            let mut media = Media::from_row(&row);
+            let path = storage
+                .medias_root()
+                .join(media.path.as_deref().unwrap_or_default());
+
+            match spawn_blocking(move || sha256_file(path)).await {
+                Ok(Ok(sha256)) => {
+                    media.sha256 = Some(sha256);
+                    media.save(db).await?;
+                    hashed += 1;
+                }
+                Ok(Err(e)) => warn!("failed to hash media {}: {}", media.id, e),
+                Err(e) => warn!("failed to hash media {}: {}", media.id, e),
+            }
+        }
+
+        Ok(hashed)
+    }
+
+    /// Returns the medias that have a crop box but whose cropped file is missing from the cropped medias.
+    ///
+    /// This happens when moving the cropped file failed after the box was saved, or when the file was moved by hand.
+    pub async fn missing_crops<Q: Queryable<impl GenericClient>>(
+        storage: &Storage,
+        db: &Q,
+    ) -> Result<Vec<Media>> {
+        let sql = r#"
+            SELECT * FROM medias
+            WHERE path IS NOT NULL AND (x IS NOT NULL OR manual_x IS NOT NULL)
+            ORDER BY id
+        "#;
+
+        let missing = db
+            .client()
+            .query(sql, &[])
+            .await?
+            .iter()
+            .map(Media::from_row)
+            .filter(|x| {
+                let path = x.path.as_deref().unwrap_or_default();
+                !storage.cropped_root().join(path).exists()
+            })
+            .collect();
+
+        Ok(missing)
+    }
+
+    /// Forgets the crop box found by the model, so that the next crop processes the media again.
+    ///
+    /// A manual crop box is kept, and the cropped file is written again from it instead.
+    pub fn reset_crop(&mut self, storage: &Storage) -> Result<()> {
+        self.x = None;
+        self.y = None;
+        self.width = None;
+        self.height = None;
+        self.confidence = None;
+
+        match self.crop_box() {
+            Some(crop_box) => self.write_crop(&crop_box, storage),
+            None => {
+                self.cropped = false;
+                Ok(())
+            }
+        }
+    }
+
+    /// Crops the downloaded file of the media with a box, and writes it to the cropped medias.
+    pub fn write_crop(&self, crop_box: &CropBox, storage: &Storage) -> Result<()> {
+        let path = self.path.as_deref().ok_or_else(|| {
+            Error::IoError(std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                format!("media {} is not downloaded", self.id),
+            ))
+        })?;
+
+        let image = ImageReader::open(storage.medias_root().join(path))?
+            .with_guessed_format()?
+            .decode()
+            .map_err(|e| Error::IoError(std::io::Error::other(e)))?;
+
+        // The box may go past the edges of the image, so its corners are kept inside the image before being cast.
+        let (image_width, image_height) = (image.width() as f64, image.height() as f64);
+        let left = (crop_box.x - crop_box.width / 2.0).clamp(0.0, image_width);
+        let top = (crop_box.y - crop_box.height / 2.0).clamp(0.0, image_height);
+        let right = (crop_box.x + crop_box.width / 2.0).clamp(left, image_width);
+        let bottom = (crop_box.y + crop_box.height / 2.0).clamp(top, image_height);
+
+        let cropped = image.crop_imm(
+            left as u32,
+            top as u32,
+            (right - left) as u32,
+            (bottom - top) as u32,
+        );
+
+        let target = storage.cropped_root().join(path);
+        if let Some(parent) = target.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        cropped
+            .to_rgb8()
+            .save(target)
+            .map_err(|e| Error::IoError(std::io::Error::other(e)))
+    }
+
+    /// Points the medias that have the same hash to a single file, and returns the files that are no longer needed.
+    ///
+    /// In each group of identical medias, the file of the first media whose file exists is kept. Only the database is
+    /// changed, so that it can be done in a transaction: the files are changed by [`Dedup::apply`] once it is
+    /// committed.
+    pub async fn dedup<Q: Queryable<impl GenericClient>>(
+        storage: &Storage,
+        db: &Q,
+    ) -> Result<Dedup> {
+        let sql = r#"
+            SELECT
+                id, path, sha256
+            FROM
+                medias
+            WHERE
+                path IS NOT NULL AND
+                sha256 IN (
+                    SELECT sha256 FROM medias
+                    WHERE path IS NOT NULL AND sha256 IS NOT NULL
+                    GROUP BY sha256
+                    HAVING COUNT(DISTINCT path) > 1
+                )
+            ORDER BY
+                sha256, id
+            ;
+        "#;
+
+        let mut groups: Vec<(String, Vec<(i32, String)>)> = vec![];
+        for row in db.client().query(sql, &[]).await? {
+            let (id, path, sha256) = (row.get(0), row.get(1), row.get::<_, String>(2));
+
+            match groups.last_mut() {
+                Some((last, medias)) if *last == sha256 => medias.push((id, path)),
+                _ => groups.push((sha256, vec![(id, path)])),
+            }
+        }
+
+        let mut dedup = Dedup::default();
+
+        for (sha256, medias) in groups {
+            // The canonical file may have been removed, another one is promoted then.
+            let canonical = medias
+                .iter()
+                .map(|(_, path)| path)
+                .find(|path| storage.medias_root().join(path).exists());
+
+            let Some(canonical) = canonical.cloned() else {
+                warn!("no file left for the medias with hash {}", sha256);
+                continue;
+            };
+
+            let ids = medias
+                .iter()
+                .filter(|(_, path)| *path != canonical)
+                .map(|(id, _)| *id)
+                .collect::<Vec<_>>();
+
+            let sql = r#"UPDATE medias SET path = $1 WHERE id = ANY($2)"#;
+            db.client().execute(sql, &[&canonical, &ids]).await?;
+            dedup.medias += ids.len();
+
+            for (_, path) in medias {
+                if path != canonical && !dedup.redundant.iter().any(|(x, _)| *x == path) {
+                    dedup.redundant.push((path, canonical.clone()));
+                }
+            }
+        }
+
+        Ok(dedup)
+    }
+}
+
+/// The changes to make to the files once identical medias point to the same file.
+#[derive(Debug, Default)]
+pub struct Dedup {
+    /// The number of medias that now point to another file.
+    pub medias: usize,
+
+    /// The files that are no longer needed, relative to the medias root, with the file that replaces them.
+    pub redundant: Vec<(String, String)>,
+}
 
-            // Perform rest of downloading.
-            while let Some(chunk) = byte_stream.next().await {
-                let bytes = chunk?;
-                file.write_all(&bytes)?;
+impl Dedup {
+    /// Removes the redundant files, and returns the number of bytes reclaimed.
+    ///
+    /// A redundant cropped file replaces the canonical one if there is none, so that no crop is lost.
+    pub fn apply(&self, storage: &Storage) -> Result<u64> {
+        let mut reclaimed = 0;
+
+        for (path, canonical) in &self.redundant {
+            let cropped = storage.cropped_root().join(path);
+            let canonical_cropped = storage.cropped_root().join(canonical);
+
+            if cropped.exists() && !canonical_cropped.exists() {
+                std::fs::rename(&cropped, &canonical_cropped)?;
+            }
+
+            for file in [cropped, storage.medias_root().join(path)] {
+                match std::fs::metadata(&file) {
+                    Ok(metadata) => {
+                        std::fs::remove_file(&file)?;
+                        reclaimed += metadata.len();
+                    }
+                    Err(e) if e.kind() == std::io::ErrorKind::NotFound => (),
+                    Err(e) => return Err(e.into()),
+                }
             }
+        }
 
-            Some(target_local)
+        Ok(reclaimed)
+    }
+}
+
+/// The time waited after a failed media download, before the next attempt.
+const MEDIA_RETRY_BACKOFF: Duration = Duration::from_secs(10);
+
+/// Marks one media of each occurrence to download, chosen by `policy`, leaving out the blacklisted datasets.
+///
+/// The medias that are already marked stay marked. Returns the ids of the chosen medias, whether they were already
+/// marked or not.
+pub async fn select_representative_media_per_occurrence<T: Queryable<impl GenericClient>>(
+    policy: RepresentativeMedia,
+    blacklist: &[Uuid],
+    db: &T,
+) -> Result<Vec<i32>> {
+    let sql = format!(
+        r#"
+        UPDATE medias
+        SET to_download = TRUE
+        FROM (
+            SELECT DISTINCT ON (occurrence) medias.id
+            FROM medias, occurrences
+            WHERE
+                medias.occurrence = occurrences.id and
+                occurrences.dataset_key <> ALL($1)
+            ORDER BY
+                medias.occurrence, {}
+        ) AS subquery
+        WHERE medias.id = subquery.id
+        RETURNING medias.id;
+    "#,
+        policy.order_by()
+    );
+
+    let rows = db.client().query(&sql, &[&blacklist]).await?;
+    Ok(rows.iter().map(|row| row.get(0)).collect())
+}
+
+/// Unmarks the medias to download of each species beyond the `max_medias` first ones, and returns how many were
+/// unmarked.
+///
+/// The medias chosen by `policy` for each occurrence come first, then the second medias of each occurrence, and so on,
+/// so that the medias kept are spread over as many occurrences as possible.
+pub async fn cap_medias_per_species<T: Queryable<impl GenericClient>>(
+    max_medias: usize,
+    policy: RepresentativeMedia,
+    db: &T,
+) -> Result<u64> {
+    let sql = format!(
+        r#"
+        UPDATE medias
+        SET to_download = FALSE
+        FROM (
+            SELECT
+                id,
+                ROW_NUMBER() OVER (PARTITION BY species ORDER BY occurrence_rank, id) AS species_rank
+            FROM (
+                SELECT
+                    medias.id,
+                    occurrences.species,
+                    ROW_NUMBER() OVER (PARTITION BY medias.occurrence ORDER BY {}) AS occurrence_rank
+                FROM medias, occurrences
+                WHERE
+                    medias.occurrence = occurrences.id AND
+                    medias.to_download
+            ) AS subquery
+        ) AS ranked
+        WHERE medias.id = ranked.id AND ranked.species_rank > $1;
+    "#,
+        policy.order_by()
+    );
+
+    Ok(db.client().execute(&sql, &[&(max_medias as i64)]).await?)
+}
+
+/// Marks the medias to download: one media of each occurrence chosen by `policy`, and every media of the species that
+/// have less than `min_occurrences` occurrences, leaving out the blacklisted datasets.
+///
+/// If `max_medias` is not 0, at most `max_medias` medias are marked for each species, including the species with less
+/// than `min_occurrences` occurrences. The previous marks are cleared first, so that the marking can be done again
+/// after changing `min_occurrences`. Returns the number of medias marked.
+pub async fn mark_to_download<T: Queryable<impl GenericClient>>(
+    min_occurrences: usize,
+    max_medias: usize,
+    policy: RepresentativeMedia,
+    blacklist: &[Uuid],
+    db: &T,
+) -> Result<u64> {
+    let sql = r#"UPDATE medias SET to_download = FALSE WHERE to_download"#;
+    db.client().execute(sql, &[]).await?;
+
+    // First one: mark one media for every occurrence
+    let first = select_representative_media_per_occurrence(policy, blacklist, db)
+        .await?
+        .len() as u64;
+
+    // Second one: mark every media for every species with less than min_occurrences occurrences
+    let sql = r#"
+        UPDATE medias
+        SET to_download = TRUE
+        FROM (
+            SELECT occurrences.id
+            FROM occurrences,
+                (
+                    SELECT occurrences.species
+                    FROM occurrences
+                    WHERE occurrences.dataset_key <> ALL($1)
+                    GROUP BY occurrences.species
+                    HAVING count(occurrences.id) < $2
+                ) as subquery
+            WHERE
+                occurrences.species = subquery.species AND
+                occurrences.dataset_key <> ALL($1)
+        ) AS subquery2
+        WHERE medias.occurrence = subquery2.id AND NOT medias.to_download;
+    "#;
+
+    let rest = db
+        .client()
+        .execute(sql, &[&blacklist, &(min_occurrences as i64)])
+        .await?;
+
+    let capped = match max_medias {
+        0 => 0,
+        max_medias => cap_medias_per_species(max_medias, policy, db).await?,
+    };
+
+    Ok(first + rest - capped)
+}
+
+/// Creates the HTTP client used to download medias.
+///
+/// Redirects are followed at most `max_redirects` times, and a redirect to an URL that was already visited fails
+/// right away instead of looping until the limit. Connecting must take less than `connect_timeout`, and the whole
+/// request, body included, less than `timeout`.
+pub fn download_client(
+    max_redirects: usize,
+    connect_timeout: Duration,
+    timeout: Duration,
+) -> Result<Client> {
+    let policy = Policy::custom(move |attempt| {
+        if attempt.previous().len() > max_redirects {
+            attempt.error("too many redirects")
+        } else if attempt.previous().contains(attempt.url()) {
+            attempt.error("redirect loop")
         } else {
-            None
-        };
+            attempt.follow()
+        }
+    });
+
+    Ok(Client::builder()
+        .connect_timeout(connect_timeout)
+        .timeout(timeout)
+        .redirect(policy)
+        .build()?)
+}
+
+/// The status code recorded for a media that is smaller than the minimum dimensions, and was deleted.
+pub const TOO_SMALL: i32 = 594;
+
+/// The status code recorded for a media whose host is blacklisted, without making any request.
+pub const HOST_BLACKLISTED: i32 = 595;
+
+/// The status code recorded for a media whose server answered successfully, but with an empty body.
+pub const EMPTY_RESPONSE: i32 = 596;
 
-        Ok((code, target))
+/// Returns true if an image is narrower or shorter than the minimum dimensions, reading only its header.
+///
+/// An image whose dimensions can't be read is never too small.
+pub fn is_too_small(path: &Path, min_width: u32, min_height: u32) -> bool {
+    match image::image_dimensions(path) {
+        Ok((width, height)) => width < min_width || height < min_height,
+        Err(_) => false,
     }
+}
 
-    /// Returns true if a media was successfully downloaded.
-    pub fn is_downloaded(&self) -> bool {
-        match self.status_code {
-            Some(i) if 200 <= i && i < 300 => true,
-            _ => false,
+/// Returns true if the host of an URL is one of the blacklisted hosts or one of their subdomains.
+///
+/// An URL that can't be parsed or has no host is never blacklisted.
+pub fn is_host_blacklisted(url: &str, hosts: &[String]) -> bool {
+    let Some(host) = reqwest::Url::parse(url)
+        .ok()
+        .and_then(|x| x.host_str().map(|x| x.to_lowercase()))
+    else {
+        return false;
+    };
+
+    hosts.iter().any(|blacklisted| {
+        let blacklisted = blacklisted.trim_start_matches('.').to_lowercase();
+        host == blacklisted
+            || host
+                .strip_suffix(&blacklisted)
+                .is_some_and(|x| x.ends_with('.'))
+    })
+}
+
+/// The number of bytes at the beginning of a media that are buffered to detect its type, which is more than the
+/// magic numbers of every image type need.
+const INFER_BYTES: usize = 64;
+
+/// The status code of a server telling that a media didn't change since we downloaded it.
+pub const NOT_MODIFIED: i32 = 304;
+
+/// The outcome of the download of an image.
+#[derive(Debug)]
+pub struct ImageDownload {
+    /// The HTTP status code, or one of our own codes when the response is not an image.
+    pub code: i32,
+
+    /// The extension of the file, if it was written.
+    pub extension: Option<&'static str>,
+
+    /// The URL that was finally downloaded, after following redirects.
+    pub final_url: String,
+
+    /// The content type announced by the server, if any, or the one of the format the file was converted to.
+    pub content_type: Option<String>,
+
+    /// The `ETag` header of the response, if any.
+    pub etag: Option<String>,
+
+    /// The `Last-Modified` header of the response, if any.
+    pub last_modified: Option<String>,
+}
+
+/// Downloads an image next to the target path, with the extension matching the type of the image.
+///
+/// An empty successful response is reported with the [`EMPTY_RESPONSE`] status code, and no file is left on the disk.
+/// A response that is not an image keeps its status code, but has no extension since no file is written.
+pub async fn download_image(client: &Client, url: &str, target: &Path) -> Result<ImageDownload> {
+    download_image_if_modified(client, url, target, None, None, &[]).await
+}
+
+/// Downloads an image like [`download_image`], unless it didn't change since the download that gave its `ETag` and
+/// `Last-Modified` headers.
+///
+/// If the server answers that the image is not modified, the [`NOT_MODIFIED`] status code is returned and nothing is
+/// written.
+///
+/// If some mime types are allowed, only the files whose detected mime type is one of them are kept, whatever their
+/// kind, otherwise every image is kept.
+pub async fn download_image_if_modified(
+    client: &Client,
+    url: &str,
+    target: &Path,
+    etag: Option<&str>,
+    last_modified: Option<&str>,
+    allowed_mime_types: &[String],
+) -> Result<ImageDownload> {
+    let mut req = client.get(url);
+
+    if let Some(etag) = etag {
+        req = req.header(IF_NONE_MATCH, etag);
+    }
+
+    if let Some(last_modified) = last_modified {
+        req = req.header(IF_MODIFIED_SINCE, last_modified);
+    }
+
+    RATE_LIMITER.acquire().await;
+    let req = req.send().await?;
+    let status = req.status();
+    let header = |name| {
+        req.headers()
+            .get(name)
+            .and_then(|x: &HeaderValue| x.to_str().ok())
+            .map(String::from)
+    };
+
+    if req.url().as_str() != url {
+        debug!("{} was redirected to {}", url, req.url());
+    }
+
+    let mut download = ImageDownload {
+        code: status.as_u16() as i32,
+        extension: None,
+        final_url: req.url().to_string(),
+        content_type: header(CONTENT_TYPE),
+        etag: header(ETAG),
+        last_modified: header(LAST_MODIFIED),
+    };
+
+    if !status.is_success() {
+        return Ok(download);
+    }
+
+    let mut byte_stream = req.bytes_stream();
+
+    // Buffer the beginning of the file to find magic numbers, mime type and extension, since some servers send it in
+    // chunks too small for them.
+    let mut bytes = vec![];
+    while bytes.len() < INFER_BYTES {
+        match byte_stream.next().await {
+            Some(chunk) => bytes.extend_from_slice(&chunk?),
+            None => break,
+        }
+    }
+
+    if bytes.is_empty() {
+        download.code = EMPTY_RESPONSE;
+        return Ok(download);
+    }
+
+    // Find mime type and extension.
+    let allowed = |ty: &infer::Type| {
+        if allowed_mime_types.is_empty() {
+            ty.matcher_type() == MatcherType::Image
+        } else {
+            allowed_mime_types.iter().any(|x| x == ty.mime_type())
+        }
+    };
+
+    let ty = match infer::get(&bytes) {
+        Some(ty) if allowed(&ty) => ty,
+        _ => {
+            debug!(
+                "{} is not an image (content type {:?})",
+                url, download.content_type
+            );
+            return Ok(download);
+        }
+    };
+
+    let target = target.with_extension(ty.extension());
+    let part = target.with_extension(format!("{}.part", ty.extension()));
+
+    // The file is written next to its final location and only moved there once complete, so that a download that
+    // fails in the middle of the body never leaves a partial file.
+    let written: Result<()> = async {
+        let mut file = File::create(&part)?;
+
+        // Write buffered chunks.
+        file.write_all(&bytes)?;
+
+        // Perform rest of downloading.
+        while let Some(chunk) = byte_stream.next().await {
+            let bytes = chunk?;
+            file.write_all(&bytes)?;
+        }
+
+        Ok(())
+    }
+    .await;
+
+    if let Err(e) = written {
+        std::fs::remove_file(&part).ok();
+        return Err(e);
+    }
+
+    std::fs::rename(&part, &target)?;
+
+    download.extension = Some(ty.extension());
+    Ok(download)
+}
+
+/// A GBIF dataset in which occurrences are published.
+#[ergol]
+#[derive(Serialize)]
+pub struct Dataset {
+    /// Id of the row in the database.
+    #[id]
+    pub id: i32,
+
+    /// UUID of the dataset in GBIF.
+    #[unique]
+    pub key: Uuid,
+
+    /// The title of the dataset, such as `iNaturalist Research-grade Observations`.
+    pub title: String,
+
+    /// The name of the organization that publishes the dataset.
+    pub publisher: Option<String>,
+}
+
+impl Dataset {
+    /// Fetches the metadata of a dataset from GBIF, and inserts or updates it in the database.
+    pub async fn fetch<Q: Queryable<impl GenericClient>>(key: Uuid, db: &Q) -> Result<Dataset> {
+        let dataset = fetch_dataset(key).await?;
+
+        // The dataset is still worth saving without its publisher.
+        let publisher = match dataset.publishing_organization_key {
+            Some(organization) => match fetch_organization(organization).await {
+                Ok(organization) => Some(organization.title),
+                Err(e) => {
+                    warn!("failed to fetch organization {}: {}", organization, e);
+                    None
+                }
+            },
+            None => None,
+        };
+
+        match Dataset::get_by_key(key, db).await? {
+            Some(mut db_dataset) => {
+                db_dataset.title = dataset.title;
+                db_dataset.publisher = publisher;
+                db_dataset.save(db).await?;
+                Ok(db_dataset)
+            }
+
+            None => Ok(Dataset::create(key, dataset.title, publisher)
+                .save(db)
+                .await?),
         }
     }
 }
 
+/// A feedback given by a visitor on the crop of a media.
+///
+/// Feedbacks flagging bad crops are kept so that those medias can be cropped again later.
+#[ergol]
+#[derive(Serialize)]
+pub struct MediaFeedback {
+    /// Id of the row in the database.
+    #[id]
+    pub id: i32,
+
+    /// The media the feedback is about.
+    #[many_to_one(feedbacks)]
+    #[serde(skip)]
+    pub media: Media,
+
+    /// Whether the visitor found the crop correct.
+    pub crop_ok: bool,
+
+    /// A free comment of the visitor.
+    pub note: Option<String>,
+
+    /// When the feedback was given.
+    pub created_at: DateTime<Utc>,
+
+    /// The address of the visitor who gave the feedback.
+    pub ip: String,
+}
+
 /// This table stores a cache for every value for every level of taxonomy.
 ///
 /// For example, if we want to know the number of medias with failed crop for a level that is not a
@@ -621,7 +2301,7 @@ pub struct SpeciesMetadata {
     /// Family of the species.
     pub family: Option<String>,
 
-    /// Genus of the species (sub-family).
+    /// Genus of the species.
     pub genus: Option<String>,
 
     /// Valid name of the specie.
@@ -0,0 +1,263 @@
+//! This module helps us read the Darwin Core Archives produced by GBIF downloads.
+//!
+//! An archive is a zip file holding tab separated tables, such as `occurrence.txt` and `multimedia.txt`. Only what we
+//! need from zip is implemented here: finding an entry and extracting it, stored or deflated, with zip64 support since
+//! the archives of big downloads easily exceed 4GiB.
+
+use std::io::{self, BufRead, Lines, Read, Seek, SeekFrom, Write};
+
+use flate2::read::DeflateDecoder;
+
+use crate::{Error, Result};
+
+/// The signature of the end of central directory record.
+const EOCD_SIGNATURE: u32 = 0x06054b50;
+
+/// The signature of the zip64 end of central directory locator.
+const ZIP64_LOCATOR_SIGNATURE: u32 = 0x07064b50;
+
+/// The signature of the zip64 end of central directory record.
+const ZIP64_EOCD_SIGNATURE: u32 = 0x06064b50;
+
+/// The signature of a central directory file header.
+const CENTRAL_HEADER_SIGNATURE: u32 = 0x02014b50;
+
+/// The signature of a local file header.
+const LOCAL_HEADER_SIGNATURE: u32 = 0x04034b50;
+
+/// The size of the end of central directory record, without its comment.
+const EOCD_SIZE: u64 = 22;
+
+/// The value of a 32 bits field whose real value is in the zip64 extra field.
+const ZIP64_MARKER: u32 = 0xffffffff;
+
+/// An entry of the central directory of a zip archive.
+#[derive(Debug, Clone)]
+struct ZipEntry {
+    /// The path of the file in the archive.
+    name: String,
+
+    /// The compression method, 0 for stored and 8 for deflated.
+    method: u16,
+
+    /// The size of the compressed data.
+    compressed_size: u64,
+
+    /// The offset of the local header of the file from the start of the archive.
+    offset: u64,
+}
+
+/// Returns an error about an invalid archive.
+fn invalid(reason: &str) -> Error {
+    Error::InvalidArchive(reason.to_owned())
+}
+
+/// Reads a little endian integer of `size` bytes from a buffer, at some offset.
+fn le(buffer: &[u8], offset: usize, size: usize) -> Result<u64> {
+    let bytes = buffer
+        .get(offset..offset + size)
+        .ok_or_else(|| invalid("truncated record"))?;
+
+    Ok(bytes
+        .iter()
+        .rev()
+        .fold(0, |acc, byte| (acc << 8) | *byte as u64))
+}
+
+/// Reads exactly `size` bytes at an offset of the archive.
+fn read_at<R: Read + Seek>(reader: &mut R, offset: u64, size: usize) -> Result<Vec<u8>> {
+    let mut buffer = vec![0; size];
+    reader.seek(SeekFrom::Start(offset))?;
+    reader.read_exact(&mut buffer)?;
+    Ok(buffer)
+}
+
+/// Returns the offset and the number of entries of the central directory of an archive.
+fn central_directory<R: Read + Seek>(reader: &mut R) -> Result<(u64, u64)> {
+    let len = reader.seek(SeekFrom::End(0))?;
+
+    // The record is at the end of the archive, followed by a comment of at most 64KiB.
+    let tail_len = len.min(EOCD_SIZE + u16::MAX as u64);
+    let tail = read_at(reader, len - tail_len, tail_len as usize)?;
+
+    let eocd = (0..tail.len().saturating_sub(EOCD_SIZE as usize - 1))
+        .rev()
+        .find(|&i| le(&tail, i, 4).ok() == Some(EOCD_SIGNATURE as u64))
+        .ok_or_else(|| invalid("end of central directory not found"))?;
+
+    let count = le(&tail, eocd + 10, 2)?;
+    let offset = le(&tail, eocd + 16, 4)?;
+
+    if count != u16::MAX as u64 && offset != ZIP64_MARKER as u64 {
+        return Ok((offset, count));
+    }
+
+    // The real values are in the zip64 record, found thanks to the locator just before the record we found.
+    let locator_offset = (len - tail_len + eocd as u64)
+        .checked_sub(20)
+        .ok_or_else(|| invalid("zip64 locator not found"))?;
+    let locator = read_at(reader, locator_offset, 20)?;
+
+    if le(&locator, 0, 4)? != ZIP64_LOCATOR_SIGNATURE as u64 {
+        return Err(invalid("zip64 locator not found"));
+    }
+
+    let record = read_at(reader, le(&locator, 8, 8)?, 56)?;
+
+    if le(&record, 0, 4)? != ZIP64_EOCD_SIGNATURE as u64 {
+        return Err(invalid("zip64 end of central directory not found"));
+    }
+
+    Ok((le(&record, 48, 8)?, le(&record, 32, 8)?))
+}
+
+/// Lists the entries of an archive.
+fn entries<R: Read + Seek>(reader: &mut R) -> Result<Vec<ZipEntry>> {
+    let (offset, count) = central_directory(reader)?;
+    reader.seek(SeekFrom::Start(offset))?;
+
+    let mut entries = vec![];
+
+    for _ in 0..count {
+        let mut header = [0; 46];
+        reader.read_exact(&mut header)?;
+
+        if le(&header, 0, 4)? != CENTRAL_HEADER_SIGNATURE as u64 {
+            return Err(invalid("bad central directory header"));
+        }
+
+        let name_len = le(&header, 28, 2)? as usize;
+        let extra_len = le(&header, 30, 2)? as usize;
+        let comment_len = le(&header, 32, 2)? as usize;
+
+        let mut name = vec![0; name_len];
+        reader.read_exact(&mut name)?;
+        let mut extra = vec![0; extra_len];
+        reader.read_exact(&mut extra)?;
+        reader.seek(SeekFrom::Current(comment_len as i64))?;
+
+        let mut uncompressed_size = le(&header, 24, 4)?;
+        let mut compressed_size = le(&header, 20, 4)?;
+        let mut local_offset = le(&header, 42, 4)?;
+
+        // The zip64 extra field only holds the values that didn't fit, in this order.
+        let mut i = 0;
+        while i + 4 <= extra.len() {
+            let id = le(&extra, i, 2)?;
+            let size = le(&extra, i + 2, 2)? as usize;
+
+            if id == 1 {
+                let mut field = i + 4;
+                for value in [
+                    &mut uncompressed_size,
+                    &mut compressed_size,
+                    &mut local_offset,
+                ] {
+                    if *value == ZIP64_MARKER as u64 {
+                        *value = le(&extra, field, 8)?;
+                        field += 8;
+                    }
+                }
+            }
+
+            i += 4 + size;
+        }
+
+        entries.push(ZipEntry {
+            name: String::from_utf8_lossy(&name).into_owned(),
+            method: le(&header, 10, 2)? as u16,
+            compressed_size,
+            offset: local_offset,
+        });
+    }
+
+    Ok(entries)
+}
+
+/// Extracts a file of a zip archive into a writer, and returns false if the archive has no such file.
+pub fn extract<R: Read + Seek, W: Write>(
+    reader: &mut R,
+    name: &str,
+    writer: &mut W,
+) -> Result<bool> {
+    let entry = match entries(reader)?.into_iter().find(|x| x.name == name) {
+        Some(entry) => entry,
+        None => return Ok(false),
+    };
+
+    let header = read_at(reader, entry.offset, 30)?;
+
+    if le(&header, 0, 4)? != LOCAL_HEADER_SIGNATURE as u64 {
+        return Err(invalid("bad local file header"));
+    }
+
+    // The lengths of the local header may differ from the ones of the central directory.
+    let skip = le(&header, 26, 2)? + le(&header, 28, 2)?;
+    reader.seek(SeekFrom::Current(skip as i64))?;
+
+    let mut data = reader.take(entry.compressed_size);
+
+    match entry.method {
+        0 => io::copy(&mut data, writer)?,
+        8 => io::copy(&mut DeflateDecoder::new(data), writer)?,
+        _ => return Err(invalid("unsupported compression method")),
+    };
+
+    Ok(true)
+}
+
+/// A tab separated table of an archive, whose first line holds the names of the columns.
+///
+/// Each row gives the values of the requested columns, in the order in which they were requested.
+pub struct Table<R> {
+    /// The remaining lines of the table.
+    lines: Lines<R>,
+
+    /// The index of each requested column in the rows.
+    indices: Vec<usize>,
+}
+
+impl<R: BufRead> Table<R> {
+    /// Reads the header of a table, and fails if one of the requested columns is missing.
+    pub fn new(reader: R, columns: &[&str]) -> Result<Table<R>> {
+        let mut lines = reader.lines();
+
+        let header = match lines.next() {
+            Some(header) => header?,
+            None => return Err(invalid("empty table")),
+        };
+        let header = header.split('\t').collect::<Vec<_>>();
+
+        let indices = columns
+            .iter()
+            .map(|column| {
+                header
+                    .iter()
+                    .position(|x| x == column)
+                    .ok_or_else(|| invalid(&format!("missing column {}", column)))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(Table { lines, indices })
+    }
+}
+
+impl<R: BufRead> Iterator for Table<R> {
+    type Item = Result<Vec<String>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let line = match self.lines.next()? {
+            Ok(line) => line,
+            Err(e) => return Some(Err(e.into())),
+        };
+
+        // Missing trailing values are empty.
+        let values = line.split('\t').collect::<Vec<_>>();
+
+        Some(Ok(self
+            .indices
+            .iter()
+            .map(|&i| values.get(i).unwrap_or(&"").to_string())
+            .collect()))
+    }
+}
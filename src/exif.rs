@@ -0,0 +1,264 @@
+//! This module helps us read the EXIF metadata of the downloaded medias.
+//!
+//! EXIF metadata is a small TIFF structure embedded in JPEG and TIFF files. Only the few tags we store are read: the
+//! date at which the photo was taken, the camera, and the GPS position.
+
+use std::path::Path;
+
+use chrono::NaiveDateTime;
+
+use image::{ImageDecoder, ImageFormat, ImageReader};
+
+/// The tag of the camera make, in IFD0.
+const TAG_MAKE: u16 = 0x010f;
+
+/// The tag of the camera model, in IFD0.
+const TAG_MODEL: u16 = 0x0110;
+
+/// The tag of the offset of the EXIF IFD, in IFD0.
+const TAG_EXIF_IFD: u16 = 0x8769;
+
+/// The tag of the offset of the GPS IFD, in IFD0.
+const TAG_GPS_IFD: u16 = 0x8825;
+
+/// The tag of the date at which the photo was taken, in the EXIF IFD.
+const TAG_DATE_TIME_ORIGINAL: u16 = 0x9003;
+
+/// The tag of the hemisphere of the latitude (`N` or `S`), in the GPS IFD.
+const TAG_GPS_LATITUDE_REF: u16 = 1;
+
+/// The tag of the latitude as degrees, minutes and seconds, in the GPS IFD.
+const TAG_GPS_LATITUDE: u16 = 2;
+
+/// The tag of the hemisphere of the longitude (`E` or `W`), in the GPS IFD.
+const TAG_GPS_LONGITUDE_REF: u16 = 3;
+
+/// The tag of the longitude as degrees, minutes and seconds, in the GPS IFD.
+const TAG_GPS_LONGITUDE: u16 = 4;
+
+/// The type of a field holding an ASCII string.
+const TYPE_ASCII: u16 = 2;
+
+/// The type of a field holding 16 bits integers.
+const TYPE_SHORT: u16 = 3;
+
+/// The type of a field holding 32 bits integers.
+const TYPE_LONG: u16 = 4;
+
+/// The type of a field holding fractions of two 32 bits integers.
+const TYPE_RATIONAL: u16 = 5;
+
+/// The metadata we keep from the EXIF of a media.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Exif {
+    /// When the photo was taken, in the local time of the camera.
+    pub captured_at: Option<NaiveDateTime>,
+
+    /// The maker of the camera.
+    pub camera_make: Option<String>,
+
+    /// The model of the camera.
+    pub camera_model: Option<String>,
+
+    /// The latitude where the photo was taken, in degrees, negative in the southern hemisphere.
+    pub latitude: Option<f64>,
+
+    /// The longitude where the photo was taken, in degrees, negative in the western hemisphere.
+    pub longitude: Option<f64>,
+}
+
+/// A field of an IFD.
+struct Field {
+    /// The type of the values of the field.
+    ty: u16,
+
+    /// The number of values of the field.
+    count: u32,
+
+    /// The offset of the values of the field, which are inline when they fit in 4 bytes.
+    offset: usize,
+}
+
+/// A TIFF structure.
+struct Tiff<'a> {
+    /// The bytes of the structure, starting with its header.
+    data: &'a [u8],
+
+    /// Whether the integers are big endian.
+    big_endian: bool,
+}
+
+impl<'a> Tiff<'a> {
+    /// Reads an integer of `N` bytes at some offset.
+    fn int<const N: usize>(&self, offset: usize) -> Option<u32> {
+        let bytes = self.data.get(offset..offset.checked_add(N)?)?;
+        let fold = |acc: u32, byte: &u8| (acc << 8) | *byte as u32;
+
+        Some(if self.big_endian {
+            bytes.iter().fold(0, fold)
+        } else {
+            bytes.iter().rev().fold(0, fold)
+        })
+    }
+
+    /// Reads the fields of the IFD at some offset.
+    fn fields(&self, offset: usize) -> Option<Vec<(u16, Field)>> {
+        let count = self.int::<2>(offset)? as usize;
+
+        (0..count)
+            .map(|i| {
+                let entry = offset + 2 + 12 * i;
+                let tag = self.int::<2>(entry)? as u16;
+                let ty = self.int::<2>(entry + 2)? as u16;
+                let count = self.int::<4>(entry + 4)?;
+
+                let size = match ty {
+                    TYPE_SHORT => 2,
+                    TYPE_LONG => 4,
+                    TYPE_RATIONAL => 8,
+                    _ => 1,
+                } * count as usize;
+
+                let offset = if size <= 4 {
+                    entry + 8
+                } else {
+                    self.int::<4>(entry + 8)? as usize
+                };
+
+                Some((tag, Field { ty, count, offset }))
+            })
+            .collect()
+    }
+
+    /// Reads a field holding a string.
+    fn ascii(&self, field: &Field) -> Option<String> {
+        if field.ty != TYPE_ASCII {
+            return None;
+        }
+
+        let bytes = self
+            .data
+            .get(field.offset..field.offset.checked_add(field.count as usize)?)?;
+        let text = String::from_utf8_lossy(bytes);
+        let text = text.trim_end_matches('\0').trim();
+
+        (!text.is_empty()).then(|| text.to_owned())
+    }
+
+    /// Reads a field holding an offset.
+    fn long(&self, field: &Field) -> Option<usize> {
+        match field.ty {
+            TYPE_LONG => self.int::<4>(field.offset).map(|x| x as usize),
+            TYPE_SHORT => self.int::<2>(field.offset).map(|x| x as usize),
+            _ => None,
+        }
+    }
+
+    /// Reads a field holding degrees, minutes and seconds, as degrees.
+    fn degrees(&self, field: &Field) -> Option<f64> {
+        if field.ty != TYPE_RATIONAL || field.count != 3 {
+            return None;
+        }
+
+        let mut degrees = 0.0;
+        for (i, unit) in [1.0, 60.0, 3600.0].into_iter().enumerate() {
+            let numerator = self.int::<4>(field.offset + 8 * i)? as f64;
+            let denominator = self.int::<4>(field.offset + 8 * i + 4)? as f64;
+
+            if denominator == 0.0 {
+                return None;
+            }
+
+            degrees += numerator / denominator / unit;
+        }
+
+        Some(degrees)
+    }
+
+    /// Reads a coordinate from the fields of its value and of its hemisphere.
+    fn coordinate(
+        &self,
+        fields: &[(u16, Field)],
+        value: u16,
+        hemisphere: u16,
+        negative: &str,
+    ) -> Option<f64> {
+        let degrees = self.degrees(find(fields, value)?)?;
+        let hemisphere = self.ascii(find(fields, hemisphere)?)?;
+
+        Some(if hemisphere == negative {
+            -degrees
+        } else {
+            degrees
+        })
+    }
+}
+
+/// Finds a field from its tag.
+fn find(fields: &[(u16, Field)], tag: u16) -> Option<&Field> {
+    fields
+        .iter()
+        .find(|(x, _)| *x == tag)
+        .map(|(_, field)| field)
+}
+
+/// Parses a raw EXIF chunk, starting with its TIFF header.
+///
+/// Returns none if the chunk is not a valid TIFF structure, and leaves out the tags that are missing or invalid.
+pub fn parse(chunk: &[u8]) -> Option<Exif> {
+    let big_endian = match chunk.get(0..4)? {
+        [0x49, 0x49, 42, 0] => false,
+        [0x4d, 0x4d, 0, 42] => true,
+        _ => return None,
+    };
+
+    let tiff = Tiff {
+        data: chunk,
+        big_endian,
+    };
+
+    let ifd0 = tiff.fields(tiff.int::<4>(4)? as usize)?;
+
+    let mut exif = Exif {
+        camera_make: find(&ifd0, TAG_MAKE).and_then(|x| tiff.ascii(x)),
+        camera_model: find(&ifd0, TAG_MODEL).and_then(|x| tiff.ascii(x)),
+        ..Exif::default()
+    };
+
+    let exif_ifd = find(&ifd0, TAG_EXIF_IFD)
+        .and_then(|x| tiff.long(x))
+        .and_then(|x| tiff.fields(x));
+
+    if let Some(exif_ifd) = exif_ifd {
+        exif.captured_at = find(&exif_ifd, TAG_DATE_TIME_ORIGINAL)
+            .and_then(|x| tiff.ascii(x))
+            .and_then(|x| NaiveDateTime::parse_from_str(&x, "%Y:%m:%d %H:%M:%S").ok());
+    }
+
+    let gps_ifd = find(&ifd0, TAG_GPS_IFD)
+        .and_then(|x| tiff.long(x))
+        .and_then(|x| tiff.fields(x));
+
+    if let Some(gps_ifd) = gps_ifd {
+        exif.latitude = tiff.coordinate(&gps_ifd, TAG_GPS_LATITUDE, TAG_GPS_LATITUDE_REF, "S");
+        exif.longitude = tiff.coordinate(&gps_ifd, TAG_GPS_LONGITUDE, TAG_GPS_LONGITUDE_REF, "W");
+    }
+
+    Some(exif)
+}
+
+/// Reads the EXIF metadata of a media.
+///
+/// Only JPEG and TIFF files are read, since other formats rarely have EXIF metadata. Returns none if the file is of
+/// another format, can't be read, or has no EXIF metadata.
+pub fn read(path: &Path) -> Option<Exif> {
+    let reader = ImageReader::open(path).ok()?.with_guessed_format().ok()?;
+
+    match reader.format() {
+        Some(ImageFormat::Jpeg) | Some(ImageFormat::Tiff) => (),
+        _ => return None,
+    }
+
+    let chunk = reader.into_decoder().ok()?.exif_metadata().ok()??;
+    parse(&chunk)
+}
@@ -1,18 +1,24 @@
 //! This module contains all the functions that help us use the GBIF API.
 
+use std::sync::RwLock;
 use std::time::Duration;
 
 use serde::{Deserialize, Serialize};
 
-use serde_json::Value;
+use serde_json::{Value, json};
 
-use uuid::{uuid, Uuid};
-
-use unidecode::unidecode;
+use reqwest::Client;
 
 use tokio::time::sleep;
 
-use crate::Result;
+use uuid::{Uuid, uuid};
+
+use unidecode::unidecode;
+
+use crate::config::GbifDownloadConfig;
+use crate::taxref::Entry;
+use crate::utils::{HYBRID_SIGN, RATE_LIMITER, RetryPolicy, is_transient, normalize_hybrid, retry};
+use crate::{Error, Result};
 
 /// GBIF Backbone dataset where we will search for species.
 pub const BACKBONE_DATASET_UUID: Uuid = uuid!("d7dddbf4-2cf0-4f39-9b2a-bb099caae36c");
@@ -20,6 +26,18 @@ pub const BACKBONE_DATASET_UUID: Uuid = uuid!("d7dddbf4-2cf0-4f39-9b2a-bb099caae
 /// Root of the GBIF API server.
 pub const GBIF_ROOT: &'static str = "https://api.gbif.org/v1";
 
+/// Root of the GBIF API server set by [`set_gbif_root`], if any.
+static ROOT: RwLock<Option<String>> = RwLock::new(None);
+
+/// The client used for the requests to the GBIF API, built by [`set_gbif_timeouts`] if it was called.
+static CLIENT: RwLock<Option<Client>> = RwLock::new(None);
+
+/// How requests to the GBIF API are retried.
+const GBIF_RETRY: RetryPolicy = RetryPolicy::new(4, Duration::from_secs(5));
+
+/// The time waited between two checks of the status of an asynchronous download.
+const DOWNLOAD_POLL_INTERVAL: Duration = Duration::from_secs(60);
+
 /// Maximum number of occurrences that can be scraped.
 pub const MAX_LIMIT_OCCURRENCES: usize = 300;
 
@@ -40,10 +58,16 @@ pub struct SpeciesOptionalResponse {
 /// A single result of a GBIF search query.
 #[derive(Debug, Serialize, Deserialize)]
 pub struct SpeciesResult {
+    /// The key of the taxon itself, which differs from the species key for infraspecific taxa.
+    pub key: i64,
+
     /// The species key of the species.
     #[serde(rename = "speciesKey")]
     pub species_key: i64,
 
+    /// The rank of the taxon (e.g. `SPECIES`, `SUBSPECIES`, `VARIETY`).
+    pub rank: Option<String>,
+
     /// The scientific name of the species (both name and author).
     #[serde(rename = "scientificName")]
     pub scientific_name: String,
@@ -52,21 +76,49 @@ pub struct SpeciesResult {
 /// A single result of a GBIF search query.
 #[derive(Debug, Serialize, Deserialize)]
 pub struct SpeciesOptionalResult {
+    /// The key of the taxon itself, which differs from the species key for infraspecific taxa.
+    pub key: i64,
+
     /// The species key of the species.
     #[serde(rename = "speciesKey")]
     pub species_key: Option<i64>,
 
+    /// The rank of the taxon (e.g. `SPECIES`, `SUBSPECIES`, `VARIETY`).
+    pub rank: Option<String>,
+
     /// The scientific name of the species (both name and author).
     #[serde(rename = "scientificName")]
     pub scientific_name: String,
 }
 
+impl SpeciesResult {
+    /// Returns true if the result is below the species rank (subspecies, variety, form...).
+    pub fn is_infraspecific(&self) -> bool {
+        match self.rank.as_deref() {
+            Some(rank) => rank != "SPECIES" && self.key != self.species_key,
+            None => false,
+        }
+    }
+
+    /// Returns the key that identifies this exact taxon: the species key for species, the taxon key for
+    /// infraspecific taxa.
+    pub fn taxon_key(&self) -> i64 {
+        if self.is_infraspecific() {
+            self.key
+        } else {
+            self.species_key
+        }
+    }
+}
+
 impl SpeciesOptionalResult {
     /// Converts SpeciesOptionalResult to Option<SpeciesResult>.
     pub fn into_option(self) -> Option<SpeciesResult> {
         if let Some(species_key) = self.species_key {
             Some(SpeciesResult {
+                key: self.key,
                 species_key,
+                rank: self.rank,
                 scientific_name: self.scientific_name,
             })
         } else {
@@ -75,14 +127,56 @@ impl SpeciesOptionalResult {
     }
 }
 
+/// Sets the root of the GBIF API server used by every request, such as a mirror or a mock server in tests.
+pub fn set_gbif_root(root: &str) {
+    *ROOT.write().unwrap() = Some(root.trim_end_matches('/').to_owned());
+}
+
+/// Sets the timeouts of the requests to the GBIF API: connecting must take less than `connect_timeout`, and the whole
+/// request, body included, less than `timeout`.
+pub fn set_gbif_timeouts(connect_timeout: Duration, timeout: Duration) {
+    let client = Client::builder()
+        .connect_timeout(connect_timeout)
+        .timeout(timeout)
+        .build();
+
+    match client {
+        Ok(client) => *CLIENT.write().unwrap() = Some(client),
+        Err(e) => warn!(
+            "failed to build the GBIF client, keeping the default one: {}",
+            e
+        ),
+    }
+}
+
+/// Returns the client used for the requests to the GBIF API.
+fn gbif_client() -> Client {
+    CLIENT.read().unwrap().clone().unwrap_or_default()
+}
+
 /// Easily create a gbif api url.
 pub fn gbif_url(suffix: &str) -> String {
-    format!("{}{}", GBIF_ROOT, suffix)
+    match ROOT.read().unwrap().as_deref() {
+        Some(root) => format!("{}{}", root, suffix),
+        None => format!("{}{}", GBIF_ROOT, suffix),
+    }
 }
 
 /// Preprocesses a string for better query.
+///
+/// Hybrid signs are kept as `×`, as in GBIF names, instead of being transliterated to an `x`.
 pub fn preprocess(input: &str) -> String {
-    unidecode(input)
+    normalize_hybrid(input)
+        .split(' ')
+        .map(|x| {
+            if x == HYBRID_SIGN {
+                x.to_owned()
+            } else {
+                unidecode(x)
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
         .replace("(", "")
         .replace(")", "")
         .replace(",", "")
@@ -91,13 +185,11 @@ pub fn preprocess(input: &str) -> String {
 
 /// Searches a name of a species on GBIF and returns it.
 pub async fn search_species(species: &str) -> Result<SpeciesResponse> {
-    let response = reqwest::get(gbif_url(&format!(
+    let (_, response) = get(&gbif_url(&format!(
         "/species/search?q={}&limit=300&datasetKey={}",
         preprocess(species),
         BACKBONE_DATASET_UUID,
     )))
-    .await?
-    .text()
     .await?;
 
     let response: SpeciesOptionalResponse = serde_json::from_str(&response)?;
@@ -132,6 +224,18 @@ pub struct OccurrencesResult {
     #[serde(rename = "datasetKey")]
     pub dataset_key: Uuid,
 
+    /// The ISO 3166-1 alpha-2 code of the country where the occurrence was observed, if known.
+    #[serde(rename = "countryCode", default)]
+    pub country: Option<String>,
+
+    /// The latitude where the occurrence was observed, in degrees, if known.
+    #[serde(rename = "decimalLatitude", default)]
+    pub latitude: Option<f64>,
+
+    /// The longitude where the occurrence was observed, in degrees, if known.
+    #[serde(rename = "decimalLongitude", default)]
+    pub longitude: Option<f64>,
+
     /// Medias that come with this occurrence.
     #[serde(rename = "media")]
     pub medias: Vec<Media>,
@@ -145,42 +249,399 @@ pub struct Media {
     pub url: Option<String>,
 }
 
+/// Filters on the quality of the occurrences that are scraped, applied by GBIF.
+///
+/// By default, nothing is filtered out.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct OccurrenceFilters {
+    /// If set, only keep occurrences that have coordinates (or that don't).
+    pub has_coordinate: Option<bool>,
+
+    /// If set, only keep occurrences that have geospatial issues (or that don't).
+    pub has_geospatial_issue: Option<bool>,
+
+    /// If not empty, only keep occurrences with one of these bases of record, such as `HUMAN_OBSERVATION`.
+    pub basis_of_record: Vec<String>,
+
+    /// If set, only keep occurrences observed in this country, as an ISO 3166-1 alpha-2 code such as `FR`.
+    ///
+    /// This is about where each observation was made, unlike the `include_non_france` filter of taxref which keeps or
+    /// drops whole species depending on whether they live in France.
+    pub country: Option<String>,
+}
+
+impl OccurrenceFilters {
+    /// Returns true if a country code looks like an ISO 3166-1 alpha-2 code, such as `FR`.
+    pub fn is_valid_country(country: &str) -> bool {
+        country.len() == 2 && country.chars().all(|c| c.is_ascii_alphabetic())
+    }
+
+    /// Returns the parameters to append to an occurrence search query, each one starting with `&`.
+    pub fn query_params(&self) -> String {
+        let mut params = String::new();
+
+        if let Some(has_coordinate) = self.has_coordinate {
+            params.push_str(&format!("&hasCoordinate={}", has_coordinate));
+        }
+
+        if let Some(has_geospatial_issue) = self.has_geospatial_issue {
+            params.push_str(&format!("&hasGeospatialIssue={}", has_geospatial_issue));
+        }
+
+        for basis in &self.basis_of_record {
+            params.push_str(&format!("&basisOfRecord={}", basis.trim().to_uppercase()));
+        }
+
+        if let Some(country) = &self.country {
+            params.push_str(&format!("&country={}", country.trim().to_uppercase()));
+        }
+
+        params
+    }
+}
+
+impl OccurrenceFilters {
+    /// Returns the predicates of an occurrence download that match the filters.
+    pub fn predicates(&self) -> Vec<Value> {
+        let mut predicates = vec![];
+
+        if let Some(has_coordinate) = self.has_coordinate {
+            predicates.push(json!({
+                "type": "equals",
+                "key": "HAS_COORDINATE",
+                "value": has_coordinate.to_string(),
+            }));
+        }
+
+        if let Some(has_geospatial_issue) = self.has_geospatial_issue {
+            predicates.push(json!({
+                "type": "equals",
+                "key": "HAS_GEOSPATIAL_ISSUE",
+                "value": has_geospatial_issue.to_string(),
+            }));
+        }
+
+        if !self.basis_of_record.is_empty() {
+            predicates.push(json!({
+                "type": "in",
+                "key": "BASIS_OF_RECORD",
+                "values": self
+                    .basis_of_record
+                    .iter()
+                    .map(|x| x.trim().to_uppercase())
+                    .collect::<Vec<_>>(),
+            }));
+        }
+
+        if let Some(country) = &self.country {
+            predicates.push(json!({
+                "type": "equals",
+                "key": "COUNTRY",
+                "value": country.trim().to_uppercase(),
+            }));
+        }
+
+        predicates
+    }
+}
+
 /// Search occurrences for a species.
-pub async fn search_occurrences(species_key: i64, offset: usize, limit: usize) -> Result<Value> {
-    let mut retries = 3;
+///
+/// The key is used as a taxon key, so that it works both for species keys and for infraspecific taxa keys.
+pub async fn search_occurrences(
+    species_key: i64,
+    offset: usize,
+    limit: usize,
+    filters: &OccurrenceFilters,
+) -> Result<Value> {
+    trace!(
+        "looking up occurrences for {} (offset={}, limit={})",
+        species_key, offset, limit,
+    );
+
+    let url = gbif_url(&format!(
+        "/occurrence/search?taxonKey={}&offset={}&limit={}&mediaType=stillImage{}",
+        species_key,
+        offset,
+        limit,
+        filters.query_params(),
+    ));
+
+    // Ideally we should return an error on failure status codes, but here, the from_str will fail, so it's not that
+    // big a deal.
+    let (_, text) = get(&url).await?;
+    Ok(serde_json::from_str(&text)?)
+}
 
+/// Requests an asynchronous download of the occurrences with medias of a taxon, and returns the key of the download.
+///
+/// GBIF prepares the archive in the background, which can take from minutes to hours: [`wait_download`] tells when it
+/// is ready. Downloads require a GBIF account.
+pub async fn request_download(
+    taxon_key: i64,
+    filters: &OccurrenceFilters,
+    config: &GbifDownloadConfig,
+) -> Result<String> {
+    let (user, password) = match (&config.user, &config.password) {
+        (Some(user), Some(password)) => (user, password),
+        _ => return Err(Error::MissingGbifCredentials),
+    };
+
+    let mut predicates = vec![
+        json!({ "type": "equals", "key": "TAXON_KEY", "value": taxon_key.to_string() }),
+        json!({ "type": "equals", "key": "MEDIA_TYPE", "value": "StillImage" }),
+    ];
+    predicates.extend(filters.predicates());
+
+    let body = json!({
+        "creator": user,
+        "notificationAddresses": config.email.iter().collect::<Vec<_>>(),
+        "sendNotification": config.email.is_some(),
+        "format": "DWCA",
+        "predicate": { "type": "and", "predicates": predicates },
+    });
+
+    // Not retried: a request that failed after reaching GBIF could still have started a download.
+    RATE_LIMITER.acquire().await;
+    let key = gbif_client()
+        .post(gbif_url("/occurrence/download/request"))
+        .basic_auth(user, Some(password))
+        .json(&body)
+        .send()
+        .await?
+        .error_for_status()?
+        .text()
+        .await?;
+
+    Ok(key.trim().to_owned())
+}
+
+/// The status of an asynchronous download.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DownloadStatus {
+    /// The key of the download.
+    pub key: String,
+
+    /// The status of the download, such as `PREPARING`, `RUNNING` or `SUCCEEDED`.
+    pub status: String,
+
+    /// The link to the archive, once the download succeeded.
+    #[serde(rename = "downloadLink")]
+    pub download_link: Option<String>,
+}
+
+/// Fetches the status of an asynchronous download.
+pub async fn download_status(key: &str) -> Result<DownloadStatus> {
+    let (_, text) = get(&gbif_url(&format!("/occurrence/download/{}", key))).await?;
+    Ok(serde_json::from_str(&text)?)
+}
+
+/// Waits until an asynchronous download is ready, and returns the link to its archive.
+pub async fn wait_download(key: &str) -> Result<String> {
     loop {
-        trace!(
-            "looking up occurrences for {} (offset={}, limit={}, attempt={})",
-            species_key,
-            offset,
-            limit,
-            4 - retries
-        );
-
-        let url = gbif_url(&format!(
-            "/occurrence/search?speciesKey={}&offset={}&limit={}&mediaType=stillImage",
-            species_key, offset, limit,
-        ));
-
-        let response = reqwest::get(url).await?;
-
-        let code = response.status().as_u16();
-        let text = response.text().await?;
-
-        if 200 <= code && code < 400 {
-            return Ok(serde_json::from_str(&text)?);
+        let status = download_status(key).await?;
+
+        match status.status.as_str() {
+            "SUCCEEDED" => {
+                return status
+                    .download_link
+                    .ok_or_else(|| Error::DownloadFailed(format!("no link for download {}", key)));
+            }
+
+            "PREPARING" | "RUNNING" | "SUSPENDED" => {
+                debug!("download {} is {}", key, status.status.to_lowercase());
+                sleep(DOWNLOAD_POLL_INTERVAL).await;
+            }
+
+            other => {
+                return Err(Error::DownloadFailed(format!(
+                    "download {} is {}",
+                    key,
+                    other.to_lowercase()
+                )));
+            }
         }
+    }
+}
 
-        if code == 429 && retries > 0 {
-            retries -= 1;
-            trace!("Received 429 Too Many Requests from GBIF, waiting a little");
-            sleep(Duration::from_secs(5)).await;
-            continue;
-        } else {
-            // Ideally we should return an error, but here, the from_str will fail, so it's not
-            // that big a deal.
-            return Ok(serde_json::from_str(&text)?);
+/// The response of a GBIF vernacular names query.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct VernacularResponse {
+    /// The vernacular names of the species, in every language.
+    pub results: Vec<VernacularName>,
+}
+
+/// A vernacular name of a species.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct VernacularName {
+    /// The name itself.
+    #[serde(rename = "vernacularName")]
+    pub vernacular_name: String,
+
+    /// The ISO 639-2 code of the language of the name, such as `fra` or `eng`.
+    pub language: Option<String>,
+
+    /// Whether the source of the name marks it as the preferred one.
+    #[serde(default)]
+    pub preferred: bool,
+}
+
+/// Fetches the vernacular name of a species in a language, such as `fra` or `eng`.
+///
+/// The name marked as preferred is picked if there is one, otherwise the first name in the language.
+pub async fn fetch_vernacular(species_key: i64, language: &str) -> Result<Option<String>> {
+    let (_, text) = get(&gbif_url(&format!(
+        "/species/{}/vernacularNames?limit=1000",
+        species_key
+    )))
+    .await?;
+
+    let response: VernacularResponse = serde_json::from_str(&text)?;
+
+    let mut names = response
+        .results
+        .into_iter()
+        .filter(|x| x.language.as_deref() == Some(language))
+        .collect::<Vec<_>>();
+
+    // The sort is stable, so the first name is kept among the preferred ones.
+    names.sort_by_key(|x| !x.preferred);
+
+    Ok(names.into_iter().next().map(|x| x.vernacular_name))
+}
+
+/// The taxonomy of a species on GBIF, as returned by `/species/<key>`.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SpeciesDetails {
+    /// The key of the taxon.
+    pub key: i64,
+
+    /// The kingdom of the taxon.
+    pub kingdom: Option<String>,
+
+    /// The phylum of the taxon.
+    pub phylum: Option<String>,
+
+    /// The class of the taxon.
+    pub class: Option<String>,
+
+    /// The order of the taxon.
+    pub order: Option<String>,
+
+    /// The family of the taxon.
+    pub family: Option<String>,
+
+    /// The genus of the taxon.
+    pub genus: Option<String>,
+
+    /// The rank of the taxon (e.g. `SPECIES`, `SUBSPECIES`, `VARIETY`).
+    pub rank: Option<String>,
+
+    /// The name of the taxon, without its author.
+    #[serde(rename = "canonicalName")]
+    pub canonical_name: Option<String>,
+
+    /// The scientific name of the taxon (both name and author).
+    #[serde(rename = "scientificName")]
+    pub scientific_name: String,
+}
+
+impl SpeciesDetails {
+    /// Converts the taxonomy to a taxref entry, for the species that are scraped without taxref.
+    ///
+    /// The GBIF ranks are converted to their taxref codes, and the habitat and the presence in France are unknown.
+    pub fn into_entry(self) -> Entry {
+        let rank = match self.rank.as_deref() {
+            Some("SPECIES") => String::from("ES"),
+            Some("SUBSPECIES") => String::from("SSES"),
+            Some("VARIETY") => String::from("VAR"),
+            rank => rank.unwrap_or_default().to_owned(),
+        };
+
+        Entry {
+            reign: self.kingdom.unwrap_or_default(),
+            phylum: self.phylum.unwrap_or_default(),
+            class: self.class.unwrap_or_default(),
+            order: self.order.unwrap_or_default(),
+            family: self.family.unwrap_or_default(),
+            genus: self.genus.unwrap_or_default(),
+            rank,
+            name: self
+                .canonical_name
+                .unwrap_or_else(|| self.scientific_name.clone()),
+            full_name: self.scientific_name.clone(),
+            valid_name: self.scientific_name,
+            habitat: String::new(),
+            fr: String::new(),
         }
     }
 }
+
+/// Fetches the taxonomy of a species from its key, failing with [`Error::SpeciesNotFound`] if there is no such key.
+pub async fn fetch_species(species_key: i64) -> Result<SpeciesDetails> {
+    let (code, text) = get(&gbif_url(&format!("/species/{}", species_key))).await?;
+
+    if code == 404 {
+        return Err(Error::SpeciesNotFound(species_key.to_string()));
+    }
+
+    Ok(serde_json::from_str(&text)?)
+}
+
+/// The metadata of a GBIF dataset.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Dataset {
+    /// UUID of the dataset.
+    pub key: Uuid,
+
+    /// The title of the dataset.
+    pub title: String,
+
+    /// UUID of the organization that publishes the dataset.
+    #[serde(rename = "publishingOrganizationKey")]
+    pub publishing_organization_key: Option<Uuid>,
+}
+
+/// Fetches the metadata of a dataset.
+pub async fn fetch_dataset(key: Uuid) -> Result<Dataset> {
+    let (_, text) = get(&gbif_url(&format!("/dataset/{}", key))).await?;
+    Ok(serde_json::from_str(&text)?)
+}
+
+/// The metadata of an organization that publishes datasets on GBIF.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Organization {
+    /// UUID of the organization.
+    pub key: Uuid,
+
+    /// The name of the organization.
+    pub title: String,
+}
+
+/// Fetches the metadata of an organization.
+pub async fn fetch_organization(key: Uuid) -> Result<Organization> {
+    let (_, text) = get(&gbif_url(&format!("/organization/{}", key))).await?;
+    Ok(serde_json::from_str(&text)?)
+}
+
+/// Sends a GET request to the GBIF API, and returns the status code and the body of the response.
+///
+/// The request is retried when GBIF answers 429 Too Many Requests or a 5xx status code, or when the network fails.
+async fn get(url: &str) -> Result<(u16, String)> {
+    retry(
+        GBIF_RETRY,
+        |attempt| async move {
+            trace!("GET {} (attempt={})", url, attempt);
+            RATE_LIMITER.acquire().await;
+            let response = gbif_client().get(url).send().await?;
+            let code = response.status().as_u16();
+            Ok((code, response.text().await?))
+        },
+        |result| match result {
+            Ok((code, _)) => *code == 429 || *code >= 500,
+            Err(e) => is_transient(e),
+        },
+    )
+    .await
+}
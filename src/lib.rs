@@ -8,44 +8,71 @@ extern crate rocket;
 pub mod config;
 pub mod cropper;
 pub mod db;
+pub mod dwca;
+pub mod exif;
 pub mod gbif;
 pub mod logger;
+pub mod metrics;
+pub mod progress;
 pub mod server;
 pub mod taxref;
 pub mod utils;
 
-use std::env::args;
+use std::collections::HashSet;
+use std::env::{args, var};
 use std::fs::File;
+use std::io::IsTerminal;
+use std::path::PathBuf;
+use std::pin::pin;
 use std::process::exit;
 use std::result::Result as StdResult;
-use std::time::Duration;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 use std::{fmt, io};
 
-use chrono::prelude::*;
+use chrono::{DateTime, TimeDelta, Utc};
 
-use tokio::fs::create_dir_all;
-use tokio::sync::mpsc::unbounded_channel;
+use log::LevelFilter;
+
+use futures::StreamExt;
+
+use tokio::sync::mpsc::channel;
 use tokio::sync::{OnceCell, Semaphore};
-use tokio::task::JoinHandle;
+use tokio::task::{JoinHandle, spawn_blocking};
 
 use rocket::State;
 use rocket::http::Status;
 use rocket::request::{FromRequest, Outcome, Request};
 use rocket::response::{self, Responder};
+use rocket::serde::json::Json;
+
+use serde_json::json;
 
 use ergol::Pool;
 use ergol::deadpool::managed::Object;
 use ergol::prelude::*;
 use ergol::tokio_postgres::Error as TpError;
+use ergol::tokio_postgres::error::SqlState;
+
+use uuid::Uuid;
 
-use crate::config::{BLACKLISTED_DATASET, Config};
+use crate::config::Config;
 use crate::cropper::Cropper;
-use crate::db::{Media, Occurrence, Species, SpeciesMetadata};
-use crate::logger::Log;
-use crate::taxref::{Entry, Taxon};
+use crate::db::{Dataset, IgnoredSpecies, Media, Occurrence, Species, SpeciesMetadata};
+use crate::gbif::OccurrenceFilters;
+use crate::logger::{
+    Log, LogOptions, is_valid_timestamp_format, parse_env_filter, parse_module_levels,
+};
+use crate::metrics::ScrapSummary;
+use crate::taxref::{Entry, Taxon, TaxrefParseError};
+use crate::utils::{RATE_LIMITER, create_dir_all, parse_duration};
 
 static SEMAPHORE: OnceCell<Semaphore> = OnceCell::const_new();
 
+/// The number of occurrences under which every media of a species is downloaded, when none is specified.
+const DEFAULT_MIN_OCCURRENCES: usize = 30;
+
 /// The error type of this library.
 #[derive(Debug)]
 pub enum Error {
@@ -55,6 +82,9 @@ pub enum Error {
     /// An IO error occured.
     IoError(io::Error),
 
+    /// A file or directory couldn't be written where the data is stored.
+    NotWritable(PathBuf, io::Error),
+
     /// An error occurred with postgresql.
     PostgresError(TpError),
 
@@ -70,12 +100,27 @@ pub enum Error {
     /// Download failed.
     DownloadFailed(String),
 
+    /// A downloaded file doesn't match what we expected from it.
+    CorruptedDownload(String),
+
     /// We attempted to download a file which media type is not supported.
     UnknownMediaType(String),
 
     /// The species was not found on GBIF.
     SpeciesNotFound(String),
 
+    /// A column we need is missing from the header of taxref.
+    MissingTaxrefColumn(String),
+
+    /// A line of taxref couldn't be parsed.
+    TaxrefParseError(TaxrefParseError),
+
+    /// Downloading from GBIF requires credentials that are not in the config.
+    MissingGbifCredentials,
+
+    /// An archive downloaded from GBIF couldn't be read.
+    InvalidArchive(String),
+
     /// An error while rendering a template.
     TeraError(tera::Error),
 
@@ -94,15 +139,32 @@ impl fmt::Display for Error {
         match self {
             Error::ReqwestError(e) => write!(f, "{}", e),
             Error::IoError(e) => write!(f, "{}", e),
+            Error::NotWritable(path, e) => write!(
+                f,
+                "\"{}\" is not writable, check that it is not read-only and that we have permission to write it: {}",
+                path.display(),
+                e
+            ),
             Error::PostgresError(e) => write!(f, "{}", e),
             Error::JsonError(e) => write!(f, "{}", e),
             Error::NoCache => write!(f, "couldn't find cache directory"),
             Error::DbError => write!(f, "error with database"),
             Error::DownloadFailed(file) => write!(f, "failed to download file: {}", file),
+            Error::CorruptedDownload(reason) => {
+                write!(f, "downloaded file is corrupted: {}", reason)
+            }
             Error::UnknownMediaType(file) => write!(f, "unknown media type for file: {}", file),
             Error::SpeciesNotFound(species) => {
                 write!(f, "species \"{}\" was not found on GBIF", species)
             }
+            Error::MissingTaxrefColumn(column) => {
+                write!(f, "column \"{}\" is missing from taxref", column)
+            }
+            Error::TaxrefParseError(e) => write!(f, "{}", e),
+            Error::MissingGbifCredentials => {
+                write!(f, "GBIF credentials are missing from the config")
+            }
+            Error::InvalidArchive(reason) => write!(f, "invalid archive: {}", reason),
             Error::InitializeCropperFailed => write!(f, "error initializing cropper"),
             Error::RocketError(e) => write!(f, "error with rocket: {}", e),
             Error::InternalServerError => write!(f, "internal server error"),
@@ -111,10 +173,85 @@ impl fmt::Display for Error {
     }
 }
 
+/// The message of the error that made a request fail, stored in the request local cache so that the
+/// 500 catcher can log it.
+pub(crate) struct CaughtError(pub(crate) Option<String>);
+
+impl Error {
+    /// Returns the HTTP status of the responses to requests that failed with this error.
+    pub fn status(&self) -> Status {
+        match self {
+            Error::SpeciesNotFound(_) => Status::NotFound,
+            Error::ReqwestError(_)
+            | Error::DownloadFailed(_)
+            | Error::CorruptedDownload(_)
+            | Error::UnknownMediaType(_)
+            | Error::InvalidArchive(_) => Status::BadGateway,
+            _ => Status::InternalServerError,
+        }
+    }
+
+    /// Returns the name of the kind of the error, for clients to tell errors apart.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Error::ReqwestError(_) => "request",
+            Error::IoError(_) => "io",
+            Error::NotWritable(_, _) => "not_writable",
+            Error::PostgresError(_) => "postgres",
+            Error::JsonError(_) => "json",
+            Error::NoCache => "no_cache",
+            Error::DbError => "db",
+            Error::DownloadFailed(_) => "download_failed",
+            Error::CorruptedDownload(_) => "corrupted_download",
+            Error::UnknownMediaType(_) => "unknown_media_type",
+            Error::SpeciesNotFound(_) => "species_not_found",
+            Error::MissingTaxrefColumn(_) => "missing_taxref_column",
+            Error::TaxrefParseError(_) => "taxref_parse_error",
+            Error::MissingGbifCredentials => "missing_gbif_credentials",
+            Error::InvalidArchive(_) => "invalid_archive",
+            Error::TeraError(_) => "tera",
+            Error::InitializeCropperFailed => "initialize_cropper_failed",
+            Error::RocketError(_) => "rocket",
+            Error::InternalServerError => "internal_server_error",
+        }
+    }
+}
+
 impl<'r, 's: 'r> Responder<'r, 's> for Error {
     fn respond_to(self, request: &'r Request) -> response::Result<'s> {
-        error!("{}", self);
-        Status::InternalServerError.respond_to(request)
+        let status = self.status();
+
+        let json = request.uri().path().starts_with("/api/")
+            || request
+                .accept()
+                .map(|x| x.preferred().is_json())
+                .unwrap_or(false);
+
+        // The catchers render the error pages, and log the internal errors: other errors of the server get its page.
+        if !json {
+            request.local_cache(|| CaughtError(Some(self.to_string())));
+
+            return Err(if status == Status::NotFound {
+                status
+            } else {
+                Status::InternalServerError
+            });
+        }
+
+        // The details of internal errors are only logged, they could reveal how the server works.
+        let message = if status == Status::InternalServerError {
+            error!("{}", self);
+            String::from("internal server error")
+        } else {
+            self.to_string()
+        };
+
+        let body = json!({
+            "error": message,
+            "kind": self.kind(),
+        });
+
+        (status, Json(body)).respond_to(request)
     }
 }
 
@@ -151,6 +288,12 @@ impl From<rocket::Error> for Error {
     }
 }
 
+impl From<TaxrefParseError> for Error {
+    fn from(error: TaxrefParseError) -> Error {
+        Error::TaxrefParseError(error)
+    }
+}
+
 impl From<tera::Error> for Error {
     fn from(error: tera::Error) -> Error {
         Error::TeraError(error)
@@ -210,15 +353,133 @@ impl<'r> FromRequest<'r> for Db {
     }
 }
 
+/// Scraps the occurrences of a species in its own transaction, and creates the directories of its medias.
+///
+/// If `refresh` is true, the species is scraped again even if its scraping is done. The errors of the scraping itself
+/// are logged, only the errors of the database are returned, and the returned boolean tells whether the scraping
+/// succeeded.
+async fn scrap_species(
+    species: Entry,
+    max_occurrences: usize,
+    refresh: bool,
+    known_datasets: &Mutex<HashSet<Uuid>>,
+    config: &Config,
+    pool: Pool,
+) -> Result<bool> {
+    let mut db = Db::from_pool(pool).await?;
+
+    // Species scraped at the same time can have the same species key, and only the first one can be inserted. The
+    // other one fails and is scraped again, so that it is recorded as a duplicate of the first one.
+    let mut attempt = 0;
+
+    let s = loop {
+        attempt += 1;
+        let transaction = db.transaction().await?;
+
+        // Stale species are scraped again
+        if refresh {
+            let sql = r#"UPDATE speciess SET done = FALSE WHERE valid_name = $1"#;
+            transaction
+                .client()
+                .execute(sql, &[&species.valid_name])
+                .await?;
+        }
+
+        let s = Species::scrap_occurrences(species.clone(), max_occurrences, config, &transaction)
+            .await;
+
+        match s {
+            Err(Error::PostgresError(e))
+                if attempt < 2 && e.code() == Some(&SqlState::UNIQUE_VIOLATION) =>
+            {
+                debug!("conflict while scraping {}, retrying", species.valid_name);
+                transaction.rollback().await?;
+            }
+            s => {
+                transaction.commit().await?;
+                break s;
+            }
+        }
+    };
+
+    let db_species = match s {
+        Ok(db_species) => db_species,
+        Err(e) => {
+            error!("{}", e);
+            return Ok(false);
+        }
+    };
+
+    prepare_species(&db_species, known_datasets, config, &db).await?;
+
+    Ok(true)
+}
+
+/// Fetches the datasets of the occurrences of a scraped species that are not known yet, and creates the directories
+/// of its medias.
+async fn prepare_species(
+    species: &Species,
+    known_datasets: &Mutex<HashSet<Uuid>>,
+    config: &Config,
+    db: &Db,
+) -> Result<()> {
+    // Fetch the title of each dataset only once, it rarely changes
+    for occurrence in species.occurrences(db).await? {
+        let unknown = known_datasets
+            .lock()
+            .unwrap()
+            .insert(occurrence.dataset_key);
+
+        if unknown && let Err(e) = Dataset::fetch(occurrence.dataset_key, db).await {
+            warn!("failed to fetch dataset {}: {}", occurrence.dataset_key, e);
+        }
+    }
+
+    create_dir_all(&config.storage.medias_dir(species)).await?;
+    create_dir_all(&config.storage.cropped_medias_dir(species)).await?;
+
+    Ok(())
+}
+
+/// How far a scrap goes after storing the occurrences and medias of the species.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScrapMode {
+    /// Only the occurrences and medias are stored, and the medias to download are marked.
+    MetadataOnly,
+
+    /// The medias are downloaded and cropped too.
+    DownloadAndCrop,
+}
+
+/// Saves the summary of a scrap that started at some instant, so that the server can expose it in its metrics.
+fn save_summary(mut summary: ScrapSummary, start: Instant, config: &Config) {
+    summary.finished_at = Utc::now();
+    summary.duration_secs = start.elapsed().as_secs_f64();
+
+    if let Err(e) = summary.write(&config.storage.last_scrap_path()) {
+        warn!("failed to save the summary of the scrap: {}", e);
+    }
+}
+
 /// Scraps occurrences and then medias.
+///
+/// If `since` is given, the species scraped more recently than that are skipped, and the other ones are scraped again.
+///
+/// With [`ScrapMode::MetadataOnly`], the scrap stops once the medias to download are marked, so that they can be
+/// downloaded later, possibly on another machine.
+///
+/// Once finished, a summary of the scrap is saved so that the server can expose it in its metrics.
 pub async fn scrap(
     taxon: Taxon,
     query: &str,
     min_occurrences: usize,
     max_occurrences: usize,
-    crop: bool,
+    mode: ScrapMode,
+    since: Option<Duration>,
     config: &Config,
 ) -> Result<()> {
+    let start = Instant::now();
+
     let pool =
         ergol::pool(&config.databases.database.url, 32).expect("Failed to connect to the database");
 
@@ -227,136 +488,213 @@ pub async fn scrap(
         .expect("Failed to connect to the database");
 
     // Create occurrences directory
-    let species_dir = config.storage.species_dir();
-    let species_dir = species_dir.to_str().expect("Failed to convert path to str");
-    create_dir_all(species_dir).await.expect(&format!(
-        "Failed to create species directory \"{}\"",
-        species_dir
-    ));
+    create_dir_all(&config.storage.species_dir()).await?;
 
     // Ensure taxref is downloaded
-    let taxref_path = taxref::path().expect("Couldn't get taxref path");
+    let taxref_path = taxref::path(&config.taxref).expect("Couldn't get taxref path");
     if !taxref_path.exists() {
-        taxref::download().await?;
+        taxref::download(&config.taxref).await?;
     }
 
-    // Find species matching query
-    let species = Entry::from_taxon(taxon, query)?;
-    let species_len = species.len();
+    // Find species matching query, and start scraping as soon as the first one is found
+    let species = Entry::stream_taxon(taxon, query, &config.taxref);
+    let mut species = pin!(species.enumerate());
+
+    // The datasets already fetched during this scrap
+    let known_datasets = Arc::new(Mutex::new(HashSet::new()));
+
+    // The species that are recent enough to be skipped
+    let fresh = match since {
+        Some(since) => {
+            // A duration going back further than any date skips every species that was ever scraped
+            let since = TimeDelta::from_std(since)
+                .ok()
+                .and_then(|since| Utc::now().checked_sub_signed(since))
+                .unwrap_or(DateTime::<Utc>::MIN_UTC);
+            let sql = r#"SELECT valid_name FROM speciess WHERE updated_at > $1"#;
+
+            db.client()
+                .query(sql, &[&since])
+                .await?
+                .into_iter()
+                .map(|row| row.get::<_, String>(0))
+                .collect::<HashSet<_>>()
+        }
+        None => HashSet::new(),
+    };
 
-    // Start by scraping species and occurrences
-    for (index, species) in species.into_iter().enumerate() {
-        info!(
-            "{:05.2}% [1/2] [{:05}/{}] {}",
-            100.0 * (index as f32 + 1.0) / species_len as f32,
-            index + 1,
-            species_len,
-            species.valid_name
-        );
+    // The number of species is unknown until taxref is completely read
+    progress::start("[1/2] Species", None);
 
-        let transaction = db.transaction().await?;
+    // Start by scraping species and occurrences, several species at once if configured
+    let scrap_semaphore = Arc::new(Semaphore::new(config.scrap_jobs.max(1)));
+    let mut scrap_handles = vec![];
 
-        let s = Species::scrap_occurrences(
-            species.clone(),
-            max_occurrences,
-            &BLACKLISTED_DATASET,
-            &config.storage,
-            &transaction,
-        )
-        .await;
+    while let Some((index, species)) = species.next().await {
+        let species = species?;
 
-        transaction.commit().await?;
+        progress::set(index as u64 + 1, &species.valid_name);
 
-        match s {
-            Ok(_) => {
-                let medias_dir = config.storage.medias_dir(&species);
-                let medias_dir = medias_dir.to_str().expect("Failed to convert path to str");
-                create_dir_all(medias_dir).await.expect(&format!(
-                    "Failed to create medias directory \"{}\"",
-                    medias_dir
-                ));
-
-                let medias_dir = config.storage.cropped_medias_dir(&species);
-                let medias_dir = medias_dir.to_str().expect("Failed to convert path to str");
-                create_dir_all(medias_dir).await.expect(&format!(
-                    "Failed to create cropped medias directory \"{}\"",
-                    medias_dir
-                ));
-            }
-            Err(e) => {
-                error!("{}", e);
-            }
+        if fresh.contains(&species.valid_name) {
+            info!(
+                "[1/2] [{:05}] {} is up to date",
+                index + 1,
+                species.valid_name
+            );
+            continue;
         }
+
+        info!("[1/2] [{:05}] {}", index + 1, species.valid_name);
+
+        let permit = scrap_semaphore.clone().acquire_owned().await.unwrap();
+        let pool = pool.clone();
+        let config = config.clone();
+        let known_datasets = known_datasets.clone();
+
+        scrap_handles.push(tokio::spawn(async move {
+            let result = scrap_species(
+                species,
+                max_occurrences,
+                since.is_some(),
+                &known_datasets,
+                &config,
+                pool,
+            )
+            .await;
+
+            // Dropping the permit here moves the permit into the async block
+            drop(permit);
+            result
+        }));
     }
 
-    /*
-        info!("Marking medias to download");
+    let mut summary = ScrapSummary::default();
 
-        // We're doing this with two big requests
-        // First one: mark every first media for every occurrence
-        info!("First request: first media for each occurrence");
-        let sql = r#"
-            UPDATE medias
-            SET to_download = TRUE
-            FROM (
-                SELECT DISTINCT ON (occurrence) medias.id
-                FROM medias, occurrences
-                WHERE
-                    medias.occurrence = occurrences.id and
-                    occurrences.dataset_key != $1
-                ORDER BY
-                    medias.occurrence, medias.id
-            ) AS subquery
-            WHERE medias.id = subquery.id;
-        "#;
+    for handle in scrap_handles {
+        if handle.await.unwrap()? {
+            summary.species_scraped += 1;
+        } else {
+            summary.species_failed += 1;
+        }
+    }
 
-        info!("{}", sql);
-        db.client().query(sql, &[&BLACKLISTED_DATASET]).await?;
+    progress::finish();
 
-        // Second one: mark every media for every species with available_occurences < min_occurrences
-        // This request does not take into account the available_occurences attribute which counts
-        // blacklisted datasets.
-        info!(
-            "Second request: every media for each species with less than {} occurrences",
-            min_occurrences
-        );
+    mark_and_download(summary, start, min_occurrences, mode, config, &mut db).await
+}
 
-        let sql = r#"
-            UPDATE medias
-            SET to_download = TRUE
-            FROM (
-                SELECT occurrences.id
-                FROM occurrences,
-                    (
-                        SELECT occurrences.species
-                        FROM occurrences
-                        WHERE occurrences.dataset_key != $1
-                        GROUP BY occurrences.species
-                        HAVING count(occurrences.id) < $2
-                    ) as subquery
-                WHERE occurrences.species = subquery.species
-            ) AS subquery2
-            WHERE medias.id = subquery2.id;
-        "#;
+/// Marks the medias to download once the occurrences are scraped, then downloads them unless the mode is
+/// [`ScrapMode::MetadataOnly`], and saves the summary of the scrap.
+async fn mark_and_download(
+    mut summary: ScrapSummary,
+    start: Instant,
+    min_occurrences: usize,
+    mode: ScrapMode,
+    config: &Config,
+    db: &mut Db,
+) -> Result<()> {
+    info!("Marking medias to download");
+    let transaction = db.transaction().await?;
+    let marked = db::mark_to_download(
+        min_occurrences,
+        config.max_medias_per_species,
+        config.representative_media,
+        &config.blacklisted_datasets,
+        &transaction,
+    )
+    .await?;
+    transaction.commit().await?;
+    info!("{} medias marked to download", marked);
+
+    if mode == ScrapMode::MetadataOnly {
+        info!("Scraping finished, medias are left to download");
+        save_summary(summary, start, config);
+        return Ok(());
+    }
+
+    let downloads = download(true, config).await?;
+    summary.medias_downloaded = downloads.medias_downloaded;
+    summary.medias_failed = downloads.medias_failed;
+    save_summary(summary, start, config);
+
+    Ok(())
+}
+
+/// Scraps the occurrences of a species from its GBIF species key, without looking for it in taxref, and then medias.
+///
+/// The medias are marked and downloaded like with [`scrap`], and a summary of the scrap is saved.
+pub async fn scrap_key(
+    species_key: i64,
+    min_occurrences: usize,
+    max_occurrences: usize,
+    mode: ScrapMode,
+    config: &Config,
+) -> Result<()> {
+    let start = Instant::now();
+
+    let pool =
+        ergol::pool(&config.databases.database.url, 32).expect("Failed to connect to the database");
+
+    let mut db = Db::from_pool(pool.clone())
+        .await
+        .expect("Failed to connect to the database");
+
+    info!("[1/2] Scraping species key {}", species_key);
 
-        info!("{}", sql);
-        db.client()
-            .query(sql, &[&BLACKLISTED_DATASET, &(min_occurrences as i64)])
+    let transaction = db.transaction().await?;
+    let species =
+        Species::scrap_occurrences_by_key(species_key, max_occurrences, config, &transaction)
             .await?;
+    transaction.commit().await?;
 
-    */
+    prepare_species(&species, &Mutex::new(HashSet::new()), config, &db).await?;
+
+    let summary = ScrapSummary {
+        species_scraped: 1,
+        ..ScrapSummary::default()
+    };
+
+    mark_and_download(summary, start, min_occurrences, mode, config, &mut db).await
+}
+
+/// Resumes the download phase of an interrupted scrap, trusting the medias that are already marked to download.
+///
+/// Nothing is scraped nor marked again, and the medias that got a status code before the interruption are not
+/// downloaded again. A summary of the downloads is saved.
+pub async fn resume_downloads(config: &Config) -> Result<()> {
+    let start = Instant::now();
+    let summary = download(true, config).await?;
+
+    info!(
+        "{} medias downloaded, {} failed",
+        summary.medias_downloaded, summary.medias_failed
+    );
+    save_summary(summary, start, config);
+
+    Ok(())
+}
+
+/// Downloads the medias that are marked to download and were never attempted, and crops them if `crop` is true.
+///
+/// The example medias of the species that got new medias are chosen again once everything is downloaded. Returns a
+/// summary with the numbers of medias downloaded and failed.
+pub async fn download(crop: bool, config: &Config) -> Result<ScrapSummary> {
+    let pool =
+        ergol::pool(&config.databases.database.url, 32).expect("Failed to connect to the database");
+
+    let db = Db::from_pool(pool.clone())
+        .await
+        .expect("Failed to connect to the database");
 
-    // First pass: download all media marked to_download
     let cropper = if crop {
         info!("initializing cropper");
         let db = Db::from_pool(pool.clone())
             .await
             .expect("Failed to connect to the database");
 
-        let (tx, rx) = unbounded_channel();
-        let cropper = Cropper::new(config.batch_size, config.clone(), db)
-            .await
-            .expect("Failed to create cropper");
+        // Downloads wait when the queue is full, so that they don't get too far ahead of the cropping.
+        let (tx, rx) = channel(config.crop_queue_capacity.max(1));
+        let cropper = Cropper::new(config.batch_size, config.clone(), db).await?;
 
         Some((cropper.run(rx), tx))
     } else {
@@ -364,144 +702,201 @@ pub async fn scrap(
     };
 
     info!("Scrap medias");
-    let client = reqwest::ClientBuilder::new()
-        .timeout(Duration::from_secs(30))
-        .build()
-        .unwrap();
+    let client = db::download_client(
+        config.max_redirects,
+        Duration::from_secs(config.connect_timeout_secs),
+        Duration::from_secs(config.request_timeout_secs),
+    )?;
 
     // Count medias to download for showing progress
-    let mut offset = 0;
-    let chunk_size = 100000;
+    let sql = r#"
+        SELECT COUNT(*)
+        FROM medias, occurrences
+        WHERE
+            medias.occurrence = occurrences.id AND
+            medias.to_download AND
+            medias.status_code IS NULL AND
+            occurrences.dataset_key <> ALL($1)
+        ;
+    "#;
+
+    let total = db
+        .client()
+        .query_one(sql, &[&config.blacklisted_datasets])
+        .await?
+        .get::<usize, i64>(0);
+
+    // The medias get a status code as they are downloaded, so the chunks start after the last media rather than at an
+    // offset.
+    let sql = r#"
+        SELECT
+            occurrences.*,
+            medias.*,
+            occurrences.species
+        FROM
+            medias,
+            occurrences
+        WHERE
+            medias.occurrence = occurrences.id AND
+            medias.to_download AND
+            medias.status_code IS NULL AND
+            occurrences.dataset_key <> ALL($1) AND
+            medias.id > $2
+        ORDER BY
+            medias.id
+        LIMIT
+            $3
+        ;
+    "#;
+
+    let mut last_id = 0;
+    let chunk_size = 10000i64;
+    let mut done = 0;
+
+    progress::start("[2/2] Medias", Some(total as u64));
 
     let semaphore = SEMAPHORE
         .get_or_init(async || Semaphore::new(config.jobs))
         .await;
 
     let mut handles = vec![];
+    let mut task_count = 0u64;
 
-    loop {
-        let species = Species::select()
-            .order_by(db::species::id::ascend())
-            .offset(offset)
-            .limit(chunk_size)
-            .execute(&db.transaction().await?)
-            .await?;
+    // The species of the last media, since the medias of a species mostly follow each other
+    let mut species: Option<Species> = None;
 
-        let len = species.len();
+    // The number of medias downloaded and of medias whose download failed
+    let media_counts = Arc::new((AtomicU64::new(0), AtomicU64::new(0)));
 
-        for (index, species) in species.into_iter().enumerate() {
-            let occurrences = species.occurrences(&db).await?;
+    // The species that got new medias, whose example media may change
+    let touched_species = Arc::new(Mutex::new(HashSet::new()));
 
-            let species_progress = 100.0 * (index as f32) / len as f32;
-            info!(
-                "[2/2] {:05.2}% [{:05}/{}] Species: {}",
-                species_progress,
-                index + 1,
-                len,
-                species.valid_name,
-            );
+    loop {
+        let rows = db
+            .client()
+            .query(sql, &[&config.blacklisted_datasets, &last_id, &chunk_size])
+            .await?;
 
-            // Find occurrences and medias for species
-            let sql = r#"
-                SELECT
-                    DISTINCT ON (occurrences.id)
-                    occurrences.*,
-                    medias.*
-                FROM
-                    speciess,
-                    medias,
-                    occurrences
-                WHERE
-                    speciess.id = occurrences.species AND
-                    medias.occurrence = occurrences.id AND
-                    occurrences.dataset_key != $1 AND
-                    speciess.id = $2
-                ORDER BY
-                    occurrences.id,
-                    medias.id
-                ;
-            "#;
-
-            let rows = db
-                .client()
-                .query(sql, &[&BLACKLISTED_DATASET, &species.id])
-                .await?;
+        let len = rows.len();
 
-            for row in rows {
-                let occurrence = Occurrence::from_row(&row);
+        for row in rows {
+            let occurrence = Occurrence::from_row(&row);
 
-                // 4 is the number of fields in the occurrence table... I know it's ugly :'(
-                let media = Media::from_row_with_offset(&row, 4);
+            // 7 is the number of fields in the occurrence table... I know it's ugly :'(
+            let media = Media::from_row_with_offset(&row, 7);
+            last_id = media.id;
 
-                if media.status_code.is_some() {
-                    if occurrences.len() > min_occurrences {
-                        break;
-                    } else {
-                        continue;
-                    }
-                }
+            let species_id = row.get::<usize, i32>(row.len() - 1);
+            let species = match &species {
+                Some(species) if species.id == species_id => species.clone(),
+                _ => species.insert(occurrence.species(&db).await?).clone(),
+            };
 
-                let pool = pool.clone();
-                let client = client.clone();
-                let config = config.clone();
-                let sender = cropper.as_ref().map(|x| x.1.clone());
-                let species = species.clone();
-                let permit = semaphore.acquire().await.unwrap();
-
-                // Remove finished handles
-                handles.retain(|x: &JoinHandle<_>| !x.is_finished());
-
-                handles.push(tokio::spawn(async move {
-                    let mut media = media;
-                    let db = Db::from_pool(pool).await.unwrap();
-
-                    // trace!("Downloading media {}", media.id);
-                    let result = media
-                        .download_with_info(&occurrence, &species, &client, &config.storage, &db)
-                        .await;
-
-                    match result {
-                        Ok(c) if 200 <= c && c < 400 => {
-                            // Ask cropper to crop media if necessary
-                            if let Some(sender) = sender {
-                                sender.send(Some(media.id)).unwrap();
-                            }
+            done += 1;
+            progress::set(done as u64, &species.valid_name);
+
+            if done % 1000 == 0 {
+                info!(
+                    "[2/2] {:05.2}% [{}/{}] Species: {}",
+                    100.0 * done as f32 / total.max(1) as f32,
+                    done,
+                    total,
+                    species.valid_name,
+                );
+            }
+
+            let pool = pool.clone();
+            let client = client.clone();
+            let config = config.clone();
+            let sender = cropper.as_ref().map(|x| x.1.clone());
+            let media_counts = media_counts.clone();
+            let touched_species = touched_species.clone();
+            let permit = semaphore.acquire().await.unwrap();
+
+            // Number the tasks so that the logs of concurrent downloads can be told apart
+            task_count += 1;
+            let task = task_count;
+
+            // Remove finished handles
+            handles.retain(|x: &JoinHandle<_>| !x.is_finished());
+
+            handles.push(tokio::spawn(async move {
+                let mut media = media;
+                let db = Db::from_pool(pool).await.unwrap();
+
+                debug!(
+                    "[2/2] [task {}] Downloading {} {}",
+                    task, media.id, media.url
+                );
+                let result = media
+                    .download_with_info(&occurrence, &species, &client, &config, &db)
+                    .await;
+
+                // A successful response that is not an image has no file, and is a failure
+                match result {
+                    Ok(c) if 200 <= c && c < 400 && media.path.is_some() => {
+                        debug!("[2/2] [task {}] Downloaded {} ({})", task, media.id, c);
+                        media_counts.0.fetch_add(1, Ordering::Relaxed);
+                        touched_species.lock().unwrap().insert(species.id);
+
+                        // Ask cropper to crop media if necessary
+                        if let Some(sender) = sender {
+                            sender.send(Some(media.id)).await.unwrap();
                         }
+                    }
 
-                        Ok(c) if c == 299 => (),
-                        Ok(e) => error!("Failed downloading {} {} {}", media.id, media.url, e),
-                        Err(e) => error!("Failed downloading {} {} {}", media.id, media.url, e),
+                    Ok(c) if c == 299 => (),
+                    Ok(e) => {
+                        error!(
+                            "[2/2] [task {}] Failed downloading {} {} {}",
+                            task, media.id, media.url, e
+                        );
+                        media_counts.1.fetch_add(1, Ordering::Relaxed);
+                    }
+                    Err(e) => {
+                        error!(
+                            "[2/2] [task {}] Failed downloading {} {} {}",
+                            task, media.id, media.url, e
+                        );
+                        media_counts.1.fetch_add(1, Ordering::Relaxed);
                     }
+                }
 
-                    // Dropping the permit here moves the permit into the async block
-                    drop(permit);
-                }));
-            }
+                // Dropping the permit here moves the permit into the async block
+                drop(permit);
+            }));
         }
 
-        if len < chunk_size {
+        if (len as i64) < chunk_size {
             break;
         }
-
-        offset += chunk_size;
     }
 
-    info!("Reached end of scraping, waiting for remaining downloads");
+    info!("Reached end of medias, waiting for remaining downloads");
 
     for handle in handles {
         handle.await.unwrap();
     }
 
+    progress::finish();
+
     // Finalize cropper
     if let Some((handle, sender)) = cropper {
-        info!("Scraping finished, waiting for cropping");
-        sender.send(None).unwrap();
+        info!("Downloads finished, waiting for cropping");
+        sender.send(None).await.unwrap();
         handle.await.unwrap();
     }
 
-    info!("Scraping finished");
+    // The other species keep their example media, which pick-examples chooses again
+    info!("Downloads finished, choosing example medias");
+    let touched_species = touched_species.lock().unwrap().drain().collect::<Vec<_>>();
+    choose_example_medias_of(&touched_species, config, &db).await?;
 
-    Ok(())
+    Ok(ScrapSummary {
+        medias_downloaded: media_counts.0.load(Ordering::Relaxed),
+        medias_failed: media_counts.1.load(Ordering::Relaxed),
+        ..ScrapSummary::default()
+    })
 }
 
 async fn crop(config: &Config) -> Result<()> {
@@ -516,9 +911,7 @@ async fn crop(config: &Config) -> Result<()> {
         .await
         .expect("Failed to connect to the database");
 
-    let mut cropper = Cropper::new(config.batch_size, config.clone(), db_clone)
-        .await
-        .expect("Failed to create cropper");
+    let mut cropper = Cropper::new(config.batch_size, config.clone(), db_clone).await?;
 
     let mut offset: i64 = 0;
     let chunk_size: i64 = 100 * config.batch_size as i64;
@@ -584,6 +977,372 @@ async fn crop(config: &Config) -> Result<()> {
     Ok(())
 }
 
+/// Imports the occurrences and medias of a species through an asynchronous GBIF download.
+///
+/// This is slower to start than scraping, since GBIF takes a while to prepare the archive, but has no limit on the
+/// number of occurrences. The species must already have been scraped, and its medias are downloaded by the next scrap.
+pub async fn bulk_download(species_key: i64, config: &Config) -> Result<()> {
+    let pool =
+        ergol::pool(&config.databases.database.url, 32).expect("Failed to connect to the database");
+
+    let mut db = Db::from_pool(pool)
+        .await
+        .expect("Failed to connect to the database");
+
+    let species = match Species::get_by_species_key(species_key, &db).await? {
+        Some(species) => species,
+        None => return Err(Error::SpeciesNotFound(species_key.to_string())),
+    };
+
+    let key = gbif::request_download(
+        species_key,
+        &config.occurrence_filters,
+        &config.gbif_download,
+    )
+    .await?;
+    info!("Requested GBIF download {} for {}", key, species.valid_name);
+
+    let link = gbif::wait_download(&key).await?;
+
+    let dir = config.storage.downloads_dir().join(&key);
+    create_dir_all(&dir).await?;
+
+    let archive = dir.with_extension("zip");
+    if !archive.exists() {
+        info!("Downloading {}", link);
+        utils::download(&link, &archive).await?;
+    }
+
+    let tables = archive.clone();
+    let tables_dir = dir.clone();
+    spawn_blocking(move || -> Result<()> {
+        let mut reader = File::open(&tables)?;
+
+        for table in ["occurrence.txt", "multimedia.txt"] {
+            let mut writer = File::create(tables_dir.join(table))?;
+            if !dwca::extract(&mut reader, table, &mut writer)? {
+                return Err(Error::InvalidArchive(format!("{} is missing", table)));
+            }
+        }
+
+        Ok(())
+    })
+    .await
+    .expect("Failed to join extracting task")?;
+
+    let transaction = db.transaction().await?;
+    let imported = species.import_archive(&dir, &transaction).await?;
+    transaction.commit().await?;
+
+    info!(
+        "Imported {} occurrences for {}",
+        imported, species.valid_name
+    );
+
+    Ok(())
+}
+
+/// Makes the medias that have the same content share a single file, and removes the other files.
+async fn dedup(config: &Config) -> Result<()> {
+    let pool =
+        ergol::pool(&config.databases.database.url, 32).expect("Failed to connect to the database");
+
+    let mut db = Db::from_pool(pool.clone())
+        .await
+        .expect("Failed to connect to the database");
+
+    // Medias downloaded before hashes were stored need one to be compared.
+    let hashed = Media::hash_missing(&config.storage, &db).await?;
+    info!("hashed {} medias", hashed);
+
+    let transaction = db.transaction().await?;
+    let dedup = Media::dedup(&config.storage, &transaction).await?;
+    transaction.commit().await?;
+
+    // The files are only removed once no media points to them anymore.
+    let reclaimed = dedup.apply(&config.storage)?;
+
+    info!(
+        "{} medias now share their file, {} files removed, {} bytes reclaimed",
+        dedup.medias,
+        dedup.redundant.len(),
+        reclaimed
+    );
+
+    Ok(())
+}
+
+/// Marks the medias to download again, with a new minimum number of occurrences.
+async fn mark(min_occurrences: usize, config: &Config) -> Result<()> {
+    let pool =
+        ergol::pool(&config.databases.database.url, 32).expect("Failed to connect to the database");
+
+    let mut db = Db::from_pool(pool.clone())
+        .await
+        .expect("Failed to connect to the database");
+
+    // Medias are never left half marked.
+    let transaction = db.transaction().await?;
+    let marked = db::mark_to_download(
+        min_occurrences,
+        config.max_medias_per_species,
+        config.representative_media,
+        &config.blacklisted_datasets,
+        &transaction,
+    )
+    .await?;
+    transaction.commit().await?;
+
+    info!("{} medias marked to download", marked);
+
+    Ok(())
+}
+
+/// Links the ignored species to the species they are synonyms of.
+async fn link_synonyms(config: &Config) -> Result<()> {
+    let pool =
+        ergol::pool(&config.databases.database.url, 32).expect("Failed to connect to the database");
+
+    let db = Db::from_pool(pool.clone())
+        .await
+        .expect("Failed to connect to the database");
+
+    let linked = IgnoredSpecies::link_canonical(&db).await?;
+    info!(
+        "{} ignored species linked to their canonical species",
+        linked
+    );
+
+    Ok(())
+}
+
+/// Prints the ignored species, with the species key they share with another species and the species key of that
+/// species once linked.
+async fn list_ignored(config: &Config) -> Result<()> {
+    let pool =
+        ergol::pool(&config.databases.database.url, 32).expect("Failed to connect to the database");
+
+    let db = Db::from_pool(pool.clone())
+        .await
+        .expect("Failed to connect to the database");
+
+    let key = |x: Option<i64>| x.map(|x| x.to_string()).unwrap_or_default();
+
+    for ignored in IgnoredSpecies::list(&db).await? {
+        println!(
+            "{}\t{}\t{}",
+            ignored.valid_name,
+            key(ignored.species_key),
+            key(ignored.canonical_species_key)
+        );
+    }
+
+    Ok(())
+}
+
+/// Removes an ignored species, or all of them, and exits with an error if there is no such ignored species.
+async fn clear_ignored(valid_name: Option<&str>, config: &Config) -> Result<()> {
+    let pool =
+        ergol::pool(&config.databases.database.url, 32).expect("Failed to connect to the database");
+
+    let db = Db::from_pool(pool.clone())
+        .await
+        .expect("Failed to connect to the database");
+
+    let removed = IgnoredSpecies::clear(valid_name, &db).await?;
+
+    if let Some(valid_name) = valid_name
+        && removed == 0
+    {
+        error!("ignored species \"{}\" does not exist", valid_name);
+        exit(1);
+    }
+
+    info!("{} ignored species removed", removed);
+
+    Ok(())
+}
+
+/// Marks the scraping of a species as done or pending, and exits with an error if there is no such species.
+async fn mark_species(valid_name: &str, done: bool, config: &Config) -> Result<()> {
+    let pool =
+        ergol::pool(&config.databases.database.url, 32).expect("Failed to connect to the database");
+
+    let db = Db::from_pool(pool.clone())
+        .await
+        .expect("Failed to connect to the database");
+
+    let found = if done {
+        Species::mark_done(valid_name, &db).await?
+    } else {
+        Species::mark_pending(valid_name, &db).await?
+    };
+
+    if !found {
+        error!("species \"{}\" does not exist", valid_name);
+        exit(1);
+    }
+
+    info!(
+        "species \"{}\" marked as {}",
+        valid_name,
+        if done { "done" } else { "pending" }
+    );
+
+    Ok(())
+}
+
+/// Chooses the example media of every species, with the strategy of the config.
+async fn choose_example_medias(config: &Config, db: &Db) -> Result<()> {
+    let mut offset = 0;
+    let chunk_size = 10000;
+    let mut chosen = 0;
+
+    loop {
+        let species = Species::select()
+            .order_by(db::species::id::ascend())
+            .offset(offset)
+            .limit(chunk_size)
+            .execute(db)
+            .await?;
+
+        let len = species.len();
+
+        for mut species in species {
+            let path = species
+                .choose_example_media(
+                    config.example_media_strategy,
+                    &config.blacklisted_datasets,
+                    db,
+                )
+                .await?;
+
+            if path.is_some() {
+                chosen += 1;
+            }
+        }
+
+        if len < chunk_size {
+            break;
+        }
+
+        offset += chunk_size;
+    }
+
+    info!("example media chosen for {} species", chosen);
+
+    Ok(())
+}
+
+/// Chooses the example media of some species, with the strategy of the config.
+async fn choose_example_medias_of(species_ids: &[i32], config: &Config, db: &Db) -> Result<()> {
+    let mut chosen = 0;
+
+    for id in species_ids {
+        let Some(mut species) = Species::get_by_id(*id, db).await? else {
+            continue;
+        };
+
+        let path = species
+            .choose_example_media(
+                config.example_media_strategy,
+                &config.blacklisted_datasets,
+                db,
+            )
+            .await?;
+
+        if path.is_some() {
+            chosen += 1;
+        }
+    }
+
+    info!(
+        "example media chosen for {} of the {} species that got new medias",
+        chosen,
+        species_ids.len()
+    );
+
+    Ok(())
+}
+
+/// Chooses the example media of every species again.
+async fn pick_examples(config: &Config) -> Result<()> {
+    let pool =
+        ergol::pool(&config.databases.database.url, 32).expect("Failed to connect to the database");
+
+    let db = Db::from_pool(pool.clone())
+        .await
+        .expect("Failed to connect to the database");
+
+    choose_example_medias(config, &db).await
+}
+
+/// Chooses the example media of the species that have downloaded medias but no example media.
+async fn backfill_examples(config: &Config) -> Result<()> {
+    let pool =
+        ergol::pool(&config.databases.database.url, 32).expect("Failed to connect to the database");
+
+    let db = Db::from_pool(pool.clone())
+        .await
+        .expect("Failed to connect to the database");
+
+    let backfilled = Species::backfill_example_medias(
+        config.example_media_strategy,
+        &config.blacklisted_datasets,
+        &db,
+    )
+    .await?;
+
+    info!("example media backfilled for {} species", backfilled);
+
+    Ok(())
+}
+
+/// Reports the medias that have a crop box but no cropped file, and resets their crop if `reset` is true so that the
+/// next crop processes them again. The cropped files of the medias with a manual box are written again instead.
+async fn verify_crops(reset: bool, config: &Config) -> Result<()> {
+    let pool =
+        ergol::pool(&config.databases.database.url, 32).expect("Failed to connect to the database");
+
+    let mut db = Db::from_pool(pool.clone())
+        .await
+        .expect("Failed to connect to the database");
+
+    let missing = Media::missing_crops(&config.storage, &db).await?;
+
+    for media in &missing {
+        println!(
+            "{}\t{}",
+            media.id,
+            media.path.as_deref().unwrap_or_default()
+        );
+    }
+
+    if !reset {
+        info!(
+            "{} medias have a crop box but no cropped file",
+            missing.len()
+        );
+        return Ok(());
+    }
+
+    let mut count = 0;
+    let transaction = db.transaction().await?;
+    for mut media in missing {
+        if let Err(e) = media.reset_crop(&config.storage) {
+            warn!("failed to crop media {} again: {}", media.id, e);
+            continue;
+        }
+        media.save(&transaction).await?;
+        count += 1;
+    }
+    transaction.commit().await?;
+
+    info!("crop reset for {} medias", count);
+
+    Ok(())
+}
+
 async fn regen_cache(config: &Config) -> Result<()> {
     let pool =
         ergol::pool(&config.databases.database.url, 32).expect("Failed to connect to the database");
@@ -597,18 +1356,119 @@ async fn regen_cache(config: &Config) -> Result<()> {
     Ok(())
 }
 
-/// Prints a pretty help.
+/// Prints the commands and options of the scraper.
 pub fn print_help() {
-    println!("NO HELP FOR YOU");
+    println!(
+        r#"scraper {}
+
+Usage: scraper <command> [options]
+
+Commands:
+  scrap <taxon>=<value>       Scraps the species of a taxon of taxref, such as family=Apidae, then downloads and crops
+                              their medias (taxon is reign, phylum, class, order, family, genus or species)
+  scrap-key <species key>     Scraps a species from its GBIF species key, without looking for it in taxref
+  bulk-download <species key> Imports the occurrences and medias of a scraped species through a GBIF download
+  download                    Resumes an interrupted scrap by downloading and cropping the medias marked to download
+  crop                        Crops the downloaded medias that are not cropped yet
+  mark                        Marks the medias to download again
+  verify-crops                Reports the medias that have a crop box but no cropped file
+  dedup                       Makes the medias with the same content share a single file
+  pick-examples               Chooses the example media of every species again
+  backfill-examples           Chooses the example media of the species that have none
+  regen-cache                 Regenerates the cache of the species metadatas
+  link-synonyms               Links the ignored species to the species they are synonyms of
+  list-ignored                Lists the ignored species
+  clear-ignored [valid name]  Removes an ignored species, or all of them
+  mark-done <valid name>      Marks the scraping of a species as done
+  mark-pending <valid name>   Marks the scraping of a species as pending
+  serve                       Starts the server
+  reset-db                    Resets the database
+
+Options:
+  --metadata-only             With scrap and scrap-key, only stores the occurrences and medias and marks the medias to
+                              download, so that they are downloaded later with download
+  --since <duration>          With scrap, skips the species scraped more recently than a duration such as 30d, 12h, 15m
+                              or 45s, and scraps the other ones again
+  --country <code>            Only scraps the occurrences of a country, such as FR (overrides the configuration)
+  --min-occurrences <number>  With scrap, scrap-key and mark, downloads every media of the species that have less
+                              occurrences than this (default {})
+  --refresh                   Searches every species on GBIF again, even if its species key is cached
+  --reset                     With verify-crops, resets the crop of these medias so that crop processes them again
+  --progress                  Shows a progress bar below the logs
+  --log-level <level>         Sets the level of the logs: error, warn, info, debug, trace or off
+  -h, --help                  Prints this help
+  -v, --version               Prints the version"#,
+        env!("CARGO_PKG_VERSION"),
+        DEFAULT_MIN_OCCURRENCES,
+    );
 }
 
 fn print_version() {
     println!("scraper {}", env!("CARGO_PKG_VERSION"));
 }
 
+/// Removes an option such as `--country=FR` or `--country FR` from the arguments, and returns its value.
+fn take_option(args: &mut Vec<String>, name: &str) -> Option<String> {
+    let index = args
+        .iter()
+        .position(|x| x == name || x.starts_with(&format!("{}=", name)))?;
+    let arg = args.remove(index);
+
+    match arg.split_once("=") {
+        Some((_, value)) => Some(value.to_owned()),
+        None if index < args.len() => Some(args.remove(index)),
+        None => {
+            print_help();
+            exit(1);
+        }
+    }
+}
+
 /// Main.
 pub async fn main() -> Result<()> {
-    let args = args().collect::<Vec<_>>();
+    let mut args = args().collect::<Vec<_>>();
+
+    // Extract the options before looking at the command
+    let log_level_arg = take_option(&mut args, "--log-level");
+    let country_arg = take_option(&mut args, "--country");
+    let since_arg = take_option(&mut args, "--since");
+    let min_occurrences_arg = take_option(&mut args, "--min-occurrences");
+
+    // Only store the occurrences and medias, they are downloaded later
+    let metadata_only = match args.iter().position(|x| x == "--metadata-only") {
+        Some(index) => {
+            args.remove(index);
+            true
+        }
+        None => false,
+    };
+
+    // Show a progress bar below the logs
+    let progress = match args.iter().position(|x| x == "--progress") {
+        Some(index) => {
+            args.remove(index);
+            true
+        }
+        None => false,
+    };
+
+    // Reset the crop of the medias whose cropped file is missing
+    let reset = match args.iter().position(|x| x == "--reset") {
+        Some(index) => {
+            args.remove(index);
+            true
+        }
+        None => false,
+    };
+
+    // Search every species on GBIF again, even if its species key is cached
+    let refresh = match args.iter().position(|x| x == "--refresh") {
+        Some(index) => {
+            args.remove(index);
+            true
+        }
+        None => false,
+    };
 
     // The first argument is the name of the binary, the second one is the command
     if args.len() < 2 {
@@ -626,17 +1486,136 @@ pub async fn main() -> Result<()> {
         exit(0);
     }
 
-    let config = Config::from_figment(&rocket::Config::figment());
+    let since = since_arg.map(|since| match parse_duration(&since) {
+        Some(duration)
+            if TimeDelta::from_std(duration)
+                .ok()
+                .and_then(|x| Utc::now().checked_sub_signed(x))
+                .is_some() =>
+        {
+            duration
+        }
+        Some(_) => {
+            eprintln!("error: duration \"{}\" is too long", since);
+            exit(1);
+        }
+        None => {
+            eprintln!("error: invalid duration \"{}\"", since);
+            exit(1);
+        }
+    });
+
+    let min_occurrences = match min_occurrences_arg.map(|x| x.parse::<usize>()) {
+        Some(Ok(min_occurrences)) => min_occurrences,
+        Some(Err(_)) => {
+            eprintln!("error: invalid minimum number of occurrences");
+            exit(1);
+        }
+        None => DEFAULT_MIN_OCCURRENCES,
+    };
+
+    let mut config = Config::from_figment(&rocket::Config::figment());
+
+    // The command line has precedence over the config
+    if let Some(country) = country_arg {
+        config.occurrence_filters.country = Some(country);
+    }
+
+    if refresh {
+        config.name_resolution_ttl_days = 0;
+    }
+
+    gbif::set_gbif_root(&config.gbif_root);
+    gbif::set_gbif_timeouts(
+        Duration::from_secs(config.connect_timeout_secs),
+        Duration::from_secs(config.request_timeout_secs),
+    );
+    RATE_LIMITER.set_rate(config.gbif_requests_per_second);
+
+    if let Some(country) = &config.occurrence_filters.country
+        && !OccurrenceFilters::is_valid_country(country)
+    {
+        eprintln!("error: invalid country code \"{}\"", country);
+        exit(1);
+    }
+
+    // A read-only data directory would only make the commands that write to it fail later, in a less obvious way, while
+    // the other ones, like serve, work with a read-only mount of scraped data
+    let writes_data = match args[1].as_ref() {
+        "scrap" | "scrap-key" | "download" | "crop" | "bulk-download" | "dedup" => true,
+        "verify-crops" => reset,
+        _ => false,
+    };
+
+    if writes_data {
+        config.storage.check_writable()?;
+    }
 
     let log_dir = config.storage.data_path.join("logs");
-    create_dir_all(&log_dir)
-        .await
-        .expect("Failed to create log directory");
+    create_dir_all(&log_dir).await?;
+
+    // The command line has precedence over the environment, which has precedence over the config
+    let parse_level = |level: &str| match level.parse::<LevelFilter>() {
+        Ok(level) => level,
+        Err(_) => {
+            eprintln!("error: invalid log level \"{}\"", level);
+            exit(1);
+        }
+    };
 
-    let module = vec![String::from(module_path!())];
-    let filename = format!("{}", Local::now().format("%Y-%m-%d--%H-%M-%S.log"));
-    let logfile = File::create(log_dir.join(filename)).expect("Failed to create log file");
-    Log::init(logfile, module).expect("Failed to init logging system");
+    let config_level = parse_level(&config.log_level);
+
+    // RUST_LOG may also set levels per module, and is only a fallback, so an invalid one is ignored
+    let (env_level, env_modules) = match var("RUST_LOG") {
+        Ok(filter) => parse_env_filter(&filter, config_level).unwrap_or_else(|directive| {
+            eprintln!(
+                "warning: ignoring RUST_LOG, invalid directive \"{}\"",
+                directive
+            );
+            (config_level, vec![])
+        }),
+        Err(_) => (config_level, vec![]),
+    };
+
+    let log_level = match log_level_arg {
+        Some(level) => parse_level(&level),
+        None => env_level,
+    };
+
+    // The progress bar would be lost among the trace logs, and can't be drawn if stderr is not a terminal
+    if progress && log_level != LevelFilter::Trace && io::stderr().is_terminal() {
+        progress::enable();
+    }
+
+    let mut modules = vec![(String::from(module_path!()), log_level)];
+    match parse_module_levels(&config.log_modules, log_level) {
+        Ok(levels) => modules.extend(levels),
+        Err(directive) => {
+            eprintln!("error: invalid module log level \"{}\"", directive);
+            exit(1);
+        }
+    }
+    modules.extend(env_modules);
+
+    if !is_valid_timestamp_format(&config.log_timestamp_format) {
+        eprintln!(
+            "error: invalid log timestamp format \"{}\"",
+            config.log_timestamp_format
+        );
+        exit(1);
+    }
+
+    let options = LogOptions {
+        level: log_level,
+        format: config.log_format,
+        color: config.log_color,
+        max_bytes: config.log_max_bytes,
+        rotate_daily: config.log_rotate_daily,
+        keep_files: config.log_keep_files,
+        timestamp_format: config.log_timestamp_format.clone(),
+        utc: config.log_utc,
+    };
+    let _flush_guard = Log::init(log_dir, modules, options).expect("Failed to init logging system");
 
     match args[1].as_ref() {
         "reset-db" => {
@@ -665,7 +1644,48 @@ pub async fn main() -> Result<()> {
                 }
             };
 
-            scrap(taxon, query[1], 30, 1200, true, &config).await?;
+            let mode = if metadata_only {
+                ScrapMode::MetadataOnly
+            } else {
+                ScrapMode::DownloadAndCrop
+            };
+
+            scrap(taxon, query[1], min_occurrences, 1200, mode, since, &config).await?;
+        }
+
+        "scrap-key" => {
+            let species_key = match args.get(2).map(|x| x.parse::<i64>()) {
+                Some(Ok(species_key)) => species_key,
+                _ => {
+                    print_help();
+                    exit(1);
+                }
+            };
+
+            let mode = if metadata_only {
+                ScrapMode::MetadataOnly
+            } else {
+                ScrapMode::DownloadAndCrop
+            };
+
+            scrap_key(species_key, min_occurrences, 1200, mode, &config).await?;
+        }
+
+        "bulk-download" => {
+            let species_key = match args.get(2).map(|x| x.parse::<i64>()) {
+                Some(Ok(species_key)) => species_key,
+                _ => {
+                    print_help();
+                    exit(1);
+                }
+            };
+
+            bulk_download(species_key, &config).await?;
+        }
+
+        "download" => {
+            info!("Resuming the download of the marked medias");
+            resume_downloads(&config).await?;
         }
 
         "crop" => {
@@ -679,10 +1699,54 @@ pub async fn main() -> Result<()> {
             }
         }
 
+        "verify-crops" => {
+            verify_crops(reset, &config).await?;
+        }
+
         "regen-cache" => {
             regen_cache(&config).await?;
         }
 
+        "dedup" => {
+            dedup(&config).await?;
+        }
+
+        "pick-examples" => {
+            pick_examples(&config).await?;
+        }
+
+        "backfill-examples" => {
+            backfill_examples(&config).await?;
+        }
+
+        "mark" => {
+            mark(min_occurrences, &config).await?;
+        }
+
+        "link-synonyms" => {
+            link_synonyms(&config).await?;
+        }
+
+        "list-ignored" => {
+            list_ignored(&config).await?;
+        }
+
+        "clear-ignored" => {
+            clear_ignored(args.get(2).map(String::as_str), &config).await?;
+        }
+
+        command @ ("mark-done" | "mark-pending") => {
+            let valid_name = match args.get(2) {
+                Some(valid_name) => valid_name,
+                None => {
+                    print_help();
+                    exit(1);
+                }
+            };
+
+            mark_species(valid_name, command == "mark-done", &config).await?;
+        }
+
         command => {
             // Unknwon command
             error!(
@@ -1,100 +1,488 @@
 //! This modules helps us with logging.
 
-use std::fs::File;
-use std::io::Write;
-use std::sync::{Arc, Mutex};
+use std::fs::{File, OpenOptions, read_dir, remove_file};
+use std::io::{self, BufWriter, IsTerminal, Write};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{Receiver, SyncSender, sync_channel};
+use std::thread;
+use std::time::Instant;
 
-use chrono::Local;
+use chrono::format::{Item, StrftimeItems};
+use chrono::{Local, NaiveDate, Utc};
 
-use log::{Level, LevelFilter, Metadata, Record, SetLoggerError};
+use log::{Level, LevelFilter, Metadata, Record};
 
 use rocket::fairing::{Fairing, Info, Kind};
-use rocket::{Orbit, Request, Response, Rocket};
+use rocket::{Data, Orbit, Request, Response, Rocket};
 
-/// This structure holds the file where log will be appended.
-pub struct Log {
-    /// The file in which the logs will be appended.
-    file: Arc<Mutex<File>>,
+use serde::{Deserialize, Serialize};
+
+use serde_json::json;
+
+use crate::config::Config;
+use crate::metrics::Metrics;
+use crate::progress;
 
-    /// Modules to log.
-    modules: Vec<String>,
+/// The format in which logs are written to the log file, stderr always getting human readable lines.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// Human readable lines, colored on stderr.
+    #[default]
+    Text,
+
+    /// One JSON object per line in the log file, for log shippers.
+    Json,
 }
 
-impl Log {
-    /// Creates a new logging with a file.
-    pub fn init(file: File, modules: Vec<String>) -> Result<(), SetLoggerError> {
-        log::set_boxed_logger(Box::new(Log {
-            file: Arc::new(Mutex::new(file)),
-            modules,
-        }))
-        .map(|()| log::set_max_level(LevelFilter::Trace))?;
+/// When the logs written on stderr are colored.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogColor {
+    /// Only when stderr is a terminal.
+    #[default]
+    Auto,
+
+    /// Always, even when stderr is redirected.
+    Always,
+
+    /// Never.
+    Never,
+}
+
+impl LogColor {
+    /// Returns true if the logs written on stderr must be colored.
+    pub fn enabled(self) -> bool {
+        match self {
+            LogColor::Auto => io::stderr().is_terminal(),
+            LogColor::Always => true,
+            LogColor::Never => false,
+        }
+    }
+}
+
+/// The options of the logging system.
+#[derive(Debug, Clone)]
+pub struct LogOptions {
+    /// The maximum level of the logs, for modules that don't specify theirs.
+    pub level: LevelFilter,
+
+    /// The format of the logs.
+    pub format: LogFormat,
+
+    /// When the logs written on stderr are colored.
+    pub color: LogColor,
+
+    /// The size in bytes after which a new log file is started, if any.
+    pub max_bytes: Option<u64>,
+
+    /// Whether a new log file is started at local midnight.
+    pub rotate_daily: bool,
+
+    /// The number of old log files to keep when rotating, older ones are deleted.
+    pub keep_files: usize,
+
+    /// The strftime format of the timestamps of text logs.
+    pub timestamp_format: String,
+
+    /// Whether timestamps are in UTC instead of local time.
+    pub utc: bool,
+}
+
+impl Default for LogOptions {
+    fn default() -> LogOptions {
+        LogOptions {
+            level: LevelFilter::Info,
+            format: LogFormat::Text,
+            color: LogColor::Auto,
+            max_bytes: None,
+            rotate_daily: false,
+            keep_files: 10,
+            timestamp_format: String::from(DEFAULT_TIMESTAMP_FORMAT),
+            utc: false,
+        }
+    }
+}
+
+/// The format of the timestamps of text logs when none is specified, such as `2025-03-14T15:09:26+01:00`.
+pub const DEFAULT_TIMESTAMP_FORMAT: &str = "%Y-%m-%dT%H:%M:%S%:z";
+
+/// Returns true if a strftime format can be used for timestamps.
+///
+/// Formatting a timestamp with an invalid format panics, so the format must be checked before logging.
+pub fn is_valid_timestamp_format(format: &str) -> bool {
+    StrftimeItems::new(format).all(|item| !matches!(item, Item::Error))
+}
+
+/// The log file currently being written, with what we need to know when to rotate it.
+struct LogFile {
+    /// The directory where the log files are created.
+    dir: PathBuf,
+
+    /// The file in which the logs are appended, flushed after each batch of lines.
+    file: BufWriter<File>,
+
+    /// The number of bytes written in the file.
+    size: u64,
+
+    /// The day the file was opened.
+    date: NaiveDate,
+}
+
+impl LogFile {
+    /// Creates a new log file in the directory, named after the current time.
+    fn create(dir: PathBuf) -> io::Result<LogFile> {
+        let now = Local::now();
+        let filename = format!("{}", now.format("%Y-%m-%d--%H-%M-%S.log"));
+
+        // Append so that two files created during the same second don't erase each other.
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(dir.join(filename))?;
+
+        let size = file.metadata()?.len();
+
+        Ok(LogFile {
+            dir,
+            file: BufWriter::new(file),
+            size,
+            date: now.date_naive(),
+        })
+    }
+
+    /// Returns true if the file must be rotated according to the options.
+    fn should_rotate(&self, options: &LogOptions) -> bool {
+        let too_big = options.max_bytes.map(|x| self.size >= x).unwrap_or(false);
+        let new_day = options.rotate_daily && Local::now().date_naive() != self.date;
+        too_big || new_day
+    }
+
+    /// Starts a new log file, and deletes the oldest ones.
+    ///
+    /// The new file is opened before the old one is dropped, so no line is lost: if the new file cannot be opened, we
+    /// keep writing in the old one.
+    fn rotate(&mut self, keep_files: usize) -> io::Result<()> {
+        let new = LogFile::create(self.dir.clone())?;
+        let old = std::mem::replace(self, new);
+        drop(old);
+
+        // Log files are named after their creation time, so sorting them by name sorts them by age.
+        let mut files = read_dir(&self.dir)?
+            .filter_map(|x| x.ok())
+            .map(|x| x.path())
+            .filter(|x| x.extension().map(|e| e == "log").unwrap_or(false))
+            .collect::<Vec<_>>();
+
+        files.sort();
+
+        // The current file is the most recent one, and is not counted as an old file.
+        let to_delete = files.len().saturating_sub(keep_files + 1);
+
+        for file in &files[..to_delete] {
+            remove_file(file)?;
+        }
+
         Ok(())
     }
 
-    fn includes_module(&self, module_path: &str) -> bool {
-        // If modules is empty, include all module paths
-        if self.modules.is_empty() {
-            return true;
+    /// Writes a line in the file.
+    fn write_line(&mut self, line: &str) -> io::Result<()> {
+        writeln!(self.file, "{}", line)?;
+        self.size += line.len() as u64 + 1;
+        Ok(())
+    }
+}
+
+/// The maximum number of lines waiting to be written in the log file.
+///
+/// When the writer thread is late, logging blocks until it catches up rather than losing lines.
+const CHANNEL_CAPACITY: usize = 4096;
+
+/// A message sent to the thread that writes the log file.
+enum Message {
+    /// A line to append to the log file.
+    Line(String),
+
+    /// A request to flush the log file, acknowledged once every line sent before is written.
+    Flush(SyncSender<()>),
+}
+
+/// Writes the lines received on the channel in the log file, until every sender is dropped.
+///
+/// The lines already waiting in the channel are written as a batch, and the file is flushed once per batch.
+///
+/// Failing to write the file never stops the program: every line is also written on stderr, so we only warn once
+/// there that the log file is incomplete, and keep trying with the next lines.
+fn write_loop(mut file: LogFile, options: LogOptions, receiver: Receiver<Message>) {
+    let mut degraded = false;
+    let mut report = |e: io::Error| {
+        if !degraded {
+            degraded = true;
+            eprintln!("logging degraded, the log file may be incomplete: {}", e);
         }
+    };
 
-        // if a prefix of module_path is in `self.modules`, it must
-        // be located at the first location before
-        // where module_path would be.
-        let search = self
-            .modules
-            .binary_search_by(|module| module.as_str().cmp(&module_path));
-
-        match search {
-            Ok(_) => {
-                // Found exact module: return true
-                true
-            }
-            Err(0) => {
-                // if there's no item which would be located before module_path, no prefix is there
-                false
+    while let Ok(message) = receiver.recv() {
+        let mut acks = vec![];
+
+        for message in std::iter::once(message).chain(receiver.try_iter()) {
+            match message {
+                Message::Line(line) => {
+                    // If the rotation fails, we keep writing in the current file.
+                    if file.should_rotate(&options)
+                        && let Err(e) = file.rotate(options.keep_files)
+                    {
+                        report(e);
+                    }
+
+                    if let Err(e) = file.write_line(&line) {
+                        report(e);
+                    }
+                }
+
+                Message::Flush(ack) => acks.push(ack),
             }
-            Err(i) => is_submodule(&self.modules[i - 1], module_path),
+        }
+
+        if let Err(e) = file.file.flush() {
+            report(e);
+        }
+
+        for ack in acks {
+            ack.send(()).ok();
         }
     }
 }
 
+/// Whether a logger was already installed by [`Log::init`] or [`Log::init_null`].
+static INITIALIZED: AtomicBool = AtomicBool::new(false);
+
+/// A logger that discards every log, for tests that call logging functions.
+pub struct NullLogger;
+
+impl log::Log for NullLogger {
+    fn enabled(&self, _: &Metadata) -> bool {
+        false
+    }
+
+    fn log(&self, _: &Record) {}
+
+    fn flush(&self) {}
+}
+
+/// Flushes the log file when dropped.
+///
+/// Keep it alive until the end of the program so that the lines still waiting for the writer thread are not lost.
+pub struct FlushGuard;
+
+impl Drop for FlushGuard {
+    fn drop(&mut self) {
+        log::logger().flush();
+    }
+}
+
+/// This structure holds the channel to the thread that appends the logs in the file.
+pub struct Log {
+    /// The channel to the thread writing the log file.
+    sender: SyncSender<Message>,
+
+    /// Modules with their own maximum level, the other ones having the default level.
+    modules: Vec<(String, LevelFilter)>,
+
+    /// The options of the logging system.
+    options: LogOptions,
+
+    /// Whether the logs written on stderr are colored.
+    colored: bool,
+}
+
+impl Log {
+    /// Creates a new logging with a file in the given directory.
+    ///
+    /// The file is written by a dedicated thread, so that logging never waits for the disk. The returned guard flushes
+    /// the file when dropped.
+    ///
+    /// If a logger is already installed, it is kept and nothing is done, so that parallel tests can all call this.
+    pub fn init(
+        dir: PathBuf,
+        modules: Vec<(String, LevelFilter)>,
+        options: LogOptions,
+    ) -> io::Result<FlushGuard> {
+        if INITIALIZED.swap(true, Ordering::SeqCst) {
+            return Ok(FlushGuard);
+        }
+
+        // The global level must let through the most verbose module.
+        let level = modules
+            .iter()
+            .map(|(_, level)| *level)
+            .max()
+            .unwrap_or(options.level);
+        let file = LogFile::create(dir)?;
+        let (sender, receiver) = sync_channel(CHANNEL_CAPACITY);
+
+        let writer_options = options.clone();
+        thread::Builder::new()
+            .name(String::from("log-writer"))
+            .spawn(move || write_loop(file, writer_options, receiver))?;
+
+        // Another logger may have been installed without us, in which case it is kept.
+        let logger = Box::new(Log {
+            sender,
+            modules,
+            colored: options.color.enabled(),
+            options,
+        });
+
+        if log::set_boxed_logger(logger).is_ok() {
+            log::set_max_level(level);
+        }
+
+        Ok(FlushGuard)
+    }
+
+    /// Installs a logger that discards every log, without creating any file.
+    ///
+    /// This is meant for tests, and does nothing if a logger is already installed.
+    pub fn init_null() {
+        if !INITIALIZED.swap(true, Ordering::SeqCst) {
+            log::set_logger(&NullLogger).ok();
+        }
+    }
+
+    /// Sends a line to the thread writing the log file.
+    ///
+    /// If the thread is gone, the line is lost for the file but was already written on stderr.
+    fn write_line(&self, line: String) {
+        self.sender.send(Message::Line(line)).ok();
+    }
+
+    /// Returns the maximum level of the logs of a module.
+    ///
+    /// The level of the most specific module of the list that contains the module is used. The list is not a filter:
+    /// the modules outside of it are logged with the default level, and can be silenced with a level such as
+    /// `rocket=off`.
+    fn level_for(&self, module_path: &str) -> LevelFilter {
+        self.modules
+            .iter()
+            .filter(|(module, _)| is_submodule(module, module_path))
+            .max_by_key(|(module, _)| module.len())
+            .map(|(_, level)| *level)
+            .unwrap_or(self.options.level)
+    }
+}
+
+/// Parses per module log levels, such as `scraper::gbif=trace,scraper::server=info`.
+///
+/// A module without level, such as `rocket`, is logged with the default level. On failure, returns the directive
+/// that couldn't be parsed.
+pub fn parse_module_levels(
+    directives: &str,
+    default: LevelFilter,
+) -> Result<Vec<(String, LevelFilter)>, String> {
+    directives
+        .split(',')
+        .map(|x| x.trim())
+        .filter(|x| !x.is_empty())
+        .map(|directive| match directive.split_once('=') {
+            Some((module, level)) => match level.trim().parse::<LevelFilter>() {
+                Ok(level) if !module.trim().is_empty() => Ok((module.trim().to_owned(), level)),
+                _ => Err(directive.to_owned()),
+            },
+            None => Ok((directive.to_owned(), default)),
+        })
+        .collect()
+}
+
+/// Parses a filter in the format of `RUST_LOG`, such as `info,hyper=warn`, into a level and per module levels.
+///
+/// A bare level, such as `info`, replaces the default level, and the other directives are parsed by
+/// [`parse_module_levels`]. On failure, returns the directive that couldn't be parsed.
+pub fn parse_env_filter(
+    filter: &str,
+    default: LevelFilter,
+) -> Result<(LevelFilter, Vec<(String, LevelFilter)>), String> {
+    let (levels, directives): (Vec<_>, Vec<_>) = filter
+        .split(',')
+        .map(|x| x.trim())
+        .partition(|x| x.parse::<LevelFilter>().is_ok());
+
+    let level = levels
+        .last()
+        .and_then(|x| x.parse().ok())
+        .unwrap_or(default);
+
+    Ok((level, parse_module_levels(&directives.join(","), level)?))
+}
+
 impl log::Log for Log {
     fn enabled(&self, metadata: &Metadata) -> bool {
-        self.includes_module(metadata.target())
+        metadata.level() <= self.level_for(metadata.target())
     }
 
     fn log(&self, record: &Record) {
         if self.enabled(record.metadata()) {
             let args = record.args();
-            let now = Local::now().format("%d/%m/%Y %H:%M:%S");
 
-            match record.level() {
-                Level::Error => {
-                    eprintln!("\x1b[38;5;243m{}\x1b[0m \x1b[31m[ERR] {}\x1b[0m", now, args);
-                    writeln!(self.file.lock().unwrap(), "{} [ERR] {}", now, args).unwrap();
-                }
-                Level::Warn => {
-                    eprintln!("\x1b[38;5;243m{}\x1b[0m \x1b[33m[WRN] {}\x1b[0m", now, args);
-                    writeln!(self.file.lock().unwrap(), "{} [WRN] {}", now, args).unwrap();
-                }
-                Level::Info => {
-                    eprintln!("\x1b[38;5;243m{}\x1b[0m \x1b[35m[LOG] {}\x1b[0m", now, args);
-                    writeln!(self.file.lock().unwrap(), "{} [LOG] {}", now, args).unwrap();
-                }
-                Level::Debug => {
-                    eprintln!("\x1b[38;5;243m{}\x1b[0m \x1b[34m[DBG] {}\x1b[0m", now, args);
-                    writeln!(self.file.lock().unwrap(), "{} [DBG] {}", now, args).unwrap();
-                }
-                Level::Trace => {
-                    eprintln!("\x1b[38;5;243m{}\x1b[0m \x1b[36m[TRC] {}\x1b[0m", now, args);
-                    writeln!(self.file.lock().unwrap(), "{} [TRC] {}", now, args).unwrap();
+            let now = if self.options.utc {
+                Utc::now()
+                    .format(&self.options.timestamp_format)
+                    .to_string()
+            } else {
+                Local::now()
+                    .format(&self.options.timestamp_format)
+                    .to_string()
+            };
+
+            let (color, label) = match record.level() {
+                Level::Error => (31, "ERR"),
+                Level::Warn => (33, "WRN"),
+                Level::Info => (35, "LOG"),
+                Level::Debug => (34, "DBG"),
+                Level::Trace => (36, "TRC"),
+            };
+
+            // Stderr is read by people, so it stays in plain text whatever the format of the file.
+            progress::suspend(|| {
+                if self.colored {
+                    eprintln!(
+                        "\x1b[38;5;243m{}\x1b[0m \x1b[{}m[{}] {}\x1b[0m",
+                        now, color, label, args
+                    );
+                } else {
+                    eprintln!("{} [{}] {}", now, label, args);
                 }
+            });
+
+            if self.options.format == LogFormat::Json {
+                let timestamp = if self.options.utc {
+                    Utc::now().to_rfc3339()
+                } else {
+                    Local::now().to_rfc3339()
+                };
+
+                let line = json!({
+                    "timestamp": timestamp,
+                    "level": record.level().as_str(),
+                    "target": record.target(),
+                    "message": args.to_string(),
+                });
+
+                self.write_line(line.to_string());
+            } else {
+                self.write_line(format!("{} [{}] {}", now, label, args));
             }
         }
     }
 
     fn flush(&self) {
-        self.file.lock().unwrap().flush().unwrap();
+        let (ack, done) = sync_channel(1);
+
+        if self.sender.send(Message::Flush(ack)).is_ok() {
+            done.recv().ok();
+        }
     }
 }
 
@@ -120,15 +508,66 @@ fn is_submodule(parent: &str, possible_child: &str) -> bool {
         || possible_child.get(parent.len()..parent.len() + 2) == Some(b"::")
 }
 
+/// Returns the addresses of the `X-Forwarded-For` header of a request, from the client to the last proxy.
+///
+/// The header is only used if the config says the server is behind a trusted proxy, otherwise any client could spoof
+/// its address.
+fn forwarded_for<'r>(req: &'r Request<'_>) -> Vec<&'r str> {
+    let trust_proxy = req
+        .rocket()
+        .state::<Config>()
+        .map(|config| config.trust_proxy)
+        .unwrap_or(false);
+
+    if !trust_proxy {
+        return vec![];
+    }
+
+    req.headers()
+        .get_one("X-Forwarded-For")
+        .map(|x| {
+            x.split(',')
+                .map(str::trim)
+                .filter(|x| !x.is_empty())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Returns the address of the client that sent a request, if known.
+///
+/// This is the left-most `X-Forwarded-For` address if the server is behind a trusted proxy. The client can send any
+/// value there, so it is only good for logging: use [`trusted_client_ip`] to tell clients apart.
+pub fn client_ip(req: &Request<'_>) -> Option<String> {
+    forwarded_for(req)
+        .first()
+        .map(|x| x.to_string())
+        .or_else(|| req.client_ip().map(|ip| ip.to_string()))
+}
+
+/// Returns the address of the client that sent a request as seen by the trusted proxy, if known.
+///
+/// This is the right-most `X-Forwarded-For` address, which the proxy appended, so that clients can't pick a new
+/// address for each request.
+pub fn trusted_client_ip(req: &Request<'_>) -> Option<String> {
+    forwarded_for(req)
+        .last()
+        .map(|x| x.to_string())
+        .or_else(|| req.client_ip().map(|ip| ip.to_string()))
+}
+
 /// Fairing to log responses to HTTP requests.
 pub struct LogFairing;
 
+/// The instant at which a request was received, stored in the request local cache.
+struct RequestStart(Option<Instant>);
+
 #[rocket::async_trait]
 impl Fairing for LogFairing {
     fn info(&self) -> Info {
         Info {
             name: "Log Fairing",
-            kind: Kind::Liftoff | Kind::Response,
+            kind: Kind::Liftoff | Kind::Request | Kind::Response,
         }
     }
 
@@ -136,18 +575,30 @@ impl Fairing for LogFairing {
         info!("Server listening on port {}", rocket.config().port);
     }
 
+    async fn on_request(&self, req: &mut Request<'_>, _: &mut Data<'_>) {
+        req.local_cache(|| RequestStart(Some(Instant::now())));
+    }
+
     async fn on_response<'r>(&self, req: &'r Request<'_>, res: &mut Response<'r>) {
-        let ip = match req.client_ip() {
-            Some(ip) => format!("{}", ip),
-            None => String::from("Unknown addr"),
+        let ip = client_ip(req).unwrap_or_else(|| String::from("Unknown addr"));
+
+        // If the request was not seen by on_request, we don't know when it started.
+        let elapsed = match req.local_cache(|| RequestStart(None)).0 {
+            Some(start) => format!("{}ms", start.elapsed().as_millis()),
+            None => String::from("?ms"),
         };
 
+        if let Some(metrics) = req.rocket().state::<Metrics>() {
+            metrics.record(res.status().code);
+        }
+
         info!(
-            "{} - {} {} {}",
+            "{} - {} {} {} {}",
             ip,
             req.method(),
             req.uri(),
-            res.status().code
+            res.status().code,
+            elapsed
         );
     }
 }
@@ -0,0 +1,191 @@
+//! This module helps us expose metrics about the server and the last scrap in the Prometheus text format.
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::fs;
+use std::path::Path;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use chrono::{DateTime, Utc};
+
+use serde::{Deserialize, Serialize};
+
+use crate::Result;
+
+/// The counters of the server, kept in the state of rocket.
+#[derive(Debug, Default)]
+pub struct Metrics {
+    /// The number of requests answered since the server started.
+    requests: AtomicU64,
+
+    /// The number of responses by status code since the server started.
+    statuses: Mutex<BTreeMap<u16, u64>>,
+}
+
+/// The counters of a scrap, saved when it finishes so that the server can expose them.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ScrapSummary {
+    /// When the scrap finished.
+    pub finished_at: DateTime<Utc>,
+
+    /// How long the scrap took, in seconds.
+    pub duration_secs: f64,
+
+    /// The number of species whose occurrences were scraped.
+    pub species_scraped: u64,
+
+    /// The number of species whose scraping failed.
+    pub species_failed: u64,
+
+    /// The number of medias that were downloaded.
+    pub medias_downloaded: u64,
+
+    /// The number of medias whose download failed.
+    pub medias_failed: u64,
+}
+
+impl ScrapSummary {
+    /// Reads the summary of the last scrap, if there is one.
+    pub fn read(path: &Path) -> Option<ScrapSummary> {
+        serde_json::from_str(&fs::read_to_string(path).ok()?).ok()
+    }
+
+    /// Saves the summary, replacing the one of the previous scrap.
+    pub fn write(&self, path: &Path) -> Result<()> {
+        fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+}
+
+/// The connections of the pool of the database.
+#[derive(Debug, Clone, Copy)]
+pub struct PoolUsage {
+    /// The number of connections that are used.
+    pub in_use: usize,
+
+    /// The maximum number of connections.
+    pub max_size: usize,
+}
+
+/// Appends the help and the type of a metric.
+fn header(output: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(output, "# HELP {} {}", name, help);
+    let _ = writeln!(output, "# TYPE {} {}", name, kind);
+}
+
+impl Metrics {
+    /// Counts a response.
+    pub fn record(&self, status: u16) {
+        self.requests.fetch_add(1, Ordering::Relaxed);
+        *self.statuses.lock().unwrap().entry(status).or_default() += 1;
+    }
+
+    /// Renders the metrics in the Prometheus text format.
+    pub fn render(&self, pool: PoolUsage, last_scrap: Option<&ScrapSummary>) -> String {
+        let mut output = String::new();
+
+        header(
+            &mut output,
+            "scraper_http_requests_total",
+            "counter",
+            "Number of HTTP requests answered.",
+        );
+        let requests = self.requests.load(Ordering::Relaxed);
+        let _ = writeln!(output, "scraper_http_requests_total {}", requests);
+
+        header(
+            &mut output,
+            "scraper_http_responses_total",
+            "counter",
+            "Number of HTTP responses by status code.",
+        );
+        for (status, count) in self.statuses.lock().unwrap().iter() {
+            let _ = writeln!(
+                output,
+                "scraper_http_responses_total{{status=\"{}\"}} {}",
+                status, count
+            );
+        }
+
+        header(
+            &mut output,
+            "scraper_db_pool_connections_in_use",
+            "gauge",
+            "Number of connections of the database pool in use.",
+        );
+        let _ = writeln!(output, "scraper_db_pool_connections_in_use {}", pool.in_use);
+
+        header(
+            &mut output,
+            "scraper_db_pool_connections_max",
+            "gauge",
+            "Maximum number of connections of the database pool.",
+        );
+        let _ = writeln!(output, "scraper_db_pool_connections_max {}", pool.max_size);
+
+        let Some(summary) = last_scrap else {
+            return output;
+        };
+
+        header(
+            &mut output,
+            "scraper_last_scrap_timestamp_seconds",
+            "gauge",
+            "When the last scrap finished, as a unix timestamp.",
+        );
+        let _ = writeln!(
+            output,
+            "scraper_last_scrap_timestamp_seconds {}",
+            summary.finished_at.timestamp()
+        );
+
+        header(
+            &mut output,
+            "scraper_last_scrap_duration_seconds",
+            "gauge",
+            "How long the last scrap took.",
+        );
+        let _ = writeln!(
+            output,
+            "scraper_last_scrap_duration_seconds {}",
+            summary.duration_secs
+        );
+
+        header(
+            &mut output,
+            "scraper_last_scrap_species",
+            "gauge",
+            "Number of species of the last scrap by outcome.",
+        );
+        for (outcome, count) in [
+            ("scraped", summary.species_scraped),
+            ("failed", summary.species_failed),
+        ] {
+            let _ = writeln!(
+                output,
+                "scraper_last_scrap_species{{outcome=\"{}\"}} {}",
+                outcome, count
+            );
+        }
+
+        header(
+            &mut output,
+            "scraper_last_scrap_medias",
+            "gauge",
+            "Number of medias of the last scrap by outcome.",
+        );
+        for (outcome, count) in [
+            ("downloaded", summary.medias_downloaded),
+            ("failed", summary.medias_failed),
+        ] {
+            let _ = writeln!(
+                output,
+                "scraper_last_scrap_medias{{outcome=\"{}\"}} {}",
+                outcome, count
+            );
+        }
+
+        output
+    }
+}
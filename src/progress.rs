@@ -0,0 +1,71 @@
+//! This module helps us show the progress of a scrap to humans watching it in a terminal.
+//!
+//! There is a single progress bar at a time, drawn below the logs written on stderr. Nothing is drawn unless the
+//! progress bar was enabled, so the functions of this module can be called unconditionally.
+
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use indicatif::{ProgressBar, ProgressStyle};
+
+/// Whether the progress bar is drawn.
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// The progress bar of the current phase, if any.
+static BAR: Mutex<Option<ProgressBar>> = Mutex::new(None);
+
+/// Draws the progress bars of the next phases.
+pub fn enable() {
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+/// Starts a new phase, replacing the progress bar of the previous one.
+///
+/// If the total is unknown, only the count is shown.
+pub fn start(phase: &str, total: Option<u64>) {
+    if !ENABLED.load(Ordering::Relaxed) {
+        return;
+    }
+
+    let (bar, template) = match total {
+        Some(total) => (
+            ProgressBar::new(total),
+            "{prefix} [{bar:40}] {pos}/{len} ({eta}) {msg}",
+        ),
+        None => (ProgressBar::no_length(), "{prefix} {spinner} {pos} {msg}"),
+    };
+
+    let style = ProgressStyle::with_template(template)
+        .expect("Invalid progress bar template")
+        .progress_chars("=> ");
+
+    bar.set_style(style);
+    bar.set_prefix(phase.to_owned());
+
+    if let Some(previous) = BAR.lock().unwrap().replace(bar) {
+        previous.finish_and_clear();
+    }
+}
+
+/// Moves the progress bar of the current phase, with a message such as the name of the current species.
+pub fn set(position: u64, message: &str) {
+    if let Some(bar) = BAR.lock().unwrap().as_ref() {
+        bar.set_position(position);
+        bar.set_message(message.to_owned());
+    }
+}
+
+/// Removes the progress bar of the current phase.
+pub fn finish() {
+    if let Some(bar) = BAR.lock().unwrap().take() {
+        bar.finish_and_clear();
+    }
+}
+
+/// Hides the progress bar while running a function that writes on stderr, so that the output doesn't overwrite it.
+pub fn suspend<R>(f: impl FnOnce() -> R) -> R {
+    match BAR.lock().unwrap().as_ref() {
+        Some(bar) => bar.suspend(f),
+        None => f(),
+    }
+}
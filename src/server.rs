@@ -1,44 +1,127 @@
 //! This module contains all the routes and utils for the webserver.
 
+use std::collections::HashMap;
+use std::collections::hash_map::Entry;
+use std::io::{self, Cursor};
 use std::path::{Path, PathBuf};
 use std::process::exit;
 use std::result::Result as StdResult;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use chrono::Utc;
 
 use serde::{Deserialize, Serialize};
 
 use serde_json::{Value, json};
 
+use ergol::Pool;
 use ergol::prelude::*;
 use ergol::tokio_postgres::types::ToSql;
 
 use tera::{Context, Tera};
 
-use rocket::fairing::AdHoc;
+use rocket::data::Data;
+use rocket::fairing::{AdHoc, Fairing, Info, Kind};
 use rocket::fs::NamedFile;
+use rocket::http::uri::Origin;
+use rocket::http::{ContentType, Header, Method, RawStr, Status};
+use rocket::request::{FromParam, FromRequest, Outcome, Request};
 use rocket::response::content::RawHtml;
+use rocket::response::stream::TextStream;
 use rocket::serde::json::Json;
 use rocket::{self, Ignite, Rocket, State as S};
 
-use image::{DynamicImage, ImageReader};
+use image::{DynamicImage, ImageFormat, ImageReader, Rgb, RgbImage};
+
+use uuid::Uuid;
+
+use base64::prelude::*;
+
+use futures_util::{Stream, StreamExt};
 
-use crate::config::{BLACKLISTED_DATASET, Config};
-use crate::db::Media;
-use crate::db::{Species, SpeciesMetadata};
-use crate::logger::LogFairing;
+use tokio::task::spawn_blocking;
+
+use crate::config::Config;
+use crate::db::{CropBox, Dataset, DatasetCount, IgnoredSpecies, Media, MediaFeedback, Occurrence};
+use crate::db::{MediaStats, Species, SpeciesMetadata};
+use crate::gbif::{fetch_dataset, set_gbif_root, set_gbif_timeouts};
+use crate::logger::{LogFairing, trusted_client_ip};
+use crate::metrics::{Metrics, PoolUsage, ScrapSummary};
 use crate::taxref::Taxon;
-use crate::utils::{pretty_finder, pretty_name};
-use crate::{Db, Result};
+use crate::utils::{RATE_LIMITER, pretty_finder, pretty_name};
+use crate::{CaughtError, Db, Error, Result};
 
 /// Number of items per page.
 const LIMIT: i64 = 16;
 
+/// Number of items per page of the JSON API.
+const API_LIMIT: i64 = 100;
+
+/// Maximum number of feedbacks a client can send during [`FEEDBACK_WINDOW`].
+const FEEDBACK_LIMIT: usize = 30;
+
+/// The period over which the feedbacks of a client are counted.
+const FEEDBACK_WINDOW: Duration = Duration::from_secs(600);
+
+/// Maximum number of characters kept from the note of a feedback.
+const FEEDBACK_NOTE_MAX_CHARS: usize = 2000;
+
+/// The path to which the requests of the clients that made too many requests are sent.
+const RATE_LIMITED_PATH: &str = "/rate-limited";
+
+/// The paths that are never rate limited, so that monitoring always gets an answer.
+const RATE_LIMIT_EXEMPT_PATHS: [&str; 1] = ["/metrics"];
+
+/// Number of clients above which the clients that can make requests freely again are forgotten.
+const RATE_LIMIT_MAX_CLIENTS: usize = 10_000;
+
 /// Easily return `RawHtml<String>`.
 type Html = RawHtml<String>;
 
+/// A page that is either rendered in HTML, or given as the JSON its template would be rendered with.
+#[derive(Responder)]
+pub enum Page {
+    /// The rendered template.
+    Html(Html, Header<'static>),
+
+    /// The data of the template.
+    Json(Value, Header<'static>),
+}
+
+/// Request guard telling whether the client prefers JSON over HTML, according to its `Accept` header.
+pub struct AcceptJson(pub bool);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for AcceptJson {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        Outcome::Success(AcceptJson(
+            request
+                .accept()
+                .map(|x| x.preferred().is_json())
+                .unwrap_or(false),
+        ))
+    }
+}
+
 /// Helper trait to be able to easily render json.
 trait Renderable {
     /// Uses tera to render with json data as context.
     fn render_json(&self, template_name: &str, value: Value) -> Result<Html>;
+
+    /// Renders the template, or returns the json data as is if the client prefers JSON.
+    fn render_page(&self, template_name: &str, value: Value, json: AcceptJson) -> Result<Page> {
+        // The same URL gives different contents depending on the Accept header.
+        let vary = Header::new("Vary", "Accept");
+
+        if json.0 {
+            Ok(Page::Json(value, vary))
+        } else {
+            Ok(Page::Html(self.render_json(template_name, value)?, vary))
+        }
+    }
 }
 
 impl Renderable for Tera {
@@ -54,6 +137,227 @@ impl Renderable for Tera {
     }
 }
 
+/// Request guard for the admin routes.
+///
+/// The request must carry one of the admin tokens of the config, either as `Authorization: Bearer <token>`, or as the
+/// password of basic auth so that browsers can prompt for it. Otherwise, the request fails with 401.
+pub struct AuthGuard;
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for AuthGuard {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let tokens = match request.rocket().state::<Config>() {
+            Some(config) => &config.admin_tokens,
+            None => return Outcome::Error((Status::InternalServerError, ())),
+        };
+
+        let given = request
+            .headers()
+            .get_one("Authorization")
+            .and_then(authorization_token);
+
+        // Check every token, so that the time taken doesn't tell which one is closest.
+        let authorized = given
+            .map(|given| {
+                tokens
+                    .iter()
+                    .fold(false, |acc, x| acc | constant_time_eq(x, &given))
+            })
+            .unwrap_or(false);
+
+        if authorized {
+            Outcome::Success(AuthGuard)
+        } else {
+            Outcome::Error((Status::Unauthorized, ()))
+        }
+    }
+}
+
+/// Request guard giving the address of the client as seen by the trusted proxy, or `unknown` if it can't be found.
+pub struct ClientIp(pub String);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for ClientIp {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        Outcome::Success(ClientIp(
+            trusted_client_ip(request).unwrap_or_else(|| String::from("unknown")),
+        ))
+    }
+}
+
+/// Remembers when clients sent feedbacks, to limit how many they can send.
+#[derive(Default)]
+pub struct FeedbackLimiter(Mutex<HashMap<String, Vec<Instant>>>);
+
+impl FeedbackLimiter {
+    /// Records a feedback from a client, unless the client already sent too many recently, in which case false is
+    /// returned.
+    pub fn allow(&self, ip: &str) -> bool {
+        let now = Instant::now();
+        let mut clients = self.0.lock().unwrap();
+
+        // Forget old feedbacks, so that the map doesn't grow forever.
+        clients.retain(|_, times| {
+            times.retain(|time| now.duration_since(*time) < FEEDBACK_WINDOW);
+            !times.is_empty()
+        });
+
+        let times = clients.entry(ip.to_owned()).or_default();
+        if times.len() >= FEEDBACK_LIMIT {
+            return false;
+        }
+
+        times.push(now);
+        true
+    }
+}
+
+/// The requests that a client can still make, refilled continuously.
+struct Bucket {
+    /// The number of requests that can be made right now.
+    tokens: f64,
+
+    /// When the number of tokens was last computed.
+    updated: Instant,
+}
+
+/// Fairing that limits the number of requests of each client, with a token bucket.
+///
+/// Each client can make `rate_limit_per_minute` requests in a burst, and then one more request every
+/// `60 / rate_limit_per_minute` seconds. Requests beyond this are answered with 429 without reaching their route.
+#[derive(Default)]
+pub struct RateLimitFairing(Mutex<HashMap<String, Bucket>>);
+
+impl RateLimitFairing {
+    /// Takes a token from the bucket of a client, and returns false if the bucket is empty.
+    fn allow(&self, ip: &str, per_minute: u32) -> bool {
+        let now = Instant::now();
+        let capacity = per_minute as f64;
+        let refill = |bucket: &Bucket| {
+            let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+            (bucket.tokens + elapsed * capacity / 60.0).min(capacity)
+        };
+
+        let mut buckets = self.0.lock().unwrap();
+
+        // Forget the clients whose bucket is full again, so that the map doesn't grow forever.
+        if buckets.len() > RATE_LIMIT_MAX_CLIENTS {
+            buckets.retain(|_, bucket| refill(bucket) < capacity);
+        }
+
+        let bucket = buckets.entry(ip.to_owned()).or_insert(Bucket {
+            tokens: capacity,
+            updated: now,
+        });
+
+        bucket.tokens = refill(bucket);
+        bucket.updated = now;
+
+        if bucket.tokens < 1.0 {
+            return false;
+        }
+
+        bucket.tokens -= 1.0;
+        true
+    }
+}
+
+#[rocket::async_trait]
+impl Fairing for RateLimitFairing {
+    fn info(&self) -> Info {
+        Info {
+            name: "Rate Limit Fairing",
+            kind: Kind::Request,
+        }
+    }
+
+    async fn on_request(&self, req: &mut Request<'_>, _: &mut Data<'_>) {
+        let per_minute = match req
+            .rocket()
+            .state::<Config>()
+            .and_then(|x| x.rate_limit_per_minute)
+        {
+            Some(per_minute) => per_minute,
+            None => return,
+        };
+
+        if RATE_LIMIT_EXEMPT_PATHS.contains(&req.uri().path().as_str()) {
+            return;
+        }
+
+        let ip = trusted_client_ip(req).unwrap_or_else(|| String::from("unknown"));
+
+        if !self.allow(&ip, per_minute) {
+            // Fairings can't answer requests, so the request is sent to a route that answers 429.
+            warn!(
+                "too many requests from {}: {} {}",
+                ip,
+                req.method(),
+                req.uri()
+            );
+            req.set_method(Method::Get);
+            req.set_uri(Origin::parse(RATE_LIMITED_PATH).unwrap());
+        }
+    }
+}
+
+/// The titles of the GBIF datasets that were already fetched.
+#[derive(Default)]
+pub struct DatasetTitles(Mutex<HashMap<Uuid, String>>);
+
+impl DatasetTitles {
+    /// Returns the title of a dataset, fetching it from GBIF if it is not known yet.
+    pub async fn get(&self, key: Uuid) -> Option<String> {
+        if let Some(title) = self.0.lock().unwrap().get(&key) {
+            return Some(title.clone());
+        }
+
+        match fetch_dataset(key).await {
+            Ok(dataset) => {
+                self.0.lock().unwrap().insert(key, dataset.title.clone());
+                Some(dataset.title)
+            }
+
+            Err(e) => {
+                warn!("failed to fetch the title of dataset {}: {}", key, e);
+                None
+            }
+        }
+    }
+}
+
+/// Extracts the token from the value of an `Authorization` header.
+fn authorization_token(header: &str) -> Option<String> {
+    let (scheme, value) = header.trim().split_once(' ')?;
+
+    if scheme.eq_ignore_ascii_case("bearer") {
+        return Some(value.trim().to_owned());
+    }
+
+    if scheme.eq_ignore_ascii_case("basic") {
+        // The user name is ignored, only the password is checked.
+        let decoded = BASE64_STANDARD.decode(value.trim()).ok()?;
+        let decoded = String::from_utf8(decoded).ok()?;
+        let (_, password) = decoded.split_once(':')?;
+        return Some(password.to_owned());
+    }
+
+    None
+}
+
+/// Compares two strings in a time that only depends on their lengths, to avoid timing attacks.
+fn constant_time_eq(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .fold(0, |acc, (x, y)| acc | (x ^ y))
+            == 0
+}
+
 /// Index route.
 #[get("/")]
 pub fn index(tera: &S<Tera>) -> Result<Html> {
@@ -66,14 +370,21 @@ pub async fn species(
     taxon_key: Taxon,
     taxon_value: &str,
     page: u32,
+    json: AcceptJson,
+    config: &S<Config>,
     tera: &S<Tera>,
     db: Db,
-) -> Result<Html> {
-    if let Taxon::Species = taxon_key {
-        species_by_valid_name(taxon_value, false, page, tera, db).await
+) -> Result<Option<Page>> {
+    let (template_name, value) = if let Taxon::Species = taxon_key {
+        match species_by_valid_name(taxon_value, false, page, config, db).await? {
+            Some(page) => page,
+            None => return Ok(None),
+        }
     } else {
-        species_list(taxon_key, taxon_value, false, page, tera, db).await
-    }
+        species_list(taxon_key, taxon_value, false, page, config, db).await?
+    };
+
+    tera.render_page(template_name, value, json).map(Some)
 }
 
 /// List the species where there are uncropped medias.
@@ -82,30 +393,42 @@ pub async fn uncropped_species(
     taxon_key: Taxon,
     taxon_value: &str,
     page: u32,
+    json: AcceptJson,
+    config: &S<Config>,
     tera: &S<Tera>,
     db: Db,
-) -> Result<Html> {
-    if let Taxon::Species = taxon_key {
-        species_by_valid_name(taxon_value, true, page, tera, db).await
+) -> Result<Option<Page>> {
+    let (template_name, value) = if let Taxon::Species = taxon_key {
+        match species_by_valid_name(taxon_value, true, page, config, db).await? {
+            Some(page) => page,
+            None => return Ok(None),
+        }
+    } else {
+        species_list(taxon_key, taxon_value, true, page, config, db).await?
+    };
+
+    tera.render_page(template_name, value, json).map(Some)
+}
+
+/// Returns the column of the speciess table that holds a taxonomic level.
+fn species_column(taxon: Taxon) -> &'static str {
+    if let Taxon::Species = taxon {
+        "valid_name"
     } else {
-        species_list(taxon_key, taxon_value, true, page, tera, db).await
+        taxon.to_str()
     }
 }
 
-/// Returns the HTML page that List species with a specific taxon filter.
+/// Returns the template and the data of the page that List species with a specific taxon filter.
 pub async fn species_list(
     taxon: Taxon,
     taxon_value: &str,
     uncropped_only: bool,
     page: u32,
-    tera: &S<Tera>,
+    config: &S<Config>,
     db: Db,
-) -> Result<Html> {
-    let taxon_key = if let Taxon::Species = taxon {
-        "valid_name"
-    } else {
-        taxon.to_str()
-    };
+) -> Result<(&'static str, Value)> {
+    let taxon_key = species_column(taxon);
 
     // Count species to know page number
 
@@ -143,14 +466,16 @@ pub async fn species_list(
         speciess.valid_name,
         speciess.example_media_path,
         COUNT(DISTINCT occurrences.id),
-        COUNT(medias.id)
+        COUNT(medias.id),
+        speciess.vernacular_name
     FROM
         speciess, occurrences, medias
     WHERE
         speciess.id = occurrences.species AND
         occurrences.id = medias.occurrence AND
         speciess.example_media_path IS NOT NULL AND
-        occurrences.dataset_key != $1 AND
+        occurrences.dataset_key <> ALL($1) AND
+        (cardinality($5::uuid[]) = 0 OR occurrences.dataset_key = ANY($5)) AND
         200 <= medias.status_code AND medias.status_code < 400 AND
         medias.path IS NOT NULL AND
         speciess.{} = $2
@@ -173,7 +498,13 @@ pub async fn species_list(
     );
 
     let offset = (page - 1) as i64 * LIMIT;
-    let arg2: &[&(dyn ToSql + Sync)] = &[&BLACKLISTED_DATASET, &taxon_value, &offset, &LIMIT];
+    let arg2: &[&(dyn ToSql + Sync)] = &[
+        &config.blacklisted_datasets,
+        &taxon_value,
+        &offset,
+        &LIMIT,
+        &config.allowed_datasets,
+    ];
 
     let query2 = db.client().query(&sql, arg2);
 
@@ -192,6 +523,7 @@ pub async fn species_list(
             let media_path = x.get::<usize, String>(9);
             let occurrence_count = x.get::<usize, i64>(10);
             let media_count = x.get::<usize, i64>(10);
+            let vernacular_name = x.get::<usize, Option<String>>(12);
 
             if breadcrumb.is_none() {
                 breadcrumb = Some(vec![
@@ -210,6 +542,7 @@ pub async fn species_list(
                 "valid_name": valid_name,
                 "pretty_name": pretty_name(&valid_name),
                 "pretty_finder": pretty_finder(&valid_name),
+                "vernacular_name": vernacular_name,
                 "media_path": media_path,
                 "occurrence_count": occurrence_count,
                 "media_count": media_count,
@@ -220,19 +553,11 @@ pub async fn species_list(
     let breadcrumb = breadcrumb.unwrap_or(vec![]);
 
     // Keep only the level of the research
-    let breadcrumb = match taxon {
-        Taxon::Reign => &breadcrumb[0..1],
-        Taxon::Phylum => &breadcrumb[0..2],
-        Taxon::Class => &breadcrumb[0..3],
-        Taxon::Order => &breadcrumb[0..4],
-        Taxon::Family => &breadcrumb[0..5],
-        Taxon::Genus => &breadcrumb[0..6],
-        Taxon::Species => &breadcrumb[0..7],
-    };
+    let breadcrumb = &breadcrumb[0..taxon.depth() + 1];
 
     let max_page = species_count / LIMIT + 1;
 
-    tera.render_json(
+    Ok((
         "species.html",
         json!({
             "current_page": page,
@@ -244,50 +569,25 @@ pub async fn species_list(
             "breadcrumb": breadcrumb,
             "breadcrumb_len": breadcrumb.len(),
         }),
-    )
+    ))
 }
 
-/// Route for visualizing medias for a certain species.
+/// Returns the template and the data of the page for visualizing medias for a certain species, or none if there is
+/// no such species.
 pub async fn species_by_valid_name(
     valid_name: &str,
     only_uncropped: bool,
     page: u32,
-    tera: &S<Tera>,
+    config: &S<Config>,
     db: Db,
-) -> Result<Html> {
-    let species = Species::get_by_valid_name(valid_name, &db).await?.unwrap();
-
-    let sql = format!(
-        r#"
-        SELECT
-            COUNT(medias.id),
-            SUM(CASE WHEN medias.x IS NOT NULL OR medias.manual_x IS NOT NULL THEN 1 ELSE 0 END)
-        FROM
-            speciess,
-            occurrences,
-            medias
-        WHERE
-            speciess.valid_name = $1 AND
-            occurrences.species = speciess.id AND
-            medias.occurrence = occurrences.id AND
-            occurrences.dataset_key != $2 AND
-            200 <= medias.status_code AND medias.status_code < 400 {}
-        GROUP BY
-            speciess.id
-        ;
-    "#,
-        if only_uncropped {
-            " AND medias.x IS NULL AND medias.manual_x IS NULL"
-        } else {
-            ""
-        }
-    );
+) -> Result<Option<(&'static str, Value)>> {
+    let Some(species) = Species::get_by_valid_name(valid_name, &db).await? else {
+        return Ok(None);
+    };
 
-    let arg: &[&(dyn ToSql + Sync)] = &[&valid_name, &BLACKLISTED_DATASET];
-    let query = db.client().query(&sql, &arg).await?;
-    let row = query.into_iter().next().unwrap();
-    let medias_len = row.get::<usize, i64>(0);
-    let medias_cropped_len = row.get::<usize, i64>(1);
+    let stats = species.media_stats(only_uncropped, config, &db).await?;
+    let medias_len = stats.downloaded;
+    let medias_cropped_len = stats.cropped;
     let offset = (page - 1) as i64 * LIMIT;
 
     let sql = format!(
@@ -302,8 +602,10 @@ pub async fn species_by_valid_name(
             speciess.valid_name = $1 AND
             occurrences.species = speciess.id AND
             medias.occurrence = occurrences.id AND
-            occurrences.dataset_key != $2 AND
-            200 <= medias.status_code AND medias.status_code < 400 {}
+            occurrences.dataset_key <> ALL($2) AND
+            (cardinality($5::uuid[]) = 0 OR occurrences.dataset_key = ANY($5)) AND
+            200 <= medias.status_code AND medias.status_code < 400 AND
+            medias.path IS NOT NULL {}
         ORDER BY
             medias.id
         OFFSET
@@ -321,20 +623,41 @@ pub async fn species_by_valid_name(
 
     let medias = db
         .client()
-        .query(&sql, &[&valid_name, &BLACKLISTED_DATASET, &offset, &LIMIT])
+        .query(
+            &sql,
+            &[
+                &valid_name,
+                &config.blacklisted_datasets,
+                &offset,
+                &LIMIT,
+                &config.allowed_datasets,
+            ],
+        )
         .await?;
 
     let medias = medias.iter().map(Media::from_row).collect::<Vec<_>>();
     let mut medias_with_occurrences = vec![];
 
+    // Most medias of a species come from the same few datasets
+    let mut datasets = HashMap::new();
+
     for media in medias {
         let occurrence = media.occurrence(&db).await?;
-        medias_with_occurrences.push((media, occurrence));
+
+        if let Entry::Vacant(entry) = datasets.entry(occurrence.dataset_key) {
+            entry.insert(Dataset::get_by_key(occurrence.dataset_key, &db).await?);
+        }
+
+        let dataset = datasets[&occurrence.dataset_key]
+            .as_ref()
+            .map(|x| x.title.clone());
+
+        medias_with_occurrences.push((media, occurrence, dataset));
     }
 
     let max_page = (medias_len as usize / LIMIT as usize) + 1;
 
-    tera.render_json(
+    Ok(Some((
         "species-key.html",
         json!({
             "species": species.to_json(&db).await?,
@@ -346,7 +669,51 @@ pub async fn species_by_valid_name(
             "offset": offset,
             "limit": LIMIT,
         }),
-    )
+    )))
+}
+
+/// The details of a species, as shown on its page.
+#[derive(Debug, Serialize)]
+pub struct SpeciesDetail {
+    /// The species, as given to the template of its page.
+    #[serde(flatten)]
+    pub species: Value,
+
+    /// The numbers of medias of the species.
+    pub medias: MediaStats,
+
+    /// The datasets of the occurrences of the species, with their numbers of occurrences and downloaded medias.
+    pub datasets: Vec<DatasetCount>,
+
+    /// The maximum number of medias of a page of the species.
+    pub page_size: i64,
+
+    /// The number of pages of medias of the species.
+    pub max_page: i64,
+}
+
+/// Returns the details of a species and the numbers of its medias, like its page without the medias.
+#[get("/api/species/<valid_name>")]
+pub async fn species_detail(
+    valid_name: &str,
+    config: &S<Config>,
+    titles: &S<DatasetTitles>,
+    db: Db,
+) -> Result<Json<SpeciesDetail>> {
+    let Some(species) = Species::get_by_valid_name(valid_name, &db).await? else {
+        return Err(Error::SpeciesNotFound(valid_name.to_owned()));
+    };
+
+    let medias = species.media_stats(false, config, &db).await?;
+    let datasets = dataset_counts(&species, false, config, titles, &db).await?;
+
+    Ok(Json(SpeciesDetail {
+        species: species.to_json(&db).await?,
+        max_page: medias.downloaded / LIMIT + 1,
+        page_size: LIMIT,
+        medias,
+        datasets,
+    }))
 }
 
 /// Test route for plotly.
@@ -411,7 +778,11 @@ impl Tree {
 
 /// Routes for dynamic plotly.
 #[get("/plotly/<taxon>/<value>")]
-pub async fn dynamic_plotly(taxon: Taxon, value: &str, db: Db) -> Result<Value> {
+pub async fn dynamic_plotly(
+    taxon: Taxon,
+    value: &str,
+    db: Db,
+) -> Result<Json<Vec<SpeciesMetadata>>> {
     let taxon_str = taxon.to_str();
 
     let null = match taxon {
@@ -457,127 +828,1214 @@ pub async fn dynamic_plotly(taxon: Taxon, value: &str, db: Db) -> Result<Value>
         .map(|x| SpeciesMetadata::from_row(&x))
         .collect::<Vec<_>>();
 
-    Ok(json!(values))
+    Ok(Json(values))
 }
 
-/// Route for visualising a media.
-#[get("/media/<species_key>/<occurrence_key>/<media_index>")]
-pub async fn media(
-    species_key: i64,
-    occurrence_key: i64,
-    media_index: i32,
-    tera: &S<Tera>,
-    db: Db,
-) -> Result<Html> {
-    let species = Species::get_by_species_key(species_key, &db)
-        .await?
-        .unwrap();
+/// Default number of buckets of the confidence histogram.
+const DEFAULT_HISTOGRAM_BINS: u32 = 10;
 
-    let media = Media::get_by_id(media_index, &db).await?.unwrap();
-    let occurrence = media.occurrence(&db).await?;
+/// Maximum number of buckets of the confidence histogram.
+const MAX_HISTOGRAM_BINS: u32 = 100;
 
-    tera.render_json(
-        "media.html",
-        json!({
-            "species": species.to_json(&db).await?,
-            "occurrence": occurrence,
-            "species_pretty_name": pretty_name(&species.valid_name),
-            "species_pretty_finder": pretty_finder(&species.valid_name),
-            "species_key": species_key,
-            "occurrence_key": occurrence_key,
-            "media_id": format!("{:04}", media.id),
-            "media": media,
-        }),
-    )
+/// A bucket of the confidence histogram.
+#[derive(Debug, Serialize)]
+pub struct HistogramBucket {
+    /// The lowest confidence of the bucket.
+    pub min: f64,
+
+    /// The highest confidence of the bucket.
+    pub max: f64,
+
+    /// The number of medias whose confidence is in the bucket.
+    pub count: i64,
 }
 
-/// Route for manually cropping a media.
-#[get("/crop/<species_key>/<occurrence_key>/<media_index>")]
-pub async fn manual_crop(
-    species_key: i64,
-    occurrence_key: i64,
-    media_index: i32,
-    tera: &S<Tera>,
+/// Returns the histogram of the confidences of the crops of the medias of a taxon.
+///
+/// The confidences, between 0 and 1, are counted in `bins` buckets of equal width, in order. Each bucket gives its
+/// bounds and its count, even when it is empty.
+#[get("/api/confidence-histogram/<taxon>/<value>?<bins>")]
+pub async fn confidence_histogram(
+    taxon: Taxon,
+    value: &str,
+    bins: Option<u32>,
+    config: &S<Config>,
     db: Db,
-) -> Result<Html> {
-    let species = Species::get_by_species_key(species_key, &db)
-        .await?
-        .unwrap();
-
-    let media = Media::get_by_id(media_index, &db).await?.unwrap();
+) -> Result<Json<Vec<HistogramBucket>>> {
+    let bins = bins
+        .unwrap_or(DEFAULT_HISTOGRAM_BINS)
+        .clamp(1, MAX_HISTOGRAM_BINS);
 
-    tera.render_json(
-        "crop.html",
-        json!({
-            "species_pretty_name": pretty_name(&species.valid_name),
-            "species_pretty_finder": pretty_finder(&species.valid_name),
-            "species_key": species_key,
-            "occurrence_key": occurrence_key,
-            "media_id": format!("{:04}", media.id),
-            "media": media,
-        }),
-    )
-}
+    // The column comes from type Taxon, so we can safely format it into the SQL query.
+    // width_bucket puts a confidence of exactly 1 in an extra bucket, it goes in the last one instead.
+    let sql = format!(
+        r#"
+        SELECT
+            LEAST(width_bucket(medias.confidence, 0.0::FLOAT8, 1.0::FLOAT8, $2), $2) AS bucket,
+            COUNT(medias.id)
+        FROM
+            medias
+            JOIN occurrences ON medias.occurrence = occurrences.id
+            JOIN speciess ON occurrences.species = speciess.id
+        WHERE
+            speciess.{column} = $1 AND
+            medias.confidence IS NOT NULL AND
+            occurrences.dataset_key <> ALL($3) AND
+            (cardinality($4::uuid[]) = 0 OR occurrences.dataset_key = ANY($4))
+        GROUP BY
+            bucket
+        ;
+    "#,
+        column = species_column(taxon),
+    );
 
-/// A type for receiving bound box from users.
-#[derive(Deserialize)]
-pub struct Bbox {
-    /// The x coordinate of the bounding box, between 0 and 1.
-    pub x: f64,
+    let rows = db
+        .client()
+        .query(
+            &sql,
+            &[
+                &value,
+                &(bins as i32),
+                &config.blacklisted_datasets,
+                &config.allowed_datasets,
+            ],
+        )
+        .await?;
 
-    /// The y coordinate of the bounding box, between 0 and 1.
-    pub y: f64,
+    let mut counts = vec![0; bins as usize];
+    for row in rows {
+        let bucket = row.get::<usize, i32>(0);
+        // Confidences below 0 would be in bucket 0, they are counted with the lowest ones.
+        counts[(bucket.max(1) - 1) as usize] += row.get::<usize, i64>(1);
+    }
 
-    /// The width of the bounding box, between 0 and 1 - x.
-    pub width: f64,
+    let width = 1.0 / bins as f64;
+    let buckets = counts
+        .into_iter()
+        .enumerate()
+        .map(|(i, count)| HistogramBucket {
+            min: i as f64 * width,
+            max: (i + 1) as f64 * width,
+            count,
+        })
+        .collect::<Vec<_>>();
 
-    /// The height of the bounding box, between 0 and 1 - y.
-    pub height: f64,
+    Ok(Json(buckets))
 }
 
-/// Route for manually cropping a media.
-#[post("/crop/<media_index>", format = "json", data = "<data>")]
-pub async fn manual_crop_post(
-    media_index: i32,
-    data: Json<Bbox>,
-    config: &S<Config>,
-    db: Db,
-) -> Result<()> {
-    let mut media = Media::get_by_id(media_index, &db).await?.unwrap();
+/// Maximum number of medias on a contact sheet.
+const CONTACT_SHEET_MAX_MEDIAS: i64 = 50;
 
-    // Save crop in cropped medias
-    let input_path = config
-        .storage
-        .medias_root()
-        .join(media.path.as_ref().unwrap());
-
-    let img = ImageReader::open(input_path).unwrap().decode().unwrap();
-    let img = image::imageops::crop_imm(
-        &img,
-        data.x as u32 - (data.width / 2.0) as u32,
-        data.y as u32 - (data.height / 2.0) as u32,
-        data.width as u32,
-        data.height as u32,
-    )
-    .to_image();
-    let img: DynamicImage = img.into();
-    let img = img.to_rgb8();
+/// Number of medias per row of a contact sheet.
+const CONTACT_SHEET_COLUMNS: u32 = 5;
 
-    let output_path = config
-        .storage
-        .cropped_root()
-        .join(media.path.as_ref().unwrap());
+/// Size in pixels of the square in which each media of a contact sheet fits.
+const CONTACT_SHEET_CELL: u32 = 200;
 
-    img.save(output_path).unwrap();
+/// Height in pixels of the label under each media of a contact sheet.
+const CONTACT_SHEET_LABEL: u32 = 20;
 
-    // Save crop in db
-    media.manual_x = Some(data.x);
-    media.manual_y = Some(data.y);
-    media.manual_width = Some(data.width);
-    media.manual_height = Some(data.height);
-    media.save(&db).await?;
+/// Space in pixels around each media of a contact sheet.
+const CONTACT_SHEET_MARGIN: u32 = 8;
 
-    Ok(())
+/// How long a contact sheet is kept before being composed again.
+const CONTACT_SHEET_TTL: Duration = Duration::from_secs(3600);
+
+/// Maximum number of contact sheets kept in memory.
+const CONTACT_SHEETS_MAX: usize = 64;
+
+/// The digits of the labels of the contact sheets, as 3×5 bitmaps whose rows are read from the top bits.
+const DIGITS: [[u8; 5]; 10] = [
+    [0b111, 0b101, 0b101, 0b101, 0b111],
+    [0b010, 0b110, 0b010, 0b010, 0b111],
+    [0b111, 0b001, 0b111, 0b100, 0b111],
+    [0b111, 0b001, 0b111, 0b001, 0b111],
+    [0b101, 0b101, 0b111, 0b001, 0b001],
+    [0b111, 0b100, 0b111, 0b001, 0b111],
+    [0b111, 0b100, 0b111, 0b101, 0b111],
+    [0b111, 0b001, 0b010, 0b010, 0b010],
+    [0b111, 0b101, 0b111, 0b101, 0b111],
+    [0b111, 0b101, 0b111, 0b001, 0b111],
+];
+
+/// A contact sheet composed recently.
+struct CachedContactSheet {
+    /// When the contact sheet was composed.
+    composed_at: Instant,
+
+    /// The JPEG image.
+    image: Vec<u8>,
+}
+
+/// The contact sheets composed recently, by species key.
+#[derive(Default)]
+pub struct ContactSheets(Mutex<HashMap<i64, CachedContactSheet>>);
+
+/// A JPEG image composed by the server.
+#[derive(Responder)]
+#[response(content_type = "image/jpeg")]
+pub struct JpegImage {
+    /// The JPEG image.
+    image: Vec<u8>,
+
+    /// The header telling how long the image can be kept.
+    cache_control: Header<'static>,
+}
+
+/// Draws a number with the digits bitmaps, its top left corner at some position.
+fn draw_number(image: &mut RgbImage, number: i32, x: u32, y: u32, scale: u32) {
+    for (i, digit) in number.to_string().bytes().enumerate() {
+        let Some(bitmap) = DIGITS.get(digit.wrapping_sub(b'0') as usize) else {
+            continue;
+        };
+
+        let left = x + i as u32 * 4 * scale;
+
+        for (row, bits) in bitmap.iter().enumerate() {
+            for column in 0..3 {
+                if bits & (0b100 >> column) == 0 {
+                    continue;
+                }
+
+                for dx in 0..scale {
+                    for dy in 0..scale {
+                        let (px, py) = (left + column * scale + dx, y + row as u32 * scale + dy);
+                        if px < image.width() && py < image.height() {
+                            image.put_pixel(px, py, Rgb([0, 0, 0]));
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Composes the medias in a grid, each one labeled with its id, and encodes it as a JPEG.
+///
+/// The medias that can't be read are left out.
+fn compose_contact_sheet(medias: Vec<(i32, PathBuf)>) -> Result<Vec<u8>> {
+    let images = medias
+        .into_iter()
+        .filter_map(|(id, path)| {
+            match ImageReader::open(&path).and_then(|x| x.with_guessed_format()) {
+                Ok(reader) => match reader.decode() {
+                    Ok(image) => {
+                        Some((id, image.thumbnail(CONTACT_SHEET_CELL, CONTACT_SHEET_CELL)))
+                    }
+                    Err(e) => {
+                        warn!("failed to decode {}: {}", path.display(), e);
+                        None
+                    }
+                },
+                Err(e) => {
+                    warn!("failed to open {}: {}", path.display(), e);
+                    None
+                }
+            }
+        })
+        .collect::<Vec<_>>();
+
+    let columns = CONTACT_SHEET_COLUMNS.min(images.len().max(1) as u32);
+    let rows = (images.len() as u32).div_ceil(CONTACT_SHEET_COLUMNS).max(1);
+    let cell_width = CONTACT_SHEET_CELL + CONTACT_SHEET_MARGIN;
+    let cell_height = CONTACT_SHEET_CELL + CONTACT_SHEET_LABEL + CONTACT_SHEET_MARGIN;
+
+    let mut sheet = RgbImage::from_pixel(
+        columns * cell_width + CONTACT_SHEET_MARGIN,
+        rows * cell_height + CONTACT_SHEET_MARGIN,
+        Rgb([255, 255, 255]),
+    );
+
+    for (i, (id, image)) in images.into_iter().enumerate() {
+        let left = CONTACT_SHEET_MARGIN + (i as u32 % CONTACT_SHEET_COLUMNS) * cell_width;
+        let top = CONTACT_SHEET_MARGIN + (i as u32 / CONTACT_SHEET_COLUMNS) * cell_height;
+
+        // Centered in its cell, with its label below.
+        let x = left + (CONTACT_SHEET_CELL - image.width()) / 2;
+        let y = top + (CONTACT_SHEET_CELL - image.height()) / 2;
+        image::imageops::overlay(&mut sheet, &image.to_rgb8(), x as i64, y as i64);
+        draw_number(&mut sheet, id, left, top + CONTACT_SHEET_CELL + 4, 2);
+    }
+
+    encode_jpeg(sheet)
+}
+
+/// Maximum number of annotated images kept in memory.
+const ANNOTATED_MEDIAS_MAX: usize = 256;
+
+/// An image annotated recently.
+struct CachedAnnotatedMedia {
+    /// When the image was annotated.
+    annotated_at: Instant,
+
+    /// The box drawn on the image, so that the image is annotated again when the crop changes.
+    crop_box: Option<CropBox>,
+
+    /// The JPEG image.
+    image: Vec<u8>,
+}
+
+/// The images annotated with their crop recently, by media id.
+#[derive(Default)]
+pub struct AnnotatedMedias(Mutex<HashMap<i32, CachedAnnotatedMedia>>);
+
+/// Draws the outline of a rectangle, its inside starting at `(left, top)`, with lines of some thickness drawn outside.
+fn draw_rectangle(
+    image: &mut RgbImage,
+    (left, top, width, height): (i64, i64, i64, i64),
+    thickness: i64,
+    color: Rgb<u8>,
+) {
+    let (outer_left, outer_top) = (left - thickness, top - thickness);
+    let (outer_right, outer_bottom) = (left + width + thickness, top + height + thickness);
+
+    for y in outer_top.max(0)..outer_bottom.min(image.height() as i64) {
+        for x in outer_left.max(0)..outer_right.min(image.width() as i64) {
+            let inside = left <= x && x < left + width && top <= y && y < top + height;
+            if !inside {
+                image.put_pixel(x as u32, y as u32, color);
+            }
+        }
+    }
+}
+
+/// Encodes an image as a JPEG.
+fn encode_jpeg(image: RgbImage) -> Result<Vec<u8>> {
+    let mut bytes = vec![];
+    DynamicImage::ImageRgb8(image)
+        .write_to(&mut Cursor::new(&mut bytes), ImageFormat::Jpeg)
+        .map_err(|e| Error::IoError(io::Error::other(e)))?;
+
+    Ok(bytes)
+}
+
+/// Draws the bounding box of a crop on an image, labeled with its confidence, and encodes it as a JPEG.
+///
+/// As in the media page, the box is white with a black outline so that it can be seen on any background.
+fn annotate_media(path: &Path, crop_box: Option<CropBox>) -> Result<Vec<u8>> {
+    let mut image = ImageReader::open(path)?
+        .with_guessed_format()?
+        .decode()
+        .map_err(|e| Error::IoError(io::Error::other(e)))?
+        .to_rgb8();
+
+    let Some(crop_box) = crop_box else {
+        return encode_jpeg(image);
+    };
+
+    // The lines and the label grow with the image, so that they can be seen once the image is scaled down.
+    let thickness = (image.width().min(image.height()) / 300).max(1) as i64;
+    let scale = (thickness as u32 * 2).max(2);
+
+    let left = (crop_box.x - crop_box.width / 2.0).round() as i64;
+    let top = (crop_box.y - crop_box.height / 2.0).round() as i64;
+    let (width, height) = (
+        crop_box.width.round() as i64,
+        crop_box.height.round() as i64,
+    );
+
+    draw_rectangle(
+        &mut image,
+        (left, top, width, height),
+        thickness,
+        Rgb([255, 255, 255]),
+    );
+    draw_rectangle(
+        &mut image,
+        (
+            left - thickness,
+            top - thickness,
+            width + 2 * thickness,
+            height + 2 * thickness,
+        ),
+        thickness,
+        Rgb([0, 0, 0]),
+    );
+
+    // The label sits on a white background inside the top left corner of the box.
+    if let Some(confidence) = crop_box.confidence {
+        let percents = (confidence * 100.0).round() as i32;
+        let digits = percents.to_string().len() as u32;
+        let (x, y) = (left.max(0) as u32, top.max(0) as u32);
+        let (label_width, label_height) = ((digits * 4 + 1) * scale, 7 * scale);
+
+        for py in y..(y + label_height).min(image.height()) {
+            for px in x..(x + label_width).min(image.width()) {
+                image.put_pixel(px, py, Rgb([255, 255, 255]));
+            }
+        }
+
+        draw_number(&mut image, percents, x + scale, y + scale, scale);
+    }
+
+    encode_jpeg(image)
+}
+
+/// Returns a grid of the cropped medias of a species in a single JPEG, for a quick review of the species.
+///
+/// At most [`CONTACT_SHEET_MAX_MEDIAS`] medias are shown, each one labeled with its id. The last
+/// [`CONTACT_SHEETS_MAX`] contact sheets are kept for [`CONTACT_SHEET_TTL`], so that new crops take a while to appear.
+#[get("/contact-sheet/<file>")]
+pub async fn contact_sheet(
+    file: &str,
+    config: &S<Config>,
+    sheets: &S<ContactSheets>,
+    db: Db,
+) -> Result<Option<JpegImage>> {
+    let Some(Ok(species_key)) = file.strip_suffix(".jpg").map(str::parse::<i64>) else {
+        return Ok(None);
+    };
+
+    let cache_control = Header::new("Cache-Control", "public, max-age=3600");
+
+    if let Some(cached) = sheets.0.lock().unwrap().get(&species_key)
+        && cached.composed_at.elapsed() < CONTACT_SHEET_TTL
+    {
+        return Ok(Some(JpegImage {
+            image: cached.image.clone(),
+            cache_control,
+        }));
+    }
+
+    if Species::get_by_species_key(species_key, &db)
+        .await?
+        .is_none()
+    {
+        return Ok(None);
+    }
+
+    let sql = r#"
+        SELECT
+            medias.id,
+            medias.path
+        FROM
+            medias
+            JOIN occurrences ON medias.occurrence = occurrences.id
+            JOIN speciess ON occurrences.species = speciess.id
+        WHERE
+            speciess.species_key = $1 AND
+            medias.path IS NOT NULL AND
+            (medias.x IS NOT NULL OR medias.manual_x IS NOT NULL) AND
+            occurrences.dataset_key <> ALL($2) AND
+            (cardinality($4::uuid[]) = 0 OR occurrences.dataset_key = ANY($4))
+        ORDER BY
+            medias.id
+        LIMIT
+            $3
+        ;
+    "#;
+
+    let medias = db
+        .client()
+        .query(
+            sql,
+            &[
+                &species_key,
+                &config.blacklisted_datasets,
+                &CONTACT_SHEET_MAX_MEDIAS,
+                &config.allowed_datasets,
+            ],
+        )
+        .await?
+        .into_iter()
+        .map(|row| {
+            let path = config.storage.cropped_root().join(row.get::<_, String>(1));
+            (row.get::<_, i32>(0), path)
+        })
+        .collect::<Vec<_>>();
+
+    let image = spawn_blocking(move || compose_contact_sheet(medias))
+        .await
+        .map_err(|e| Error::IoError(io::Error::other(e)))??;
+
+    let mut cache = sheets.0.lock().unwrap();
+
+    // Make room for the new contact sheet by forgetting the expired ones, or else the oldest one.
+    if cache.len() >= CONTACT_SHEETS_MAX {
+        cache.retain(|_, x| x.composed_at.elapsed() < CONTACT_SHEET_TTL);
+    }
+
+    if cache.len() >= CONTACT_SHEETS_MAX
+        && let Some(oldest) = cache
+            .iter()
+            .min_by_key(|(_, x)| x.composed_at)
+            .map(|(key, _)| *key)
+    {
+        cache.remove(&oldest);
+    }
+
+    let cached = CachedContactSheet {
+        composed_at: Instant::now(),
+        image: image.clone(),
+    };
+    cache.insert(species_key, cached);
+
+    Ok(Some(JpegImage {
+        image,
+        cache_control,
+    }))
+}
+
+/// Lists the values of a taxonomic level that have scraped species under a given value of another level.
+///
+/// For example, `/api/taxa/genus/family/Apidae` lists the genera of the Apidae family that have medias.
+#[get("/api/taxa/<level>/<parent_level>/<parent_value>?<page>")]
+pub async fn taxa(
+    level: Taxon,
+    parent_level: Taxon,
+    parent_value: &str,
+    page: Option<u32>,
+    db: Db,
+) -> Result<Json<Vec<String>>> {
+    // Both columns come from type Taxon, so we can safely format them into the SQL query.
+    let sql = format!(
+        r#"
+        SELECT DISTINCT
+            speciess.{level}
+        FROM
+            speciess
+        WHERE
+            speciess.{parent_level} = $1 AND
+            speciess.example_media_path IS NOT NULL
+        ORDER BY
+            speciess.{level}
+        OFFSET
+            $2
+        LIMIT
+            $3
+        ;
+    "#,
+        level = species_column(level),
+        parent_level = species_column(parent_level),
+    );
+
+    let offset = (page.unwrap_or(1).max(1) - 1) as i64 * API_LIMIT;
+    let rows = db
+        .client()
+        .query(&sql, &[&parent_value, &offset, &API_LIMIT])
+        .await?;
+
+    let values = rows
+        .into_iter()
+        .map(|x| x.get::<usize, String>(0))
+        .collect::<Vec<_>>();
+
+    Ok(Json(values))
+}
+
+/// A scraped species, in the same shape as the items of the species list.
+#[derive(Debug, Serialize)]
+pub struct SpeciesSummary {
+    /// The species key of the species on GBIF.
+    pub species_key: Option<i64>,
+
+    /// The valid name of the species, with its finder.
+    pub valid_name: String,
+
+    /// The valid name of the species, without its finder.
+    pub pretty_name: String,
+
+    /// The finder of the species.
+    pub pretty_finder: String,
+
+    /// The path of the example media of the species.
+    pub media_path: String,
+
+    /// The number of occurrences of the species.
+    pub occurrence_count: i64,
+
+    /// The number of downloaded medias of the species.
+    pub media_count: i64,
+}
+
+/// Returns a random scraped species that has at least one media, in the same shape as the items of the species list.
+#[get("/api/random-species")]
+pub async fn random_species(config: &S<Config>, db: Db) -> Result<Option<Json<SpeciesSummary>>> {
+    let sql = r#"
+        SELECT
+            speciess.species_key,
+            speciess.valid_name,
+            speciess.example_media_path,
+            COUNT(DISTINCT occurrences.id),
+            COUNT(medias.id)
+        FROM
+            (
+                SELECT * FROM speciess
+                WHERE speciess.example_media_path IS NOT NULL
+                ORDER BY random()
+                LIMIT 1
+            ) AS speciess
+            LEFT JOIN occurrences ON
+                speciess.id = occurrences.species AND
+                occurrences.dataset_key <> ALL($1) AND
+                (cardinality($2::uuid[]) = 0 OR occurrences.dataset_key = ANY($2))
+            LEFT JOIN medias ON
+                occurrences.id = medias.occurrence AND
+                200 <= medias.status_code AND medias.status_code < 400 AND
+                medias.path IS NOT NULL
+        GROUP BY
+            speciess.species_key,
+            speciess.valid_name,
+            speciess.example_media_path
+        ;
+    "#;
+
+    let row = match db
+        .client()
+        .query_opt(
+            sql,
+            &[&config.blacklisted_datasets, &config.allowed_datasets],
+        )
+        .await?
+    {
+        Some(row) => row,
+        None => return Ok(None),
+    };
+
+    let valid_name = row.get::<usize, String>(1);
+
+    Ok(Some(Json(SpeciesSummary {
+        species_key: row.get::<usize, Option<i64>>(0),
+        pretty_name: pretty_name(&valid_name),
+        pretty_finder: pretty_finder(&valid_name),
+        valid_name,
+        media_path: row.get::<usize, String>(2),
+        occurrence_count: row.get::<usize, i64>(3),
+        media_count: row.get::<usize, i64>(4),
+    })))
+}
+
+/// A JSON response that clients and proxies can keep for a while.
+#[derive(Responder)]
+pub struct Cached<T> {
+    /// The content of the response.
+    value: Json<T>,
+
+    /// The header telling how long the response can be kept.
+    cache_control: Header<'static>,
+}
+
+/// A blacklisted dataset.
+#[derive(Debug, Serialize)]
+pub struct BlacklistedDataset {
+    /// The key of the dataset on GBIF.
+    pub key: Uuid,
+
+    /// The title of the dataset on GBIF, if it could be fetched.
+    pub title: Option<String>,
+}
+
+/// Lists the blacklisted datasets, whose occurrences are neither scraped nor shown, with their titles on GBIF.
+///
+/// The title is null if it could not be fetched.
+#[get("/api/blacklist")]
+pub async fn blacklist(
+    config: &S<Config>,
+    titles: &S<DatasetTitles>,
+) -> Cached<Vec<BlacklistedDataset>> {
+    let mut datasets = vec![];
+
+    for key in &config.blacklisted_datasets {
+        datasets.push(BlacklistedDataset {
+            key: *key,
+            title: titles.get(*key).await,
+        });
+    }
+
+    Cached {
+        value: Json(datasets),
+        cache_control: Header::new("Cache-Control", "public, max-age=86400"),
+    }
+}
+
+/// The sitemap of the gallery, in XML.
+#[derive(Responder)]
+#[response(content_type = "xml")]
+pub struct Sitemap<T> {
+    /// The content of the sitemap.
+    stream: TextStream<T>,
+
+    /// The header telling how long the sitemap can be kept.
+    cache_control: Header<'static>,
+}
+
+/// Escapes the characters of a text that are special in XML.
+fn xml_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+/// Exposes the counters of the server and of the last scrap in the Prometheus text format.
+#[get("/metrics")]
+pub async fn metrics(
+    config: &S<Config>,
+    metrics: &S<Metrics>,
+    pool: &S<Pool>,
+) -> (ContentType, String) {
+    let status = pool.status();
+    let pool = PoolUsage {
+        in_use: status.size.saturating_sub(status.available.max(0) as usize),
+        max_size: status.max_size,
+    };
+
+    let last_scrap = ScrapSummary::read(&config.storage.last_scrap_path());
+
+    let content_type = ContentType::new("text", "plain").with_params(("version", "0.0.4"));
+    (content_type, metrics.render(pool, last_scrap.as_ref()))
+}
+
+/// Lists the pages of the gallery for search engines: the page of every species that has medias, and the listing
+/// pages of the taxa above them.
+///
+/// The sitemap is streamed as the rows come from the database, so that big catalogs don't need to be held in memory.
+#[get("/sitemap.xml")]
+pub async fn sitemap(config: &S<Config>, db: Db) -> Sitemap<impl Stream<Item = String>> {
+    let root = config.root.trim_end_matches('/').to_owned();
+
+    let levels = Taxon::all()
+        .iter()
+        .map(|taxon| {
+            // The column comes from type Taxon, so we can safely format it into the SQL query.
+            format!(
+                "SELECT DISTINCT '{taxon}', speciess.{column} FROM speciess WHERE speciess.example_media_path IS NOT NULL",
+                taxon = taxon.to_str(),
+                column = species_column(*taxon),
+            )
+        })
+        .collect::<Vec<_>>()
+        .join(" UNION ALL ");
+
+    let stream = TextStream! {
+        yield String::from(r#"<?xml version="1.0" encoding="UTF-8"?>"#);
+        yield String::from("\n<urlset xmlns=\"http://www.sitemaps.org/schemas/sitemap/0.9\">\n");
+
+        let params: [&(dyn ToSql + Sync); 0] = [];
+        match db.client().query_raw(&levels, params).await {
+            Ok(rows) => {
+                let mut rows = std::pin::pin!(rows);
+                while let Some(row) = rows.next().await {
+                    let row = match row {
+                        Ok(row) => row,
+                        Err(e) => {
+                            error!("failed to read the sitemap rows: {}", e);
+                            break;
+                        }
+                    };
+
+                    let taxon = row.get::<usize, &str>(0);
+                    let value = row.get::<usize, &str>(1);
+                    let loc = format!("{}/species/{}/{}/1", root, taxon, RawStr::new(value).percent_encode());
+                    yield format!("<url><loc>{}</loc></url>\n", xml_escape(&loc));
+                }
+            }
+
+            Err(e) => error!("failed to list the sitemap pages: {}", e),
+        }
+
+        yield String::from("</urlset>\n");
+    };
+
+    Sitemap {
+        stream,
+        cache_control: Header::new("Cache-Control", "public, max-age=3600"),
+    }
+}
+
+/// Route for visualising a media, which is not found if either the species or the media doesn't exist.
+#[get("/media/<species_key>/<occurrence_key>/<media_index>")]
+pub async fn media(
+    species_key: i64,
+    occurrence_key: i64,
+    media_index: i32,
+    json: AcceptJson,
+    tera: &S<Tera>,
+    db: Db,
+) -> Result<Option<Page>> {
+    let Some(species) = Species::get_by_species_key(species_key, &db).await? else {
+        return Ok(None);
+    };
+
+    let Some(media) = Media::get_by_id(media_index, &db).await? else {
+        return Ok(None);
+    };
+    let occurrence = media.occurrence(&db).await?;
+    let dataset = Dataset::get_by_key(occurrence.dataset_key, &db).await?;
+
+    tera.render_page(
+        "media.html",
+        json!({
+            "species": species.to_json(&db).await?,
+            "occurrence": occurrence,
+            "dataset": dataset,
+            "species_pretty_name": pretty_name(&species.valid_name),
+            "species_vernacular_name": species.vernacular_name,
+            "species_pretty_finder": pretty_finder(&species.valid_name),
+            "species_key": species_key,
+            "occurrence_key": occurrence_key,
+            "media_id": format!("{:04}", media.id),
+            "media": media,
+        }),
+        json,
+    )
+    .map(Some)
+}
+
+/// Returns the bounding box of the crop of a media, or 204 if the media is not cropped.
+///
+/// The coordinates are the center, width and height of the box, in pixels of the original image. The manual box is
+/// returned if there is one, otherwise the box found by the model, with its confidence.
+#[get("/api/media/<media_id>/box")]
+pub async fn media_box(media_id: i32, db: Db) -> Result<Option<StdResult<Json<CropBox>, Status>>> {
+    let media = match Media::get_by_id(media_id, &db).await? {
+        Some(media) => media,
+        None => return Ok(None),
+    };
+
+    match media.crop_box() {
+        Some(crop_box) => Ok(Some(Ok(Json(crop_box)))),
+        None => Ok(Some(Err(Status::NoContent))),
+    }
+}
+
+/// Returns the original image of a media with the bounding box of its crop drawn on it, labeled with the confidence
+/// in percents, or the plain image if the media is not cropped.
+///
+/// The annotated images are kept until the crop of the media changes.
+#[get("/api/media/<media_id>/annotated.jpg")]
+pub async fn annotated_media(
+    media_id: i32,
+    config: &S<Config>,
+    annotated: &S<AnnotatedMedias>,
+    db: Db,
+) -> Result<Option<JpegImage>> {
+    let Some(media) = Media::get_by_id(media_id, &db).await? else {
+        return Ok(None);
+    };
+
+    let Some(path) = &media.path else {
+        return Ok(None);
+    };
+
+    let crop_box = media.crop_box();
+    let cache_control = Header::new("Cache-Control", "no-cache");
+
+    if let Some(cached) = annotated.0.lock().unwrap().get(&media_id)
+        && cached.crop_box == crop_box
+    {
+        return Ok(Some(JpegImage {
+            image: cached.image.clone(),
+            cache_control,
+        }));
+    }
+
+    let path = config.storage.medias_root().join(path);
+    if !path.exists() {
+        return Ok(None);
+    }
+
+    let image = spawn_blocking(move || annotate_media(&path, crop_box))
+        .await
+        .map_err(|e| Error::IoError(io::Error::other(e)))??;
+
+    let mut cache = annotated.0.lock().unwrap();
+
+    // Make room for the new image by forgetting the oldest one.
+    if cache.len() >= ANNOTATED_MEDIAS_MAX
+        && let Some(oldest) = cache
+            .iter()
+            .min_by_key(|(_, x)| x.annotated_at)
+            .map(|(id, _)| *id)
+    {
+        cache.remove(&oldest);
+    }
+
+    let cached = CachedAnnotatedMedia {
+        annotated_at: Instant::now(),
+        crop_box,
+        image: image.clone(),
+    };
+    cache.insert(media_id, cached);
+
+    Ok(Some(JpegImage {
+        image,
+        cache_control,
+    }))
+}
+
+/// A feedback on the crop of a media, sent by a visitor.
+#[derive(Deserialize)]
+pub struct Feedback {
+    /// Whether the crop is correct.
+    pub crop_ok: bool,
+
+    /// A free comment on the crop.
+    #[serde(default)]
+    pub note: Option<String>,
+}
+
+/// The feedback that was stored.
+#[derive(Debug, Serialize)]
+pub struct FeedbackCreated {
+    /// The id of the feedback.
+    pub id: i32,
+}
+
+/// Stores a feedback on the crop of a media.
+///
+/// Each client can only send a few feedbacks in a given period, after which the route answers 429.
+#[post("/api/media/<media_id>/feedback", format = "json", data = "<data>")]
+pub async fn media_feedback(
+    media_id: i32,
+    data: Json<Feedback>,
+    ip: ClientIp,
+    limiter: &S<FeedbackLimiter>,
+    db: Db,
+) -> Result<Option<StdResult<Json<FeedbackCreated>, Status>>> {
+    if !limiter.allow(&ip.0) {
+        warn!("too many feedbacks from {}", ip.0);
+        return Ok(Some(Err(Status::TooManyRequests)));
+    }
+
+    let media = match Media::get_by_id(media_id, &db).await? {
+        Some(media) => media,
+        None => return Ok(None),
+    };
+
+    let Feedback { crop_ok, note } = data.into_inner();
+
+    let note = note
+        .map(|x| {
+            x.trim()
+                .chars()
+                .take(FEEDBACK_NOTE_MAX_CHARS)
+                .collect::<String>()
+        })
+        .filter(|x| !x.is_empty());
+
+    let feedback = MediaFeedback::create(&media, crop_ok, note, Utc::now(), ip.0)
+        .save(&db)
+        .await?;
+
+    Ok(Some(Ok(Json(FeedbackCreated { id: feedback.id }))))
+}
+
+/// Deletes a media, with its files and its feedbacks, so that curators can prune bad images from the gallery.
+///
+/// If the files can't be removed, the media is kept and the route fails, see [`Media::delete_with_files`].
+#[delete("/api/media/<media_id>")]
+pub async fn delete_media(
+    _auth: AuthGuard,
+    media_id: i32,
+    config: &S<Config>,
+    db: Db,
+) -> Result<Option<()>> {
+    let media = match Media::get_by_id(media_id, &db).await? {
+        Some(media) => media,
+        None => return Ok(None),
+    };
+
+    info!("deleting media {} ({})", media.id, media.url);
+    media.delete_with_files(&config.storage, &db).await?;
+
+    Ok(Some(()))
+}
+
+/// Lists the species that are ignored because they share their species key with another species.
+#[get("/api/ignored-species")]
+pub async fn ignored_species(_auth: AuthGuard, db: Db) -> Result<Json<Vec<IgnoredSpecies>>> {
+    Ok(Json(IgnoredSpecies::list(&db).await?))
+}
+
+/// Removes an ignored species, so that the next scrap evaluates it again.
+#[delete("/api/ignored-species/<valid_name>")]
+pub async fn clear_ignored_species(
+    _auth: AuthGuard,
+    valid_name: &str,
+    db: Db,
+) -> Result<Option<()>> {
+    if IgnoredSpecies::clear(Some(valid_name), &db).await? == 0 {
+        return Ok(None);
+    }
+
+    info!("ignored species \"{}\" removed", valid_name);
+    Ok(Some(()))
+}
+
+/// An occurrence with its number of downloaded medias.
+#[derive(Debug, Serialize)]
+pub struct OccurrenceWithMedias {
+    /// The occurrence.
+    #[serde(flatten)]
+    pub occurrence: Occurrence,
+
+    /// The number of downloaded medias of the occurrence.
+    pub media_count: i64,
+}
+
+/// A page of the occurrences of a species.
+#[derive(Debug, Serialize)]
+pub struct OccurrencesPage {
+    /// The number of the page, starting at 1.
+    pub page: u32,
+
+    /// The maximum number of occurrences of a page.
+    pub page_size: i64,
+
+    /// The number of occurrences of the species.
+    pub total: i64,
+
+    /// The occurrences of the page.
+    pub occurrences: Vec<OccurrenceWithMedias>,
+}
+
+/// Lists the datasets of the occurrences of a species, with their numbers of occurrences and downloaded medias.
+///
+/// The blacklisted datasets are left out, unless `blacklisted` is true in which case they are flagged as such. The
+/// titles of the datasets that were not fetched when scraping are fetched from GBIF.
+#[get("/api/species/<species_key>/datasets?<blacklisted>")]
+pub async fn species_datasets(
+    species_key: i64,
+    blacklisted: Option<bool>,
+    config: &S<Config>,
+    titles: &S<DatasetTitles>,
+    db: Db,
+) -> Result<Option<Json<Vec<DatasetCount>>>> {
+    let Some(species) = Species::get_by_species_key(species_key, &db).await? else {
+        return Ok(None);
+    };
+
+    let datasets =
+        dataset_counts(&species, blacklisted.unwrap_or(false), config, titles, &db).await?;
+    Ok(Some(Json(datasets)))
+}
+
+/// Counts the occurrences and medias of a species by dataset, fetching from GBIF the titles of the datasets that were
+/// not fetched when scraping.
+async fn dataset_counts(
+    species: &Species,
+    include_blacklisted: bool,
+    config: &Config,
+    titles: &DatasetTitles,
+    db: &Db,
+) -> Result<Vec<DatasetCount>> {
+    let mut datasets = species
+        .dataset_counts(include_blacklisted, config, db)
+        .await?;
+
+    for dataset in &mut datasets {
+        if dataset.title.is_none() {
+            dataset.title = titles.get(dataset.key).await;
+        }
+    }
+
+    Ok(datasets)
+}
+
+/// Lists the occurrences of a species, with their number of downloaded medias, leaving out the blacklisted datasets.
+///
+/// The occurrences are ordered by key and paginated like the rest of the JSON API. It comes after
+/// [`occurrences_json`], which serves the keys followed by `.json`.
+#[get("/api/occurrences/<species_key>?<page>", rank = 1)]
+pub async fn occurrences(
+    species_key: i64,
+    page: Option<u32>,
+    config: &S<Config>,
+    db: Db,
+) -> Result<Option<Json<OccurrencesPage>>> {
+    let Some(species) = Species::get_by_species_key(species_key, &db).await? else {
+        return Ok(None);
+    };
+
+    let sql = r#"
+        SELECT COUNT(*)
+        FROM occurrences
+        WHERE
+            occurrences.species = $1 AND
+            occurrences.dataset_key <> ALL($2) AND
+            (cardinality($3::uuid[]) = 0 OR occurrences.dataset_key = ANY($3))
+        ;
+    "#;
+
+    let total = db
+        .client()
+        .query_one(
+            sql,
+            &[
+                &species.id,
+                &config.blacklisted_datasets,
+                &config.allowed_datasets,
+            ],
+        )
+        .await?
+        .get::<usize, i64>(0);
+
+    let sql = r#"
+        SELECT
+            occurrences.*,
+            COUNT(medias.id)
+        FROM
+            occurrences
+            LEFT JOIN medias ON
+                occurrences.id = medias.occurrence AND
+                200 <= medias.status_code AND medias.status_code < 400 AND
+                medias.path IS NOT NULL
+        WHERE
+            occurrences.species = $1 AND
+            occurrences.dataset_key <> ALL($2) AND
+            (cardinality($5::uuid[]) = 0 OR occurrences.dataset_key = ANY($5))
+        GROUP BY
+            occurrences.id
+        ORDER BY
+            occurrences.key
+        OFFSET
+            $3
+        LIMIT
+            $4
+        ;
+    "#;
+
+    let page = page.unwrap_or(1).max(1);
+    let offset = (page - 1) as i64 * API_LIMIT;
+    let rows = db
+        .client()
+        .query(
+            sql,
+            &[
+                &species.id,
+                &config.blacklisted_datasets,
+                &offset,
+                &API_LIMIT,
+                &config.allowed_datasets,
+            ],
+        )
+        .await?;
+
+    let occurrences = rows
+        .iter()
+        .map(|row| OccurrenceWithMedias {
+            occurrence: Occurrence::from_row(row),
+            media_count: row.get::<usize, i64>(row.len() - 1),
+        })
+        .collect::<Vec<_>>();
+
+    Ok(Some(Json(OccurrencesPage {
+        page,
+        page_size: API_LIMIT,
+        total,
+        occurrences,
+    })))
+}
+
+/// The name of the file of the occurrences of a species, its species key followed by `.json`.
+pub struct OccurrencesFile(i64);
+
+impl<'a> FromParam<'a> for OccurrencesFile {
+    type Error = &'a str;
+
+    fn from_param(param: &'a str) -> StdResult<Self, Self::Error> {
+        param
+            .strip_suffix(".json")
+            .and_then(|x| x.parse().ok())
+            .map(OccurrencesFile)
+            .ok_or(param)
+    }
+}
+
+/// Returns the occurrences of a species as they were received from GBIF during scraping.
+///
+/// The file name must be the species key followed by `.json`, anything else is forwarded to [`occurrences`].
+#[get("/api/occurrences/<file>")]
+pub async fn occurrences_json(file: OccurrencesFile, config: &S<Config>) -> Option<NamedFile> {
+    // Parsing the key guarantees that the path can't leave the species directory.
+    NamedFile::open(
+        config
+            .storage
+            .species_dir()
+            .join(format!("{}.json", file.0)),
+    )
+    .await
+    .ok()
+}
+
+/// Route for manually cropping a media.
+#[get("/crop/<species_key>/<occurrence_key>/<media_index>")]
+pub async fn manual_crop(
+    _auth: AuthGuard,
+    species_key: i64,
+    occurrence_key: i64,
+    media_index: i32,
+    tera: &S<Tera>,
+    db: Db,
+) -> Result<Html> {
+    let species = Species::get_by_species_key(species_key, &db)
+        .await?
+        .unwrap();
+
+    let media = Media::get_by_id(media_index, &db).await?.unwrap();
+
+    tera.render_json(
+        "crop.html",
+        json!({
+            "species_pretty_name": pretty_name(&species.valid_name),
+            "species_vernacular_name": species.vernacular_name,
+            "species_pretty_finder": pretty_finder(&species.valid_name),
+            "species_key": species_key,
+            "occurrence_key": occurrence_key,
+            "media_id": format!("{:04}", media.id),
+            "media": media,
+        }),
+    )
+}
+
+/// A type for receiving bound box from users.
+#[derive(Deserialize)]
+pub struct Bbox {
+    /// The x coordinate of the center of the bounding box, in pixels of the original image.
+    pub x: f64,
+
+    /// The y coordinate of the center of the bounding box, in pixels of the original image.
+    pub y: f64,
+
+    /// The width of the bounding box, in pixels.
+    pub width: f64,
+
+    /// The height of the bounding box, in pixels.
+    pub height: f64,
+}
+
+/// Route for manually cropping a media.
+#[post("/crop/<media_index>", format = "json", data = "<data>")]
+pub async fn manual_crop_post(
+    _auth: AuthGuard,
+    media_index: i32,
+    data: Json<Bbox>,
+    config: &S<Config>,
+    db: Db,
+) -> Result<()> {
+    let mut media = Media::get_by_id(media_index, &db).await?.unwrap();
+
+    // Save crop in cropped medias
+    let crop_box = CropBox {
+        x: data.x,
+        y: data.y,
+        width: data.width,
+        height: data.height,
+        confidence: None,
+        manual: true,
+    };
+    media.write_crop(&crop_box, &config.storage)?;
+
+    // Save crop in db
+    media.manual_x = Some(data.x);
+    media.manual_y = Some(data.y);
+    media.manual_width = Some(data.width);
+    media.manual_height = Some(data.height);
+    media.save(&db).await?;
+
+    Ok(())
 }
 
 /// Route for static files.
@@ -586,19 +2044,120 @@ async fn static_files(file: PathBuf) -> Option<NamedFile> {
     NamedFile::open(Path::new("static/").join(file)).await.ok()
 }
 
-/// Route for scraped data.
-#[get("/data/<file..>")]
-async fn data_files(config: &S<Config>, file: PathBuf) -> Option<NamedFile> {
+/// Route for the downloaded medias, which are public since they are shown in the gallery.
+#[get("/data/medias/<file..>")]
+async fn media_files(config: &S<Config>, file: PathBuf) -> Option<NamedFile> {
+    NamedFile::open(config.storage.medias_root().join(file))
+        .await
+        .ok()
+}
+
+/// Route for the cropped medias, which are public since they are shown in the gallery.
+#[get("/data/medias_cropped/<file..>")]
+async fn cropped_media_files(config: &S<Config>, file: PathBuf) -> Option<NamedFile> {
+    NamedFile::open(config.storage.cropped_root().join(file))
+        .await
+        .ok()
+}
+
+/// Route for the rest of the scraped data, such as logs, which is only available to admins.
+#[get("/data/<file..>", rank = 2)]
+async fn data_files(_auth: AuthGuard, config: &S<Config>, file: PathBuf) -> Option<NamedFile> {
     NamedFile::open(config.storage.data_path.join(file))
         .await
         .ok()
 }
 
+/// Response to requests without valid admin credentials.
+#[derive(Responder)]
+#[response(status = 401)]
+pub struct Unauthorized {
+    /// The error page.
+    page: Html,
+
+    /// The header that makes browsers prompt for credentials.
+    authenticate: Header<'static>,
+}
+
+/// Response to clients that made too many requests.
+#[derive(Responder)]
+#[response(status = 429)]
+pub struct TooManyRequests {
+    /// The error message.
+    message: &'static str,
+
+    /// The header telling how long to wait before making a request again.
+    retry_after: Header<'static>,
+}
+
+/// Route to which the rate limit fairing sends the requests of clients that made too many requests.
+#[get("/rate-limited")]
+pub fn rate_limited(config: &S<Config>) -> TooManyRequests {
+    let per_minute = config.rate_limit_per_minute.unwrap_or(1).max(1);
+
+    TooManyRequests {
+        message: "Trop de requêtes, réessayez plus tard.",
+        retry_after: Header::new("Retry-After", (60 / per_minute).max(1).to_string()),
+    }
+}
+
+/// Renders an error page with the tera instance managed by rocket.
+fn render_error_page(request: &Request, template_name: &str) -> Result<Html> {
+    let tera = request
+        .rocket()
+        .state::<Tera>()
+        .ok_or(Error::InternalServerError)?;
+
+    tera.render_json(template_name, json!({ "uri": request.uri().to_string() }))
+}
+
+/// Catcher for requests to admin routes without valid credentials.
+#[catch(401)]
+pub fn unauthorized(request: &Request) -> Result<Unauthorized> {
+    Ok(Unauthorized {
+        page: render_error_page(request, "401.html")?,
+        authenticate: Header::new("WWW-Authenticate", "Basic realm=\"scraper\""),
+    })
+}
+
+/// Catcher for unknown routes.
+#[catch(404)]
+pub fn not_found(request: &Request) -> Result<Html> {
+    render_error_page(request, "404.html")
+}
+
+/// Catcher for internal server errors.
+///
+/// The underlying error is logged here, the user only gets a friendly page without details.
+#[catch(500)]
+pub fn internal_server_error(status: Status, request: &Request) -> Result<Html> {
+    match &request.local_cache(|| CaughtError(None)).0 {
+        Some(e) => error!("{}", e),
+        None => error!("{} on {} {}", status, request.method(), request.uri()),
+    }
+
+    render_error_page(request, "500.html")
+}
+
 /// Starts the web server.
 pub async fn serve() -> StdResult<Rocket<Ignite>, rocket::Error> {
-    rocket::build()
+    let figment = rocket::Config::figment();
+    let config = Config::from_figment(&figment);
+
+    // Ctrl-c is handled by the shutdown fairing, so that the shutdown sequence is logged.
+    let figment = figment
+        .merge(("shutdown.ctrlc", false))
+        .merge(("shutdown.grace", config.shutdown_grace_secs));
+
+    let rocket = rocket::custom(figment)
         .attach(AdHoc::on_ignite("Config", |rocket| async move {
             let config = Config::from_rocket(&rocket);
+            set_gbif_root(&config.gbif_root);
+            set_gbif_timeouts(
+                Duration::from_secs(config.connect_timeout_secs),
+                Duration::from_secs(config.request_timeout_secs),
+            );
+            RATE_LIMITER.set_rate(config.gbif_requests_per_second);
             rocket.manage(config)
         }))
         .attach(AdHoc::on_ignite("Database", |rocket| async move {
@@ -617,7 +2176,41 @@ pub async fn serve() -> StdResult<Rocket<Ignite>, rocket::Error> {
             tera.autoescape_on(vec![".html"]);
             rocket.manage(tera)
         }))
+        .manage(FeedbackLimiter::default())
+        .manage(DatasetTitles::default())
+        .manage(ContactSheets::default())
+        .manage(AnnotatedMedias::default())
+        .manage(Metrics::default())
+        .attach(AdHoc::on_liftoff("Shutdown", |rocket| {
+            Box::pin(async move {
+                let shutdown = rocket.shutdown();
+                let grace = rocket.config().shutdown.grace;
+
+                tokio::spawn(async move {
+                    if let Err(e) = tokio::signal::ctrl_c().await {
+                        error!("failed to listen for ctrl-c: {}", e);
+                        return;
+                    }
+
+                    info!(
+                        "received ctrl-c, waiting up to {}s for the requests in flight",
+                        grace
+                    );
+                    shutdown.notify();
+                });
+            })
+        }))
+        .attach(AdHoc::on_shutdown("Shutdown", |_| {
+            Box::pin(async move {
+                info!("server is shutting down, no longer accepting connections");
+            })
+        }))
         .attach(LogFairing)
+        .attach(RateLimitFairing::default())
+        .register(
+            "/",
+            catchers![unauthorized, not_found, internal_server_error],
+        )
         .mount(
             "/",
             routes![
@@ -626,15 +2219,38 @@ pub async fn serve() -> StdResult<Rocket<Ignite>, rocket::Error> {
                 uncropped_species,
                 plotly,
                 dynamic_plotly,
+                taxa,
+                confidence_histogram,
+                contact_sheet,
+                random_species,
+                blacklist,
+                sitemap,
+                metrics,
+                media_box,
+                annotated_media,
+                media_feedback,
+                delete_media,
+                ignored_species,
+                clear_ignored_species,
+                species_detail,
+                species_datasets,
+                occurrences,
+                occurrences_json,
                 media,
                 manual_crop,
                 manual_crop_post,
                 static_files,
+                media_files,
+                cropped_media_files,
                 data_files,
+                rate_limited,
             ],
         )
         .ignite()
         .await?
         .launch()
-        .await
+        .await;
+
+    info!("server stopped");
+    rocket
 }
@@ -1,44 +1,222 @@
 //! Module that helps us deal with taxref.
 
+use std::collections::{BTreeSet, HashSet};
 use std::fmt;
-use std::fs::File;
-use std::io::{BufRead, BufReader};
-use std::path::PathBuf;
+use std::fs::{File, create_dir_all, remove_file, rename};
+use std::io::{BufRead, BufReader, BufWriter};
+use std::path::{Path, PathBuf};
 use std::result::Result as StdResult;
 use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+use serde::{Deserialize, Serialize};
+
+use futures::stream::{self, Stream};
+
+use rayon::ThreadPoolBuilder;
+use rayon::prelude::*;
+
+use tokio::sync::mpsc::channel;
+use tokio::task::spawn_blocking;
+
+use flate2::read::GzDecoder;
+
+use sha2::{Digest, Sha256};
 
 use rocket::request::FromParam;
 
+use crate::config::TaxrefConfig;
 use crate::db::SpeciesTrait;
 use crate::utils;
 use crate::{Error, Result};
 
+/// The number of entries found by [`Entry::stream_taxon`] that can wait to be consumed.
+const STREAM_CAPACITY: usize = 64;
+
+/// The number of lines of taxref read at once when it is parsed by several threads.
+const PARALLEL_CHUNK_LINES: usize = 16_384;
+
+/// The entries of a taxref file that pass a filter.
+type FilteredEntries = (PathBuf, TaxrefFilter, Arc<Vec<Entry>>);
+
+/// The entries of each taxref file that pass each filter, loaded once and kept in memory.
+static FILTERED_ENTRIES: Mutex<Vec<FilteredEntries>> = Mutex::new(vec![]);
+
 /// Retrieves the path of the taxref file on the local disk.
-pub fn path() -> Option<PathBuf> {
+///
+/// The name of the file is the last segment of the download url, so that a gzipped taxref keeps its `.gz` extension.
+pub fn path(config: &TaxrefConfig) -> Option<PathBuf> {
+    let filename = config
+        .url
+        .split(['?', '#'])
+        .next()?
+        .rsplit('/')
+        .next()
+        .filter(|x| !x.is_empty())
+        .unwrap_or("TAXREFv17.txt");
+
     let mut target = dirs::cache_dir()?;
     target.push("gbif-scraper");
-    target.push("TAXREFv17.txt");
+    target.push(filename);
     Some(target)
 }
 
+/// The entries of taxref matching a taxonomic level, saved on the disk with the modification time of the taxref file
+/// they were read from.
+#[derive(Serialize, Deserialize)]
+struct CachedEntries {
+    /// The modification time of the taxref file when the entries were read.
+    modified: SystemTime,
+
+    /// The entries that matched.
+    entries: Vec<Entry>,
+}
+
+/// Retrieves the path of the file caching the entries whose taxonomic level `taxon` is any of the queries.
+///
+/// The file is named after a hash of the taxref file name, the queries and the filter, so that a new version of taxref
+/// or another filter never reads the entries of another one.
+fn cached_entries_path(taxon: Taxon, queries: &[&str], config: &TaxrefConfig) -> Option<PathBuf> {
+    let taxref = path(config)?;
+
+    let mut queries = queries.iter().map(|x| x.to_lowercase()).collect::<Vec<_>>();
+    queries.sort();
+    queries.dedup();
+
+    let mut ranks = config.filter.allowed_ranks.iter().collect::<Vec<_>>();
+    ranks.sort();
+
+    let key = format!(
+        "{}\n{}\n{}\n{}\n{}\n{:?}",
+        taxref.file_name()?.to_string_lossy(),
+        taxon,
+        queries.join(","),
+        config.filter.include_marine,
+        config.filter.include_non_france,
+        ranks,
+    );
+
+    let hash = format!("{:x}", Sha256::digest(key.as_bytes()));
+    Some(taxref.parent()?.join("taxa").join(format!("{}.json", hash)))
+}
+
+/// Reads cached entries, if they were read from the taxref file as it is now.
+fn read_cached_entries(path: &Path, config: &TaxrefConfig) -> Option<Vec<Entry>> {
+    let modified = self::path(config)?.metadata().ok()?.modified().ok()?;
+    let cached: CachedEntries =
+        serde_json::from_reader(BufReader::new(File::open(path).ok()?)).ok()?;
+    (cached.modified == modified).then_some(cached.entries)
+}
+
+/// Saves the entries read from the taxref file, replacing the ones that were cached.
+fn write_cached_entries(path: &Path, config: &TaxrefConfig, entries: Vec<Entry>) -> Result<()> {
+    let modified = self::path(config)
+        .ok_or(Error::NoCache)?
+        .metadata()?
+        .modified()?;
+
+    if let Some(parent) = path.parent() {
+        create_dir_all(parent)?;
+    }
+
+    // Write next to the final file then move it, so that an interrupted write is never read.
+    let mut part = path.to_owned().into_os_string();
+    part.push(".part");
+    let part = PathBuf::from(part);
+
+    serde_json::to_writer(
+        BufWriter::new(File::create(&part)?),
+        &CachedEntries { modified, entries },
+    )?;
+    rename(&part, path)?;
+
+    Ok(())
+}
+
+/// Returns true if the file is gzipped, either by its extension or by its magic numbers.
+fn is_gzipped(path: &Path) -> Result<bool> {
+    if path.extension().map(|x| x == "gz").unwrap_or(false) {
+        return Ok(true);
+    }
+
+    Ok(infer::get_from_path(path)?
+        .map(|x| x.mime_type() == "application/gzip")
+        .unwrap_or(false))
+}
+
+/// Opens the taxref file for reading lines, decompressing it on the fly if it is gzipped.
+pub fn open(config: &TaxrefConfig) -> Result<Box<dyn BufRead>> {
+    let path = path(config).ok_or(Error::NoCache)?;
+    let file = File::open(&path)?;
+
+    if is_gzipped(&path)? {
+        Ok(Box::new(BufReader::new(GzDecoder::new(file))))
+    } else {
+        Ok(Box::new(BufReader::new(file)))
+    }
+}
+
 /// Downloads taxref in the cache directory.
 ///
-/// Returns the path to the taxref file.
-pub async fn download() -> Result<()> {
-    let target = path().ok_or(Error::NoCache)?;
+/// The file is only moved to its final location once it is completely downloaded, and is removed if it doesn't have
+/// the expected size and hash, so that a crashed or truncated download is never mistaken for a complete one.
+pub async fn download(config: &TaxrefConfig) -> Result<()> {
+    let target = path(config).ok_or(Error::NoCache)?;
 
     // No need to download if it already exists
     if target.exists() {
         return Ok(());
     }
 
-    utils::download("https://storage.tforgione.fr/TAXREFv17.txt", target).await?;
+    // Log every ten percent, so that we know the download of this big file is not stuck
+    let mut logged = 0;
+    utils::download_with_progress(&config.url, &target, |progress| {
+        match progress.percentage() {
+            Some(percentage) if percentage as u32 >= logged + 10 => {
+                logged = percentage as u32 / 10 * 10;
+                info!("downloading taxref: {}%", logged);
+            }
+            _ => (),
+        }
+    })
+    .await?;
+
+    if let Err(e) = check(&target, config) {
+        remove_file(&target)?;
+        return Err(e);
+    }
+
+    Ok(())
+}
+
+/// Checks that a downloaded taxref file has the expected size and hash.
+fn check(path: &Path, config: &TaxrefConfig) -> Result<()> {
+    let size = path.metadata()?.len();
+
+    if size < config.min_size {
+        return Err(Error::CorruptedDownload(format!(
+            "taxref is {} bytes long, expected at least {} bytes",
+            size, config.min_size
+        )));
+    }
+
+    if let Some(expected) = &config.sha256 {
+        let hash = utils::sha256_file(path)?;
+
+        if hash != expected.to_lowercase() {
+            return Err(Error::CorruptedDownload(format!(
+                "taxref has SHA-256 {}, expected {}",
+                hash, expected
+            )));
+        }
+    }
 
     Ok(())
 }
 
 /// The different taxonomic levels.
-#[derive(Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum Taxon {
     /// Reign.
     Reign,
@@ -55,7 +233,7 @@ pub enum Taxon {
     /// Family.
     Family,
 
-    /// Genus (sub-family).
+    /// Genus.
     Genus,
 
     /// Species.
@@ -75,6 +253,40 @@ impl Taxon {
             Taxon::Species => "species",
         }
     }
+
+    /// Returns all the taxonomic levels, from the most general to the most specific.
+    pub fn all() -> [Taxon; 7] {
+        [
+            Taxon::Reign,
+            Taxon::Phylum,
+            Taxon::Class,
+            Taxon::Order,
+            Taxon::Family,
+            Taxon::Genus,
+            Taxon::Species,
+        ]
+    }
+
+    /// Returns the position of the taxon in [`Taxon::all`], the reign being 0.
+    pub fn depth(self) -> usize {
+        self as usize
+    }
+
+    /// Returns the taxonomic level right above this one, none for the reign.
+    pub fn parent(self) -> Option<Taxon> {
+        self.depth().checked_sub(1).map(|x| Taxon::all()[x])
+    }
+
+    /// Returns the taxonomic level right below this one, none for the species.
+    pub fn child(self) -> Option<Taxon> {
+        Taxon::all().get(self.depth() + 1).copied()
+    }
+}
+
+impl fmt::Display for Taxon {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.to_str())
+    }
 }
 
 impl<'a> FromParam<'a> for Taxon {
@@ -111,8 +323,151 @@ impl FromStr for Taxon {
     }
 }
 
+/// The filter that tells which taxref entries must be considered.
+///
+/// The default filter keeps terrestrial species that are present in France.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TaxrefFilter {
+    /// Whether marine and brackish water species must be kept too.
+    pub include_marine: bool,
+
+    /// Whether species that are not present in France must be kept too.
+    pub include_non_france: bool,
+
+    /// The taxref ranks that are kept (e.g. `ES` for species, `SSES` for subspecies, `VAR` for varieties).
+    pub allowed_ranks: HashSet<String>,
+}
+
+impl Default for TaxrefFilter {
+    fn default() -> TaxrefFilter {
+        TaxrefFilter {
+            include_marine: false,
+            include_non_france: false,
+            allowed_ranks: HashSet::from([String::from("ES")]),
+        }
+    }
+}
+
+impl TaxrefFilter {
+    /// Returns true if the entry passes the filter.
+    pub fn matches(&self, entry: &Entry) -> bool {
+        entry.has_rank_in(&self.allowed_ranks)
+            && (self.include_non_france || entry.is_present_france())
+            && (entry.is_terrestrial() || (self.include_marine && entry.is_marine()))
+    }
+}
+
+/// The indices of the columns of taxref that we use, found by their names in the header line.
+#[derive(Debug, Clone, Copy)]
+pub struct Columns {
+    /// Index of the `REGNE` column.
+    pub reign: usize,
+
+    /// Index of the `PHYLUM` column.
+    pub phylum: usize,
+
+    /// Index of the `CLASSE` column.
+    pub class: usize,
+
+    /// Index of the `ORDRE` column.
+    pub order: usize,
+
+    /// Index of the `FAMILLE` column.
+    pub family: usize,
+
+    /// Index of the `RANG` column.
+    pub rank: usize,
+
+    /// Index of the `LB_NOM` column.
+    pub name: usize,
+
+    /// Index of the `NOM_COMPLET` column.
+    pub full_name: usize,
+
+    /// Index of the `NOM_VALIDE` column.
+    pub valid_name: usize,
+
+    /// Index of the `HABITAT` column.
+    pub habitat: usize,
+
+    /// Index of the `FR` column.
+    pub fr: usize,
+}
+
+impl Columns {
+    /// Finds the indices of the columns from the header line of taxref.
+    pub fn from_header(header: &str) -> Result<Columns> {
+        let names = header
+            .split("\t")
+            .map(|x| x.replace("\"", "").trim().to_owned())
+            .collect::<Vec<_>>();
+
+        let find = |name: &str| {
+            names
+                .iter()
+                .position(|x| x == name)
+                .ok_or_else(|| Error::MissingTaxrefColumn(name.to_owned()))
+        };
+
+        Ok(Columns {
+            reign: find("REGNE")?,
+            phylum: find("PHYLUM")?,
+            class: find("CLASSE")?,
+            order: find("ORDRE")?,
+            family: find("FAMILLE")?,
+            rank: find("RANG")?,
+            name: find("LB_NOM")?,
+            full_name: find("NOM_COMPLET")?,
+            valid_name: find("NOM_VALIDE")?,
+            habitat: find("HABITAT")?,
+            fr: find("FR")?,
+        })
+    }
+
+    /// Returns the largest index of the columns we use.
+    pub fn max(&self) -> usize {
+        [
+            self.reign,
+            self.phylum,
+            self.class,
+            self.order,
+            self.family,
+            self.rank,
+            self.name,
+            self.full_name,
+            self.valid_name,
+            self.habitat,
+            self.fr,
+        ]
+        .into_iter()
+        .max()
+        .unwrap_or(0)
+    }
+}
+
+/// An error that occured while parsing a line of taxref.
+#[derive(Debug)]
+pub struct TaxrefParseError {
+    /// The number of the line in the taxref file, starting at 1.
+    pub line_number: usize,
+
+    /// Why the line couldn't be parsed.
+    pub reason: String,
+}
+
+impl fmt::Display for TaxrefParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "failed to parse taxref line {}: {}",
+            self.line_number, self.reason
+        )
+    }
+}
+
 /// A taxref entry.
-#[derive(Debug, Clone, PartialOrd, Ord, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialOrd, Ord, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Entry {
     /// Reign of the species.
     pub reign: String,
@@ -129,7 +484,7 @@ pub struct Entry {
     /// Family of the species.
     pub family: String,
 
-    /// Genus of the species (sub-family).
+    /// Genus of the species, taken from its valid name since taxref has no genus column.
     pub genus: String,
 
     /// Rank of the species.
@@ -163,6 +518,11 @@ impl Entry {
         self.rank == "ES"
     }
 
+    /// Returns true if the rank of the entry is one of the given ranks.
+    pub fn has_rank_in(&self, ranks: &HashSet<String>) -> bool {
+        ranks.contains(&self.rank)
+    }
+
     /// Returns true if the entry is present in france.
     pub fn is_present_france(&self) -> bool {
         self.fr == "P"
@@ -184,9 +544,14 @@ impl Entry {
             || self.habitat == "8"
     }
 
+    /// Returns true if the entry lives in marine or brackish water.
+    pub fn is_marine(&self) -> bool {
+        self.habitat == "1" || self.habitat == "4" || self.habitat == "5" || self.habitat == "6"
+    }
+
     /// Returns true if the entry must be considered.
-    pub fn filter(&self) -> bool {
-        self.is_species() && self.is_present_france() && self.is_terrestrial()
+    pub fn filter(&self, filter: &TaxrefFilter) -> bool {
+        filter.matches(self)
     }
 
     /// Returns the corresponding name of the taxonomic level given as argument.
@@ -203,56 +568,322 @@ impl Entry {
     }
 
     /// Recreates an entry from a line in taxref.
-    pub fn from_line(line: &str) -> Result<Entry> {
+    ///
+    /// The line number is only used to give meaningful errors.
+    pub fn from_line(line: &str, line_number: usize, columns: &Columns) -> Result<Entry> {
         let split = line
             .split("\t")
             .map(|x| x.replace("\"", ""))
             .collect::<Vec<_>>();
 
+        if split.len() <= columns.max() {
+            return Err(Error::TaxrefParseError(TaxrefParseError {
+                line_number,
+                reason: format!(
+                    "found {} columns, expected at least {}",
+                    split.len(),
+                    columns.max() + 1
+                ),
+            }));
+        }
+
         Ok(Entry {
-            reign: split[0].to_string(),
-            phylum: split[1].to_string(),
-            class: split[2].to_string(),
-            order: split[3].to_string(),
-            family: split[4].to_string(),
-            genus: split[5].to_string(),
-            rank: split[14].to_string(),
-            name: split[15].to_string(),
-            full_name: split[17].to_string(),
-            valid_name: split[19].to_string(),
-            habitat: split[22].to_string(),
-            fr: split[23].to_string(),
+            reign: split[columns.reign].to_string(),
+            phylum: split[columns.phylum].to_string(),
+            class: split[columns.class].to_string(),
+            order: split[columns.order].to_string(),
+            family: split[columns.family].to_string(),
+            genus: utils::genus(&split[columns.valid_name]),
+            rank: split[columns.rank].to_string(),
+            name: split[columns.name].to_string(),
+            full_name: split[columns.full_name].to_string(),
+            valid_name: split[columns.valid_name].to_string(),
+            habitat: split[columns.habitat].to_string(),
+            fr: split[columns.fr].to_string(),
         })
     }
 
-    /// Retrives all the species corresponding to a specific filter.
-    pub fn from_taxon(taxon: Taxon, query: &str) -> Result<Vec<Entry>> {
+    /// Parses a line of taxref, and returns the entry if it passes the filter.
+    ///
+    /// Empty lines are skipped, and so are broken lines since one of them should not prevent us from using the rest of
+    /// taxref.
+    fn parse_filtered(
+        line: &str,
+        line_number: usize,
+        columns: &Columns,
+        filter: &TaxrefFilter,
+    ) -> Option<Entry> {
+        if line.is_empty() {
+            return None;
+        }
+
+        match Entry::from_line(line, line_number, columns) {
+            Ok(entry) => Some(entry).filter(|x| x.filter(filter)),
+            Err(e) => {
+                warn!("{}", e);
+                None
+            }
+        }
+    }
+
+    /// Returns all the entries of taxref that pass the filter.
+    ///
+    /// The taxref file is only read the first time this function is called with a given filter, the entries are then
+    /// kept in memory.
+    pub fn filtered(config: &TaxrefConfig) -> Result<Arc<Vec<Entry>>> {
+        Entry::visit_filtered(config, |_| ())
+    }
+
+    /// Calls `visit` on each entry of taxref that passes the filter, in the order of the file, and returns them all.
+    ///
+    /// If the entries are not in memory yet, `visit` is called as the file is read, so that the first entries can be
+    /// used before the whole file is parsed. When several threads parse the file, it is read by chunks of lines.
+    fn visit_filtered<F: FnMut(&Entry)>(
+        config: &TaxrefConfig,
+        mut visit: F,
+    ) -> Result<Arc<Vec<Entry>>> {
+        let path = path(config).ok_or(Error::NoCache)?;
+        let filter = &config.filter;
+        let mut cache = FILTERED_ENTRIES.lock().unwrap();
+
+        if let Some((_, _, entries)) = cache.iter().find(|(p, f, _)| *p == path && f == filter) {
+            entries.iter().for_each(visit);
+            return Ok(entries.clone());
+        }
+
         let mut entries: Vec<Entry> = vec![];
 
-        let taxref = path().ok_or(Error::NoCache)?;
-        let taxref = File::open(taxref)?;
+        let mut lines = open(config)?.lines();
+
+        let header = match lines.next() {
+            Some(header) => header?,
+            None => return Err(Error::MissingTaxrefColumn(String::from("REGNE"))),
+        };
 
-        for line in BufReader::new(taxref).lines().skip(1) {
-            let line = line?;
-            if line.is_empty() {
-                continue;
+        let columns = Columns::from_header(&header)?;
+
+        // Line numbers start at 1, and the header is the first line.
+        let mut lines = lines.enumerate().map(|(i, x)| (i + 2, x));
+
+        if config.threads == 1 {
+            for (line_number, line) in lines {
+                if let Some(entry) = Entry::parse_filtered(&line?, line_number, &columns, filter) {
+                    visit(&entry);
+                    entries.push(entry);
+                }
+            }
+        } else {
+            let pool = ThreadPoolBuilder::new()
+                .num_threads(config.threads)
+                .build()
+                .expect("Failed to create the thread pool parsing taxref");
+
+            loop {
+                let chunk = lines
+                    .by_ref()
+                    .take(PARALLEL_CHUNK_LINES)
+                    .map(|(line_number, line)| Ok((line_number, line?)))
+                    .collect::<Result<Vec<_>>>()?;
+
+                if chunk.is_empty() {
+                    break;
+                }
+
+                // The lines are parsed in parallel, but visited in the order of the file, so that the duplicates of a
+                // species are skipped as when parsing on a single thread.
+                let parsed = pool.install(|| {
+                    chunk
+                        .par_iter()
+                        .filter_map(|(line_number, line)| {
+                            Entry::parse_filtered(line, *line_number, &columns, filter)
+                        })
+                        .collect::<Vec<_>>()
+                });
+
+                for entry in parsed {
+                    visit(&entry);
+                    entries.push(entry);
+                }
             }
+        }
 
-            let entry = Entry::from_line(&line)?;
+        let entries = Arc::new(entries);
+        cache.push((path, filter.clone(), entries.clone()));
+        Ok(entries)
+    }
 
-            if entry.filter() && entry.get_taxon(taxon).to_lowercase() == query.to_lowercase() {
-                // Found match, check to avoid duplicates
-                if let Some(previous) = entries.last() {
-                    if previous.valid_name == entry.valid_name {
-                        continue;
+    /// Retrives all the species corresponding to a specific filter.
+    pub fn from_taxon(taxon: Taxon, query: &str, config: &TaxrefConfig) -> Result<Vec<Entry>> {
+        Entry::from_taxon_many(taxon, &[query], config)
+    }
+
+    /// Retrives all the species whose taxonomic level `taxon` is any of the queries, reading taxref only once.
+    pub fn from_taxon_many(
+        taxon: Taxon,
+        queries: &[&str],
+        config: &TaxrefConfig,
+    ) -> Result<Vec<Entry>> {
+        let mut entries: Vec<Entry> = vec![];
+        let mut matcher = TaxonMatcher::new(taxon, queries);
+
+        Entry::visit_filtered(config, |entry| {
+            if matcher.accept(entry) {
+                entries.push(entry.clone());
+            }
+        })?;
+
+        Ok(entries)
+    }
+
+    /// Retrieves all the species corresponding to a specific filter like [`Entry::from_taxon`], but saves them in a file
+    /// so that the next calls don't need to read taxref.
+    ///
+    /// The saved species are read again when the taxref file is modified.
+    pub fn from_taxon_cached(
+        taxon: Taxon,
+        query: &str,
+        config: &TaxrefConfig,
+    ) -> Result<Vec<Entry>> {
+        let path = cached_entries_path(taxon, &[query], config).ok_or(Error::NoCache)?;
+
+        if let Some(entries) = read_cached_entries(&path, config) {
+            return Ok(entries);
+        }
+
+        let entries = Entry::from_taxon(taxon, query, config)?;
+
+        // Without the cache, the next calls are only slower.
+        if let Err(e) = write_cached_entries(&path, config, entries.clone()) {
+            warn!("failed to cache the species of {} {}: {}", taxon, query, e);
+        }
+
+        Ok(entries)
+    }
+
+    /// Streams the species corresponding to a specific filter, as soon as they are found in taxref.
+    ///
+    /// This yields the same entries as [`Entry::from_taxon`], but the first ones are available before the whole taxref
+    /// file is read. The file is read on a blocking thread, so this must be polled from a tokio runtime, and other
+    /// lookups in taxref wait for the file to be completely read.
+    pub fn stream_taxon(
+        taxon: Taxon,
+        query: &str,
+        config: &TaxrefConfig,
+    ) -> impl Stream<Item = Result<Entry>> + use<> {
+        Entry::stream_taxon_many(taxon, &[query], config)
+    }
+
+    /// Streams the species whose taxonomic level `taxon` is any of the queries, as soon as they are found in taxref.
+    ///
+    /// The species are saved like with [`Entry::from_taxon_cached`], and the next streams read them from the saved file
+    /// until the taxref file is modified.
+    pub fn stream_taxon_many(
+        taxon: Taxon,
+        queries: &[&str],
+        config: &TaxrefConfig,
+    ) -> impl Stream<Item = Result<Entry>> + use<> {
+        let (sender, receiver) = channel(STREAM_CAPACITY);
+        let mut matcher = TaxonMatcher::new(taxon, queries);
+        let cached_path = cached_entries_path(taxon, queries, config);
+        let config = config.clone();
+
+        spawn_blocking(move || {
+            let cached = cached_path
+                .as_ref()
+                .and_then(|path| read_cached_entries(path, &config));
+
+            if let Some(entries) = cached {
+                for entry in entries {
+                    // If the receiver is gone, nobody cares about the next entries anymore.
+                    if sender.blocking_send(Ok(entry)).is_err() {
+                        return;
                     }
                 }
+                return;
+            }
+
+            let mut entries = vec![];
+            let result = Entry::visit_filtered(&config, |entry| {
+                if matcher.accept(entry) {
+                    entries.push(entry.clone());
+                    sender.blocking_send(Ok(entry.clone())).ok();
+                }
+            });
 
-                // Match that is not a duplicate
-                entries.push(entry);
+            match (result, cached_path) {
+                (Ok(_), Some(path)) => {
+                    // Without the cache, the next scraps are only slower.
+                    if let Err(e) = write_cached_entries(&path, &config, entries) {
+                        warn!("failed to cache the species of {}: {}", taxon, e);
+                    }
+                }
+                (Ok(_), None) => (),
+                (Err(e), _) => {
+                    sender.blocking_send(Err(e)).ok();
+                }
             }
+        });
+
+        stream::unfold(receiver, |mut receiver| async move {
+            receiver.recv().await.map(|entry| (entry, receiver))
+        })
+    }
+
+    /// Returns the sorted distinct values of the taxonomic level `level` among the filtered entries whose taxonomic
+    /// level `parent` is `parent_value`.
+    ///
+    /// For example, `Entry::list_values(Taxon::Family, "Apidae", Taxon::Genus, config)` returns all the genera of the
+    /// Apidae family, even if they have not been scraped yet.
+    pub fn list_values(
+        parent: Taxon,
+        parent_value: &str,
+        level: Taxon,
+        config: &TaxrefConfig,
+    ) -> Result<Vec<String>> {
+        let parent_value = parent_value.to_lowercase();
+
+        let values = Entry::filtered(config)?
+            .iter()
+            .filter(|x| x.get_taxon(parent).to_lowercase() == parent_value)
+            .map(|x| x.get_taxon(level).to_owned())
+            .filter(|x| !x.is_empty())
+            .collect::<BTreeSet<_>>();
+
+        Ok(values.into_iter().collect())
+    }
+}
+
+/// Finds the species matching a taxonomic level, skipping the entries of taxref that repeat a species.
+struct TaxonMatcher {
+    /// The taxonomic level to look at.
+    taxon: Taxon,
+
+    /// The lowercase values the taxonomic level can have.
+    queries: Vec<String>,
+
+    /// The valid names of the species that already matched.
+    seen: HashSet<String>,
+}
+
+impl TaxonMatcher {
+    /// Creates a matcher for the entries whose taxonomic level `taxon` is one of `queries`, ignoring case.
+    fn new(taxon: Taxon, queries: &[&str]) -> TaxonMatcher {
+        TaxonMatcher {
+            taxon,
+            queries: queries.iter().map(|x| x.to_lowercase()).collect(),
+            seen: HashSet::new(),
         }
+    }
 
-        Ok(entries)
+    /// Returns true if the entry matches and is not a duplicate of a previous match.
+    fn accept(&mut self, entry: &Entry) -> bool {
+        let value = entry.get_taxon(self.taxon).to_lowercase();
+
+        if !self.queries.contains(&value) {
+            return false;
+        }
+
+        // Found match, check to avoid duplicates
+        self.seen.insert(entry.valid_name.clone())
     }
 }
@@ -1,62 +1,540 @@
 //! This module contains utils functions.
 
-use std::fs::File;
-use std::io::Write;
-use std::path::Path;
+use std::collections::hash_map::RandomState;
+use std::error::Error as StdError;
+use std::fs::{File, remove_file, rename};
+use std::hash::{BuildHasher, Hasher};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::pin::pin;
+use std::result::Result as StdResult;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
 
-use futures_util::StreamExt;
+use futures_util::{Stream, StreamExt};
 
 use reqwest::Client;
 
-use crate::Result;
+use sha2::{Digest, Sha256};
 
-/// Downloads a file to a place on the disk.
+use tokio::time::sleep;
+
+use unidecode::unidecode;
+
+use crate::{Error, Result};
+
+/// The number of attempts made by [`download`].
+const DOWNLOAD_ATTEMPTS: u32 = 5;
+
+/// The time waited by [`download`] after its first failed attempt.
+const DOWNLOAD_BACKOFF: Duration = Duration::from_secs(2);
+
+/// How many times and how long apart an operation is attempted by [`retry`].
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    /// The maximum number of attempts, including the first one.
+    pub attempts: u32,
+
+    /// The time waited after the first failed attempt, doubled after each of the next ones.
+    pub backoff: Duration,
+
+    /// The maximum time waited between two attempts.
+    pub max_backoff: Duration,
+}
+
+impl RetryPolicy {
+    /// Creates a policy that makes at most `attempts` attempts, waiting `backoff` after the first failure.
+    pub const fn new(attempts: u32, backoff: Duration) -> RetryPolicy {
+        RetryPolicy {
+            attempts,
+            backoff,
+            max_backoff: Duration::from_secs(60),
+        }
+    }
+
+    /// Returns the time to wait after a failed attempt, the first attempt being 1.
+    ///
+    /// The time is picked at random between half and all of the exponential backoff, so that tasks failing together
+    /// don't retry together.
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let factor = 1u32 << attempt.saturating_sub(1).min(16);
+        let delay = self.backoff.saturating_mul(factor).min(self.max_backoff);
+
+        // RandomState is seeded randomly, which is enough for jitter without depending on a random crate.
+        let random = RandomState::new().build_hasher().finish();
+        let jitter = (random % 1000) as u32;
+
+        delay / 2 + delay / 2 * jitter / 1000
+    }
+}
+
+/// Runs an operation until it succeeds, following a retry policy.
+///
+/// The operation receives the number of the attempt, starting at 1. After each attempt, `should_retry` tells whether
+/// the result is a transient failure, such as a connection error or a 429 status code, in which case the operation
+/// is attempted again after a delay. The result of the last attempt is returned.
+pub async fn retry<T, F, Fut, C>(policy: RetryPolicy, mut op: F, should_retry: C) -> Result<T>
+where
+    F: FnMut(u32) -> Fut,
+    Fut: Future<Output = Result<T>>,
+    C: Fn(&Result<T>) -> bool,
+{
+    let mut attempt = 1;
+
+    loop {
+        let result = op(attempt).await;
+
+        if attempt >= policy.attempts || !should_retry(&result) {
+            return result;
+        }
+
+        let delay = policy.delay(attempt);
+        trace!(
+            "attempt {} failed, retrying in {}ms",
+            attempt,
+            delay.as_millis()
+        );
+        sleep(delay).await;
+        attempt += 1;
+    }
+}
+
+/// The rate limiter shared by the requests to GBIF and the downloads of medias.
+pub static RATE_LIMITER: RateLimiter = RateLimiter::new();
+
+/// A token bucket that spaces out requests so that they stay under a number of requests per second.
+///
+/// As many requests as the rate can be sent at once, after which each request waits for its turn. There is no limit
+/// until a rate is set.
+#[derive(Debug)]
+pub struct RateLimiter {
+    /// The state of the bucket, only locked while reserving a turn.
+    state: Mutex<RateLimiterState>,
+}
+
+/// The state of a [`RateLimiter`].
+#[derive(Debug)]
+struct RateLimiterState {
+    /// The time between two requests, none if there is no limit.
+    interval: Option<Duration>,
+
+    /// The number of requests that can be sent at once.
+    burst: u32,
+
+    /// The time at which the bucket will be full again, none if it is full.
+    full_at: Option<Instant>,
+
+    /// Whether the last request had to wait, so that throttling is only logged when it starts.
+    throttled: bool,
+}
+
+impl RateLimiter {
+    /// Creates a rate limiter without limit.
+    pub const fn new() -> RateLimiter {
+        RateLimiter {
+            state: Mutex::new(RateLimiterState {
+                interval: None,
+                burst: 1,
+                full_at: None,
+                throttled: false,
+            }),
+        }
+    }
+
+    /// Sets the number of requests per second, or removes the limit if none.
+    pub fn set_rate(&self, per_second: Option<f64>) {
+        let mut state = self.state.lock().unwrap();
+
+        state.interval = per_second
+            .filter(|x| x.is_finite() && *x > 0.0)
+            .map(|x| Duration::from_secs_f64(1.0 / x));
+        state.burst = per_second.map_or(1, |x| x.max(1.0) as u32);
+        state.full_at = None;
+    }
+
+    /// Returns the time to wait before sending a request, and reserves its turn.
+    pub fn reserve(&self) -> Duration {
+        let mut state = self.state.lock().unwrap();
+
+        let Some(interval) = state.interval else {
+            return Duration::ZERO;
+        };
+
+        // Each request takes a token, that is one interval of the time it takes for the bucket to be full again.
+        let now = Instant::now();
+        let full_at = state.full_at.map_or(now, |x| x.max(now)) + interval;
+        let wait = full_at.saturating_duration_since(now + interval * state.burst);
+        state.full_at = Some(full_at);
+
+        if !wait.is_zero() && !state.throttled {
+            info!(
+                "reached {:.1} requests per second, throttling",
+                1.0 / interval.as_secs_f64()
+            );
+        }
+
+        state.throttled = !wait.is_zero();
+        wait
+    }
+
+    /// Waits for the turn of a request.
+    pub async fn acquire(&self) {
+        let wait = self.reserve();
+
+        if !wait.is_zero() {
+            trace!("throttled for {}ms", wait.as_millis());
+            sleep(wait).await;
+        }
+    }
+}
+
+impl Default for RateLimiter {
+    fn default() -> RateLimiter {
+        RateLimiter::new()
+    }
+}
+
+/// How far a download is.
+#[derive(Debug, Clone, Copy)]
+pub struct Progress {
+    /// The number of bytes downloaded so far.
+    pub downloaded: u64,
+
+    /// The size of the file, if the server sent a `Content-Length`.
+    pub total: Option<u64>,
+}
+
+impl Progress {
+    /// Returns the percentage of the file that is downloaded, if its size is known.
+    pub fn percentage(&self) -> Option<f64> {
+        match self.total {
+            Some(0) => Some(100.0),
+            Some(total) => Some(100.0 * self.downloaded as f64 / total as f64),
+            None => None,
+        }
+    }
+}
+
+/// Downloads a file to a place on the disk, retrying a few times if the network fails.
 pub async fn download<P: AsRef<Path>>(url: &str, target: P) -> Result<()> {
+    download_with_progress(url, target, |_| ()).await
+}
+
+/// Downloads a file to a place on the disk, calling `progress` each time a chunk is received.
+pub async fn download_with_progress<P: AsRef<Path>, F: FnMut(Progress) + Send>(
+    url: &str,
+    target: P,
+    mut progress: F,
+) -> Result<()> {
+    download_with_retry(
+        url,
+        target,
+        DOWNLOAD_ATTEMPTS,
+        DOWNLOAD_BACKOFF,
+        &mut progress,
+    )
+    .await
+}
+
+/// Downloads a file to a place on the disk, making at most `attempts` attempts.
+///
+/// Connection errors and 5xx responses are retried, waiting `backoff` after the first failure and twice longer after
+/// each of the next ones. The file is written next to the target with a `.part` suffix, and only renamed to the target
+/// once it is completely downloaded, so an interrupted download never leaves a truncated file at the target.
+///
+/// The progress starts over from zero at each attempt.
+pub async fn download_with_retry<P: AsRef<Path>>(
+    url: &str,
+    target: P,
+    attempts: u32,
+    backoff: Duration,
+    progress: &mut (dyn FnMut(Progress) + Send),
+) -> Result<()> {
     let target = target.as_ref();
 
+    let mut part = target.to_owned().into_os_string();
+    part.push(".part");
+    let part = PathBuf::from(part);
+
     let client = Client::new();
 
-    let req = client.get(url).send().await?;
+    // Each attempt borrows the progress callback in turn.
+    let progress = Mutex::new(progress);
+
+    let result = retry(
+        RetryPolicy::new(attempts, backoff),
+        |attempt| {
+            if attempt > 1 {
+                warn!("retrying download of {} (attempt={})", url, attempt);
+            }
+            download_once(&client, url, &part, &progress)
+        },
+        |result| result.as_ref().err().map(is_transient).unwrap_or(false),
+    )
+    .await;
+
+    match result {
+        Ok(()) => {
+            rename(&part, target)?;
+            Ok(())
+        }
+
+        Err(e) => {
+            remove_file(&part).ok();
+            Err(e)
+        }
+    }
+}
+
+/// Makes a single attempt at downloading a file.
+async fn download_once(
+    client: &Client,
+    url: &str,
+    target: &Path,
+    progress: &Mutex<&mut (dyn FnMut(Progress) + Send)>,
+) -> Result<()> {
+    let req = client.get(url).send().await?.error_for_status()?;
+
+    let mut current = Progress {
+        downloaded: 0,
+        total: req.content_length(),
+    };
 
-    let mut file = File::create(&target)?;
+    let mut file = File::create(target)?;
     let mut byte_stream = req.bytes_stream();
 
     while let Some(chunk) = byte_stream.next().await {
         let bytes = chunk?;
         file.write_all(&bytes)?;
+        current.downloaded += bytes.len() as u64;
+        (progress.lock().unwrap())(current);
     }
 
     Ok(())
 }
 
-/// Returns the name of the entry, without the author.
-pub fn pretty_name(valid_name: &str) -> Option<String> {
-    let split = valid_name.replace("(", "").replace(")", "");
-    let split = split.split_whitespace().collect::<Vec<_>>();
+/// Returns true if a request failed for a reason that may not happen again.
+///
+/// A connection that breaks while the body is read is transient too, even though reqwest reports it as a decoding
+/// error.
+pub fn is_transient(error: &Error) -> bool {
+    match error {
+        Error::ReqwestError(e) => match e.status() {
+            Some(status) => status.is_server_error(),
+            None => {
+                e.is_connect()
+                    || e.is_timeout()
+                    || e.is_request()
+                    || e.is_body()
+                    || (e.is_decode() && has_io_source(e))
+            }
+        },
+        _ => false,
+    }
+}
+
+/// Returns true if an I/O error is among the causes of an error.
+fn has_io_source(error: &(dyn StdError + 'static)) -> bool {
+    let mut source = error.source();
+
+    while let Some(error) = source {
+        if error.is::<io::Error>() {
+            return true;
+        }
+        source = error.source();
+    }
+
+    false
+}
+
+/// Hashes a stream of chunks with SHA-256 as they arrive, and returns the hash in lowercase hexadecimal.
+pub async fn sha256_stream<S, B, E>(stream: S) -> Result<String>
+where
+    S: Stream<Item = StdResult<B, E>>,
+    B: AsRef<[u8]>,
+    Error: From<E>,
+{
+    let mut stream = pin!(stream);
+    let mut hasher = Sha256::new();
+
+    while let Some(chunk) = stream.next().await {
+        hasher.update(chunk?);
+    }
+
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// Hashes a file with SHA-256, and returns the hash in lowercase hexadecimal.
+pub fn sha256_file<P: AsRef<Path>>(path: P) -> Result<String> {
+    let mut file = File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0; 1 << 16];
+
+    loop {
+        let read = file.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+    }
+
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// Turns a name into a directory name that is safe on any filesystem and in URLs.
+///
+/// The name is transliterated to lowercase ASCII, whitespace becomes `_`, and every character that is not a letter, a
+/// digit, `-` or `_` is removed, including path separators. Sanitizing a sanitized name gives it back unchanged.
+pub fn sanitize_dirname(name: &str) -> String {
+    let name = unidecode(name).to_lowercase();
+
+    let words = name
+        .split(|c: char| c.is_whitespace() || c == '_')
+        .map(|word| {
+            word.chars()
+                .filter(|c| c.is_ascii_alphanumeric() || *c == '-')
+                .collect::<String>()
+        })
+        .filter(|word| !word.is_empty())
+        .collect::<Vec<_>>();
+
+    if words.is_empty() {
+        // Never return an empty name, which would be the parent directory itself
+        String::from("unnamed")
+    } else {
+        words.join("_")
+    }
+}
+
+/// The multiplication sign used to mark hybrid taxa, such as `Mentha × piperita`.
+pub const HYBRID_SIGN: &str = "×";
+
+/// Splits a name in words, with the hybrid sign as a word of its own.
+///
+/// The sign may be glued to the epithet (`Mentha ×piperita`), and a lowercase `x` alone after the genus is also a
+/// hybrid sign (`Mentha x piperita`).
+fn split_name(name: &str) -> Vec<String> {
+    let mut split = vec![];
 
-    // Found the author: its the second word that starts with uppercase character
-    let author_index = split
-        .iter()
-        .skip(1)
-        .position(|x| x.chars().any(char::is_uppercase))?
-        + 1;
+    for word in name.split_whitespace() {
+        match word.strip_prefix(HYBRID_SIGN) {
+            Some(rest) => {
+                split.push(HYBRID_SIGN.to_owned());
+                if !rest.is_empty() {
+                    split.push(rest.to_owned());
+                }
+            }
+            None if word == "x" && !split.is_empty() => split.push(HYBRID_SIGN.to_owned()),
+            None => split.push(word.to_owned()),
+        }
+    }
+
+    split
+}
 
-    // Join everything until author
-    Some(split[0..author_index].join(" "))
+/// Writes the hybrid signs of a name the way GBIF does, as a `×` separated from the other words by spaces.
+pub fn normalize_hybrid(name: &str) -> String {
+    split_name(name).join(" ")
+}
+
+/// Returns the genus of a valid name, its first word, with the hybrid sign of the intergeneric hybrids such as
+/// `× Triticosecale`.
+///
+/// A lowercase `x` alone before the genus is also a hybrid sign, since no genus is written in lowercase.
+pub fn genus(valid_name: &str) -> String {
+    let mut split = split_name(valid_name).into_iter();
+
+    match split.next() {
+        Some(first) if first == HYBRID_SIGN || first == "x" => split
+            .next()
+            .map(|genus| format!("{} {}", HYBRID_SIGN, genus))
+            .unwrap_or_default(),
+        Some(first) => first,
+        None => String::new(),
+    }
+}
+
+/// Returns the index of the first word of the author in a split valid name, if there is an author.
+///
+/// The author is the first word after the genus that contains an uppercase character. The word following a hybrid
+/// sign is part of the name even if it is capitalized, as in `× Triticosecale`.
+fn author_index(split: &[String]) -> Option<usize> {
+    (1..split.len()).find(|&i| {
+        split[i] != HYBRID_SIGN
+            && split[i - 1] != HYBRID_SIGN
+            && split[i].chars().any(char::is_uppercase)
+    })
+}
+
+/// The abbreviations of ranks that introduce an infraspecific epithet, as in `Bufo bufo subsp. spinosus`.
+const RANK_CONNECTORS: &[&str] = &["subsp.", "ssp.", "var.", "subvar.", "f.", "subf."];
+
+/// Splits a valid name between the words of the name and the words of the author.
+///
+/// Rank connectors are dropped from the name, and only the author of the most specific part of the name is kept, so
+/// that `Salix alba L. var. vitellina (L.) Stokes` gives `Salix alba vitellina` and `L. Stokes`.
+fn split_author(valid_name: &str) -> (Vec<String>, Vec<String>) {
+    let split = split_name(&valid_name.replace("(", "").replace(")", ""));
+
+    // A connector must be followed by a lowercase epithet, otherwise it can be part of an author, like `L. f.`
+    let connector = (1..split.len().saturating_sub(1)).rev().find(|&i| {
+        RANK_CONNECTORS.contains(&split[i].as_str()) && split[i + 1].starts_with(char::is_lowercase)
+    });
+
+    let (mut name, rest) = match connector {
+        Some(i) => {
+            let species_end = author_index(&split[..i]).unwrap_or(i);
+            (split[..species_end].to_vec(), &split[i + 1..])
+        }
+        None => (vec![], &split[..]),
+    };
+
+    // Found the author in what remains: the epithet itself never contains an uppercase character
+    let author_index = author_index(rest).unwrap_or(rest.len());
+    name.extend_from_slice(&rest[..author_index]);
+
+    (name, rest[author_index..].to_vec())
+}
+
+/// Returns the name of the entry, without the author.
+///
+/// If no author can be found, the whole name is returned.
+pub fn pretty_name(valid_name: &str) -> String {
+    split_author(valid_name).0.join(" ")
 }
 
 /// Returns the author of the entry.
-pub fn pretty_finder(valid_name: &str) -> Option<String> {
-    let split = valid_name.replace("(", "").replace(")", "");
-    let split = split.split_whitespace().collect::<Vec<_>>();
-
-    // Found the author: its the second word that starts with uppercase character
-    let author_index = split
-        .iter()
-        .skip(1)
-        .position(|x| x.chars().any(char::is_uppercase))?
-        + 1;
-
-    // Join everything until author
-    Some(split[author_index..].join(" "))
+///
+/// If no author can be found, an empty string is returned.
+pub fn pretty_finder(valid_name: &str) -> String {
+    split_author(valid_name).1.join(" ")
+}
+
+/// Parses a human duration, such as `30d`, `12h`, `15m` or `45s`.
+///
+/// Returns none if the unit is missing or unknown, or if the number isn't a positive integer.
+pub fn parse_duration(duration: &str) -> Option<Duration> {
+    let duration = duration.trim();
+    let unit = duration.chars().last()?;
+    let value = duration[..duration.len() - unit.len_utf8()]
+        .parse::<u64>()
+        .ok()?;
+
+    let seconds = match unit {
+        's' => 1,
+        'm' => 60,
+        'h' => 60 * 60,
+        'd' => 24 * 60 * 60,
+        'w' => 7 * 24 * 60 * 60,
+        _ => return None,
+    };
+
+    Some(Duration::from_secs(value.checked_mul(seconds)?))
+}
+
+/// Creates a directory and its parents, failing with an error that names the directory.
+pub async fn create_dir_all(path: &Path) -> Result<()> {
+    tokio::fs::create_dir_all(path)
+        .await
+        .map_err(|e| Error::NotWritable(path.to_owned(), e))
 }
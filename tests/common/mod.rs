@@ -0,0 +1,251 @@
+//! Helpers shared by the tests that run against a mock GBIF server and a test database.
+
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::Path;
+use std::{env, fs, thread};
+
+use ergol::tokio_postgres::NoTls;
+use ergol::{Ergol, Transaction};
+
+use rocket::figment::Figment;
+use rocket::figment::providers::{Format, Toml};
+
+use tempfile::TempDir;
+
+use uuid::Uuid;
+
+use scraper::config::Config;
+use scraper::db::{Media, Occurrence, Species};
+use scraper::taxref::Entry;
+
+/// The environment variable holding the url of the database used by the ignored tests, which fail if it is not set.
+pub const DATABASE_URL_VAR: &str = "SCRAPER_TEST_DATABASE_URL";
+
+/// Reads a canned GBIF response from the fixtures directory.
+pub fn fixture(name: &str) -> String {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures/gbif")
+        .join(name);
+
+    fs::read_to_string(&path).unwrap_or_else(|e| panic!("reading {}: {}", path.display(), e))
+}
+
+/// Reads the request line and the headers of a request, and returns its target, such as `/path?query`.
+pub fn read_request(stream: &TcpStream) -> String {
+    let mut reader = BufReader::new(stream);
+
+    // The request line is `GET /path?query HTTP/1.1`, the headers don't matter.
+    let mut line = String::new();
+    reader.read_line(&mut line).unwrap();
+    let target = line.split(' ').nth(1).unwrap_or("").to_owned();
+
+    loop {
+        line.clear();
+        if reader.read_line(&mut line).unwrap_or(0) <= 2 {
+            break;
+        }
+    }
+
+    target
+}
+
+/// Starts a server that answers each request it receives with the next raw HTTP response, and returns its URL.
+pub fn mock_server(responses: Vec<Vec<u8>>) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    thread::spawn(move || {
+        for response in responses {
+            let (mut stream, _) = listener.accept().unwrap();
+            read_request(&stream);
+            stream.write_all(&response).unwrap();
+        }
+    });
+
+    format!("http://{}", addr)
+}
+
+/// Starts a server that mimics the GBIF API, and returns its root.
+///
+/// Each route is the beginning of a path with its query, such as `/occurrence/search?taxonKey=1&offset=0`, and the
+/// body of the response. The longest route matching a request is answered, and requests matching no route get a 404.
+pub fn mock_gbif<B: Into<Vec<u8>>>(routes: Vec<(&str, B)>) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let routes = routes
+        .into_iter()
+        .map(|(route, body)| (route.to_owned(), body.into()))
+        .collect::<Vec<_>>();
+
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let Ok(mut stream) = stream else { continue };
+            let target = read_request(&stream);
+
+            let body = routes
+                .iter()
+                .filter(|(route, _)| target.starts_with(route.as_str()))
+                .max_by_key(|(route, _)| route.len())
+                .map(|(_, body)| body);
+
+            let (status, body) = match body {
+                Some(body) => ("200 OK", body.as_slice()),
+                None => ("404 Not Found", b"{}".as_slice()),
+            };
+
+            let headers = format!(
+                "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                status,
+                body.len(),
+            );
+
+            let _ = stream.write_all(&[headers.as_bytes(), body].concat());
+        }
+    });
+
+    format!("http://{}", addr)
+}
+
+/// Returns a config whose data is stored in a temporary directory that is unique to a test, along with the directory,
+/// which is removed when it is dropped.
+pub fn config(name: &str) -> (Config, TempDir) {
+    let dir = tempfile::Builder::new()
+        .prefix(&format!("scraper-{}-", name))
+        .tempdir()
+        .unwrap();
+    let data_path = dir.path();
+    fs::create_dir_all(data_path.join("species")).unwrap();
+
+    let toml = format!(
+        r#"
+        root = "http://localhost:8000"
+        data_path = "{}"
+        jobs = 1
+        batch_size = 1
+        vernacular_language = "fra"
+
+        [databases.database]
+        url = "{}"
+        "#,
+        data_path.display(),
+        env::var(DATABASE_URL_VAR).unwrap_or_default(),
+    );
+
+    (
+        Config::from_figment(&Figment::from(Toml::string(&toml))),
+        dir,
+    )
+}
+
+/// Connects to the test database, and panics if there is none.
+pub async fn connect() -> Ergol {
+    let url = env::var(DATABASE_URL_VAR)
+        .unwrap_or_else(|_| panic!("{} must be set to run this test", DATABASE_URL_VAR));
+    let (db, connection) = ergol::connect(&url, NoTls).await.unwrap();
+
+    tokio::spawn(async move {
+        if let Err(e) = connection.await {
+            eprintln!("connection to the test database failed: {}", e);
+        }
+    });
+
+    db
+}
+
+/// Creates the tables in a schema of its own inside a transaction, so that everything is gone when it rolls back.
+pub async fn migrate(transaction: &Transaction<'_>) {
+    migrate_schema(transaction, "scraper_test").await;
+}
+
+/// Creates the tables in a new schema inside a transaction, and uses this schema until the end of the transaction.
+pub async fn migrate_schema(transaction: &Transaction<'_>, schema: &str) {
+    let migrations = Path::new(env!("CARGO_MANIFEST_DIR")).join("migrations");
+
+    let mut versions = fs::read_dir(&migrations)
+        .unwrap()
+        .filter_map(|x| x.ok()?.file_name().to_str()?.parse::<u32>().ok())
+        .collect::<Vec<_>>();
+
+    versions.sort();
+
+    transaction
+        .inner
+        .batch_execute(&format!(
+            "CREATE SCHEMA {schema}; SET LOCAL search_path TO {schema};"
+        ))
+        .await
+        .unwrap();
+
+    for version in versions {
+        let path = migrations.join(version.to_string()).join("up.sql");
+        let sql = fs::read_to_string(&path).unwrap();
+        transaction.inner.batch_execute(&sql).await.unwrap();
+    }
+}
+
+/// The taxref entry of the species served by the fixtures.
+pub fn apis_mellifera() -> Entry {
+    Entry {
+        reign: String::from("Animalia"),
+        phylum: String::from("Arthropoda"),
+        class: String::from("Insecta"),
+        order: String::from("Hymenoptera"),
+        family: String::from("Apidae"),
+        genus: String::from("Apis"),
+        rank: String::from("ES"),
+        name: String::from("Apis mellifera"),
+        full_name: String::from("Apis mellifera Linnaeus, 1758"),
+        valid_name: String::from("Apis mellifera Linnaeus, 1758"),
+        habitat: String::from("3"),
+        fr: String::from("P"),
+    }
+}
+
+/// The rows created by [`seed`].
+pub struct Seed {
+    /// Apis mellifera, with its GBIF species key.
+    pub species: Species,
+
+    /// The occurrence of key 1 of the species, in the nil dataset.
+    pub occurrence: Occurrence,
+
+    /// The medias of the occurrence, in the order of their urls.
+    pub medias: Vec<Media>,
+}
+
+/// Creates the tables like [`migrate`], and saves a species with an occurrence that has a media for each url.
+pub async fn seed(transaction: &Transaction<'_>, urls: &[&str]) -> Seed {
+    seed_schema(transaction, "scraper_test", urls).await
+}
+
+/// Creates the tables like [`migrate_schema`], and saves a species with an occurrence that has a media for each url.
+pub async fn seed_schema(transaction: &Transaction<'_>, schema: &str, urls: &[&str]) -> Seed {
+    migrate_schema(transaction, schema).await;
+
+    let species = Species::from_taxref(apis_mellifera(), Some(1341976), 0)
+        .save(transaction)
+        .await
+        .unwrap();
+    let occurrence = Occurrence::create(1, Uuid::nil(), &species, None, None, None)
+        .save(transaction)
+        .await
+        .unwrap();
+
+    let mut medias = vec![];
+    for url in urls {
+        medias.push(
+            Media::new(url, &occurrence)
+                .save(transaction)
+                .await
+                .unwrap(),
+        );
+    }
+
+    Seed {
+        species,
+        occurrence,
+        medias,
+    }
+}
@@ -0,0 +1,61 @@
+use scraper::cropper::{Framing, Response};
+
+#[tokio::test]
+async fn reads_length_prefixed_messages() {
+    let mut bytes = Framing::LengthPrefixed.encode("{\"a\":\n1}").unwrap();
+    bytes.extend(Framing::LengthPrefixed.encode("{}").unwrap());
+    let mut reader = &bytes[..];
+
+    let framing = Framing::LengthPrefixed;
+    assert_eq!(
+        framing.read(&mut reader).await.unwrap().as_deref(),
+        Some("{\"a\":\n1}")
+    );
+    assert_eq!(
+        framing.read(&mut reader).await.unwrap().as_deref(),
+        Some("{}")
+    );
+    assert_eq!(framing.read(&mut reader).await.unwrap(), None);
+}
+
+#[tokio::test]
+async fn reads_newline_messages() {
+    let bytes = Framing::Newline.encode("{}").unwrap();
+    let mut reader = &bytes[..];
+
+    assert_eq!(
+        Framing::Newline.read(&mut reader).await.unwrap().as_deref(),
+        Some("{}\n")
+    );
+    assert_eq!(Framing::Newline.read(&mut reader).await.unwrap(), None);
+}
+
+#[tokio::test]
+async fn rejects_truncated_message() {
+    let bytes = Framing::LengthPrefixed
+        .encode("{\"type\":\"run\"}")
+        .unwrap();
+    let mut reader = &bytes[..bytes.len() - 1];
+
+    assert!(Framing::LengthPrefixed.read(&mut reader).await.is_err());
+}
+
+#[test]
+fn ready_without_framing_means_newline() {
+    let old: Response = serde_json::from_str("{\"type\": \"ready\"}").unwrap();
+    let new: Response =
+        serde_json::from_str("{\"type\": \"ready\", \"framing\": \"length_prefixed\"}").unwrap();
+
+    assert!(matches!(
+        old,
+        Response::Ready {
+            framing: Framing::Newline
+        }
+    ));
+    assert!(matches!(
+        new,
+        Response::Ready {
+            framing: Framing::LengthPrefixed
+        }
+    ));
+}
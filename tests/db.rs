@@ -0,0 +1,289 @@
+// Public so that the helpers only used by the other test files are not reported as dead code.
+pub mod common;
+
+use std::io::Write;
+use std::net::TcpListener;
+use std::path::PathBuf;
+use std::thread;
+use std::time::Duration;
+use std::{env, fs};
+
+use reqwest::Client;
+
+use scraper::Error;
+use scraper::db::{
+    ConvertFormat, EMPTY_RESPONSE, NOT_MODIFIED, convert_image, download_client, download_image,
+    download_image_if_modified, is_host_blacklisted, is_too_small,
+};
+use scraper::utils::is_transient;
+
+use common::{mock_server, read_request};
+
+/// The timeouts of the download clients, long enough for every test.
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The beginning of a PNG file, enough for its type to be recognized.
+const PNG: &[u8] = b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR";
+
+/// Builds an HTTP response that redirects to a path.
+fn redirect(path: &str) -> Vec<u8> {
+    format!(
+        "HTTP/1.1 302 Found\r\nLocation: {}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+        path
+    )
+    .into_bytes()
+}
+
+/// Builds an HTTP response with a body.
+fn ok(body: &[u8]) -> Vec<u8> {
+    let mut response = format!(
+        "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        body.len()
+    )
+    .into_bytes();
+    response.extend_from_slice(body);
+    response
+}
+
+/// Builds an HTTP response telling that the resource was not modified.
+fn not_modified() -> Vec<u8> {
+    b"HTTP/1.1 304 Not Modified\r\nETag: \"a\"\r\nConnection: close\r\n\r\n".to_vec()
+}
+
+/// Returns a path in the temporary directory that is unique to a test.
+fn target(name: &str) -> PathBuf {
+    env::temp_dir().join(format!("scraper-{}-{}", name, std::process::id()))
+}
+
+#[tokio::test]
+async fn rejects_empty_response() {
+    let url = mock_server(vec![ok(b"")]);
+    let target = target("empty");
+
+    let download = download_image(&Client::new(), &url, &target).await.unwrap();
+
+    assert_eq!(download.code, EMPTY_RESPONSE);
+    assert_eq!(download.extension, None);
+    assert!(!target.exists());
+}
+
+#[tokio::test]
+async fn follows_redirect_chain() {
+    let url = mock_server(vec![redirect("/b"), redirect("/c"), ok(PNG)]);
+    let target = target("redirect");
+    let client = download_client(5, TIMEOUT, TIMEOUT).unwrap();
+
+    let download = download_image(&client, &format!("{}/a", url), &target)
+        .await
+        .unwrap();
+
+    let written = target.with_extension("png");
+    let exists = written.exists();
+    fs::remove_file(&written).ok();
+
+    assert_eq!(download.code, 200);
+    assert_eq!(download.extension, Some("png"));
+    assert_eq!(download.final_url, format!("{}/c", url));
+    assert!(exists);
+}
+
+#[tokio::test]
+async fn stops_after_max_redirects() {
+    let url = mock_server(vec![redirect("/b"), redirect("/c"), redirect("/d")]);
+    let client = download_client(1, TIMEOUT, TIMEOUT).unwrap();
+
+    let result = download_image(&client, &format!("{}/a", url), &target("too-many")).await;
+
+    assert!(result.is_err());
+}
+
+#[tokio::test]
+async fn detects_redirect_loop() {
+    let url = mock_server(vec![redirect("/b"), redirect("/a")]);
+    let client = download_client(10, TIMEOUT, TIMEOUT).unwrap();
+
+    let result = download_image(&client, &format!("{}/a", url), &target("loop")).await;
+
+    assert!(result.is_err());
+}
+
+#[tokio::test]
+async fn keeps_validators_of_download() {
+    let mut response = format!(
+        "HTTP/1.1 200 OK\r\nETag: \"a\"\r\nLast-Modified: Wed, 21 Oct 2015 07:28:00 GMT\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        PNG.len()
+    )
+    .into_bytes();
+    response.extend_from_slice(PNG);
+
+    let url = mock_server(vec![response]);
+    let target = target("validators");
+
+    let download = download_image(&Client::new(), &url, &target).await.unwrap();
+    fs::remove_file(target.with_extension("png")).ok();
+
+    assert_eq!(download.etag.as_deref(), Some("\"a\""));
+    assert_eq!(
+        download.last_modified.as_deref(),
+        Some("Wed, 21 Oct 2015 07:28:00 GMT")
+    );
+}
+
+#[tokio::test]
+async fn skips_not_modified_media() {
+    let url = mock_server(vec![not_modified()]);
+    let target = target("not-modified");
+
+    let download =
+        download_image_if_modified(&Client::new(), &url, &target, Some("\"a\""), None, &[])
+            .await
+            .unwrap();
+
+    assert_eq!(download.code, NOT_MODIFIED);
+    assert_eq!(download.extension, None);
+    assert!(!target.with_extension("png").exists());
+}
+
+#[tokio::test]
+async fn rejects_mime_type_not_allowed() {
+    let url = mock_server(vec![ok(PNG)]);
+    let target = target("not-allowed");
+    let allowed = vec![String::from("image/jpeg")];
+
+    let download = download_image_if_modified(&Client::new(), &url, &target, None, None, &allowed)
+        .await
+        .unwrap();
+
+    assert_eq!(download.code, 200);
+    assert_eq!(download.extension, None);
+    assert!(!target.with_extension("png").exists());
+}
+
+#[test]
+fn converts_images() {
+    let png = target("convert").with_extension("png");
+    image::RgbaImage::from_pixel(4, 3, image::Rgba([200, 100, 50, 128]))
+        .save(&png)
+        .unwrap();
+
+    let jpg = convert_image(&png, ConvertFormat::Jpeg, 80).unwrap();
+    assert_eq!(jpg, png.with_extension("jpg"));
+    assert!(!png.exists());
+
+    let image = image::open(&jpg).unwrap();
+    assert_eq!((image.width(), image.height()), (4, 3));
+
+    // An image that can't be decoded is kept as it is.
+    let broken = target("convert-broken").with_extension("png");
+    fs::write(&broken, PNG).unwrap();
+    assert!(convert_image(&broken, ConvertFormat::Jpeg, 80).is_err());
+    assert!(broken.exists());
+    assert!(!broken.with_extension("jpg").exists());
+}
+
+#[tokio::test]
+async fn detects_type_of_dribbled_image() {
+    // WebP needs 12 bytes to be recognized, and the server sends 4 bytes at a time.
+    let body = b"RIFF\x24\0\0\0WEBPVP8 \x18\0\0\0".to_vec();
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+
+    thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        read_request(&stream);
+
+        let headers = format!(
+            "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+            body.len()
+        );
+        stream.write_all(headers.as_bytes()).unwrap();
+
+        for chunk in body.chunks(4) {
+            stream.write_all(chunk).unwrap();
+            stream.flush().unwrap();
+            thread::sleep(Duration::from_millis(20));
+        }
+    });
+
+    let target = target("dribbled");
+    let download = download_image(&Client::new(), &url, &target).await.unwrap();
+
+    assert_eq!(download.code, 200);
+    assert_eq!(download.extension, Some("webp"));
+    assert_eq!(
+        fs::read(target.with_extension("webp")).unwrap(),
+        b"RIFF\x24\0\0\0WEBPVP8 \x18\0\0\0"
+    );
+    fs::remove_file(target.with_extension("webp")).ok();
+}
+
+#[test]
+fn matches_blacklisted_hosts() {
+    let hosts = vec![String::from("example.com")];
+
+    assert!(is_host_blacklisted("https://example.com/a.jpg", &hosts));
+    assert!(is_host_blacklisted("http://img.Example.com/a.jpg", &hosts));
+    assert!(!is_host_blacklisted("https://notexample.com/a.jpg", &hosts));
+    assert!(!is_host_blacklisted(
+        "https://example.com.org/a.jpg",
+        &hosts
+    ));
+    assert!(!is_host_blacklisted("not an url", &hosts));
+}
+
+#[test]
+fn detects_too_small_images() {
+    let png = target("too-small").with_extension("png");
+    image::RgbaImage::from_pixel(40, 30, image::Rgba([0, 0, 0, 255]))
+        .save(&png)
+        .unwrap();
+
+    assert!(!is_too_small(&png, 0, 0));
+    assert!(!is_too_small(&png, 40, 30));
+    assert!(is_too_small(&png, 41, 0));
+    assert!(is_too_small(&png, 0, 31));
+
+    // An image whose size can't be read is kept.
+    fs::write(&png, b"not an image").unwrap();
+    assert!(!is_too_small(&png, 100, 100));
+}
+
+#[tokio::test]
+async fn times_out_slow_server() {
+    // The server accepts the connection but never answers.
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/slow.png", listener.local_addr().unwrap());
+    thread::spawn(move || {
+        let _streams = listener.incoming().collect::<Vec<_>>();
+    });
+
+    let client = download_client(1, TIMEOUT, Duration::from_millis(200)).unwrap();
+    let target = target("slow");
+
+    match download_image(&client, &url, &target).await {
+        Err(Error::ReqwestError(e)) => assert!(e.is_timeout()),
+        result => panic!("unexpected result: {:?}", result),
+    }
+}
+
+#[tokio::test]
+async fn removes_interrupted_download() {
+    // The server announces more bytes than it sends, then closes the connection.
+    let mut body = PNG.to_vec();
+    body.resize(100, 0);
+    let mut response =
+        b"HTTP/1.1 200 OK\r\nContent-Length: 1000\r\nConnection: close\r\n\r\n".to_vec();
+    response.extend_from_slice(&body);
+
+    let url = mock_server(vec![response]);
+    let target = target("interrupted");
+
+    match download_image(&Client::new(), &url, &target).await {
+        Err(e) => assert!(is_transient(&e)),
+        Ok(download) => panic!("unexpected download: {:?}", download),
+    }
+
+    assert!(!target.with_extension("png").exists());
+    assert!(!target.with_extension("png.part").exists());
+}
@@ -0,0 +1,98 @@
+use std::io::{Cursor, Write};
+
+use flate2::Compression;
+use flate2::write::DeflateEncoder;
+
+use scraper::dwca::{Table, extract};
+
+/// Builds a zip archive whose files are deflated.
+fn zip(files: &[(&str, &str)]) -> Vec<u8> {
+    let mut archive = vec![];
+    let mut central = vec![];
+
+    for (name, content) in files {
+        let mut encoder = DeflateEncoder::new(vec![], Compression::default());
+        encoder.write_all(content.as_bytes()).unwrap();
+        let data = encoder.finish().unwrap();
+
+        let offset = archive.len() as u32;
+        let sizes = [data.len() as u32, content.len() as u32];
+
+        archive.extend(0x04034b50u32.to_le_bytes());
+        archive.extend([20, 0, 0, 0, 8, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        sizes.iter().for_each(|x| archive.extend(x.to_le_bytes()));
+        archive.extend((name.len() as u16).to_le_bytes());
+        archive.extend([0, 0]);
+        archive.extend(name.as_bytes());
+        archive.extend(&data);
+
+        central.extend(0x02014b50u32.to_le_bytes());
+        central.extend([20, 0, 20, 0, 0, 0, 8, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        sizes.iter().for_each(|x| central.extend(x.to_le_bytes()));
+        central.extend((name.len() as u16).to_le_bytes());
+        central.extend([0; 12]);
+        central.extend(offset.to_le_bytes());
+        central.extend(name.as_bytes());
+    }
+
+    let central_offset = archive.len() as u32;
+    let count = files.len() as u16;
+
+    archive.extend(&central);
+    archive.extend(0x06054b50u32.to_le_bytes());
+    archive.extend([0, 0, 0, 0]);
+    archive.extend(count.to_le_bytes());
+    archive.extend(count.to_le_bytes());
+    archive.extend((central.len() as u32).to_le_bytes());
+    archive.extend(central_offset.to_le_bytes());
+    archive.extend([0, 0]);
+
+    archive
+}
+
+#[test]
+fn extracts_file_from_archive() {
+    let archive = zip(&[
+        ("meta.xml", "<archive></archive>"),
+        ("occurrence.txt", "gbifID\tdatasetKey\n1\tabc\n"),
+    ]);
+
+    let mut extracted = vec![];
+    let found = extract(&mut Cursor::new(&archive), "occurrence.txt", &mut extracted).unwrap();
+
+    assert!(found);
+    assert_eq!(extracted, b"gbifID\tdatasetKey\n1\tabc\n");
+
+    let found = extract(&mut Cursor::new(&archive), "multimedia.txt", &mut vec![]).unwrap();
+    assert!(!found);
+}
+
+#[test]
+fn rejects_invalid_archive() {
+    let result = extract(
+        &mut Cursor::new(b"not a zip"),
+        "occurrence.txt",
+        &mut vec![],
+    );
+    assert!(result.is_err());
+}
+
+#[test]
+fn reads_table_columns() {
+    let table =
+        "gbifID\ttype\tformat\tidentifier\n1\tStillImage\timage/jpeg\thttps://a\n2\tSound\n";
+    let rows = Table::new(table.as_bytes(), &["identifier", "gbifID"])
+        .unwrap()
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+
+    assert_eq!(
+        rows,
+        vec![
+            vec![String::from("https://a"), String::from("1")],
+            vec![String::from(""), String::from("2")],
+        ]
+    );
+
+    assert!(Table::new(table.as_bytes(), &["datasetKey"]).is_err());
+}
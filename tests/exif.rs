@@ -0,0 +1,63 @@
+use chrono::NaiveDate;
+
+use scraper::exif::parse;
+
+/// Appends an IFD entry in little endian.
+fn entry(bytes: &mut Vec<u8>, tag: u16, ty: u16, count: u32, value: u32) {
+    bytes.extend(tag.to_le_bytes());
+    bytes.extend(ty.to_le_bytes());
+    bytes.extend(count.to_le_bytes());
+    bytes.extend(value.to_le_bytes());
+}
+
+/// Builds a little endian EXIF chunk with a camera model, a capture date and a GPS position.
+fn chunk() -> Vec<u8> {
+    // Header, then IFD0 at 8 with 3 entries, ending at 8 + 2 + 3 * 12 + 4 = 50.
+    let mut bytes = b"II\x2a\x00\x08\x00\x00\x00".to_vec();
+    bytes.extend(3u16.to_le_bytes());
+    entry(&mut bytes, 0x0110, 2, 4, u32::from_le_bytes(*b"X10\0"));
+    entry(&mut bytes, 0x8769, 4, 1, 50);
+    entry(&mut bytes, 0x8825, 4, 1, 88);
+    bytes.extend(0u32.to_le_bytes());
+
+    // EXIF IFD at 50 with 1 entry, ending at 68, followed by the date.
+    bytes.extend(1u16.to_le_bytes());
+    entry(&mut bytes, 0x9003, 2, 20, 68);
+    bytes.extend(0u32.to_le_bytes());
+    bytes.extend(b"2024:05:17 14:03:21\0");
+
+    // GPS IFD at 88 with 4 entries, ending at 142, followed by the latitude and the longitude.
+    bytes.extend(4u16.to_le_bytes());
+    entry(&mut bytes, 1, 2, 2, u32::from_le_bytes(*b"N\0\0\0"));
+    entry(&mut bytes, 2, 5, 3, 142);
+    entry(&mut bytes, 3, 2, 2, u32::from_le_bytes(*b"W\0\0\0"));
+    entry(&mut bytes, 4, 5, 3, 166);
+    bytes.extend(0u32.to_le_bytes());
+
+    for (numerator, denominator) in [(45, 1), (30, 1), (0, 1), (1, 1), (15, 1), (36, 10)] {
+        bytes.extend((numerator as u32).to_le_bytes());
+        bytes.extend((denominator as u32).to_le_bytes());
+    }
+
+    bytes
+}
+
+#[test]
+fn parses_exif_chunk() {
+    let exif = parse(&chunk()).unwrap();
+
+    assert_eq!(exif.camera_make, None);
+    assert_eq!(exif.camera_model.as_deref(), Some("X10"));
+    assert_eq!(
+        exif.captured_at,
+        NaiveDate::from_ymd_opt(2024, 5, 17).and_then(|x| x.and_hms_opt(14, 3, 21))
+    );
+    assert_eq!(exif.latitude, Some(45.5));
+    assert!((exif.longitude.unwrap() + 1.251).abs() < 1e-9);
+}
+
+#[test]
+fn rejects_invalid_chunk() {
+    assert_eq!(parse(b"not exif"), None);
+    assert_eq!(parse(b"II\x2a\x00\xff\x00\x00\x00"), None);
+}
@@ -0,0 +1,32 @@
+use scraper::gbif::OccurrenceFilters;
+
+#[test]
+fn no_occurrence_filter_by_default() {
+    assert_eq!(OccurrenceFilters::default().query_params(), "");
+}
+
+#[test]
+fn builds_occurrence_filters() {
+    let filters = OccurrenceFilters {
+        has_coordinate: Some(true),
+        has_geospatial_issue: Some(false),
+        basis_of_record: vec![
+            String::from("human_observation"),
+            String::from("MACHINE_OBSERVATION"),
+        ],
+        country: Some(String::from("fr")),
+    };
+
+    assert_eq!(
+        filters.query_params(),
+        "&hasCoordinate=true&hasGeospatialIssue=false\
+         &basisOfRecord=HUMAN_OBSERVATION&basisOfRecord=MACHINE_OBSERVATION&country=FR"
+    );
+}
+
+#[test]
+fn validates_country() {
+    assert!(OccurrenceFilters::is_valid_country("FR"));
+    assert!(!OccurrenceFilters::is_valid_country("FRA"));
+    assert!(!OccurrenceFilters::is_valid_country("F1"));
+}
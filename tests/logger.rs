@@ -0,0 +1,59 @@
+use log::LevelFilter;
+
+use scraper::logger::{
+    Log, LogOptions, is_valid_timestamp_format, parse_env_filter, parse_module_levels,
+};
+
+#[test]
+fn parses_module_levels() {
+    let levels = parse_module_levels("scraper::gbif=trace, rocket", LevelFilter::Info).unwrap();
+
+    assert_eq!(
+        levels,
+        vec![
+            (String::from("scraper::gbif"), LevelFilter::Trace),
+            (String::from("rocket"), LevelFilter::Info),
+        ]
+    );
+}
+
+#[test]
+fn rejects_invalid_module_level() {
+    let error = parse_module_levels("scraper::gbif=loud", LevelFilter::Info).unwrap_err();
+    assert_eq!(error, "scraper::gbif=loud");
+}
+
+#[test]
+fn parses_env_filter() {
+    let (level, levels) = parse_env_filter("info,hyper=warn", LevelFilter::Error).unwrap();
+    assert_eq!(level, LevelFilter::Info);
+    assert_eq!(levels, vec![(String::from("hyper"), LevelFilter::Warn)]);
+
+    // Without a bare level, the default one is kept.
+    let (level, levels) = parse_env_filter("rocket", LevelFilter::Error).unwrap();
+    assert_eq!(level, LevelFilter::Error);
+    assert_eq!(levels, vec![(String::from("rocket"), LevelFilter::Error)]);
+
+    assert_eq!(
+        parse_env_filter("info,hyper=loud", LevelFilter::Error).unwrap_err(),
+        "hyper=loud"
+    );
+}
+
+#[test]
+fn init_is_idempotent() {
+    Log::init_null();
+    Log::init_null();
+
+    // The null logger is kept, so no log file is created.
+    let dir = std::env::temp_dir().join("scraper-test-init-is-idempotent");
+    Log::init(dir.clone(), vec![], LogOptions::default()).unwrap();
+    assert!(!dir.exists());
+}
+
+#[test]
+fn validates_timestamp_format() {
+    assert!(is_valid_timestamp_format("%Y-%m-%dT%H:%M:%S%:z"));
+    assert!(is_valid_timestamp_format("%d/%m/%Y %H:%M:%S"));
+    assert!(!is_valid_timestamp_format("%Y-%m-%d %Q"));
+}
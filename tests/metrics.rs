@@ -0,0 +1,50 @@
+use chrono::{TimeZone, Utc};
+
+use scraper::metrics::{Metrics, PoolUsage, ScrapSummary};
+
+#[test]
+fn renders_prometheus_metrics() {
+    let metrics = Metrics::default();
+    metrics.record(200);
+    metrics.record(200);
+    metrics.record(404);
+
+    let pool = PoolUsage {
+        in_use: 3,
+        max_size: 32,
+    };
+
+    let output = metrics.render(pool, None);
+
+    assert!(output.contains("# TYPE scraper_http_requests_total counter\n"));
+    assert!(output.contains("\nscraper_http_requests_total 3\n"));
+    assert!(output.contains("scraper_http_responses_total{status=\"200\"} 2\n"));
+    assert!(output.contains("scraper_http_responses_total{status=\"404\"} 1\n"));
+    assert!(output.contains("scraper_db_pool_connections_in_use 3\n"));
+    assert!(output.contains("scraper_db_pool_connections_max 32\n"));
+    assert!(!output.contains("scraper_last_scrap"));
+}
+
+#[test]
+fn renders_last_scrap_summary() {
+    let summary = ScrapSummary {
+        finished_at: Utc.with_ymd_and_hms(2024, 5, 17, 14, 3, 21).unwrap(),
+        duration_secs: 12.5,
+        species_scraped: 4,
+        species_failed: 1,
+        medias_downloaded: 20,
+        medias_failed: 2,
+    };
+
+    let pool = PoolUsage {
+        in_use: 0,
+        max_size: 32,
+    };
+
+    let output = Metrics::default().render(pool, Some(&summary));
+
+    assert!(output.contains("scraper_last_scrap_timestamp_seconds 1715954601\n"));
+    assert!(output.contains("scraper_last_scrap_duration_seconds 12.5\n"));
+    assert!(output.contains("scraper_last_scrap_species{outcome=\"failed\"} 1\n"));
+    assert!(output.contains("scraper_last_scrap_medias{outcome=\"downloaded\"} 20\n"));
+}
@@ -0,0 +1,1242 @@
+// Public so that the helpers only used by the other test files are not reported as dead code.
+pub mod common;
+
+use std::fs;
+use std::time::Duration;
+
+use image::codecs::jpeg::JpegEncoder;
+use image::{ExtendedColorType, ImageEncoder};
+
+use tokio::sync::Mutex;
+
+use uuid::Uuid;
+
+use scraper::db::{
+    ConvertFormat, CropBox, Dataset, ExampleMediaStrategy, IgnoredSpecies, Media, MediaStats,
+    Occurrence, RepresentativeMedia, Species, download_client, mark_to_download,
+    select_representative_media_per_occurrence,
+};
+use scraper::gbif::set_gbif_root;
+use scraper::taxref::Entry;
+use scraper::utils::sha256_file;
+use scraper::{Error, download};
+
+use common::{
+    Seed, apis_mellifera, config, connect, fixture, migrate, mock_gbif, seed, seed_schema,
+};
+
+/// Held by the tests that point the GBIF root to their mock server, since the root is shared by the whole process.
+static GBIF_ROOT_LOCK: Mutex<()> = Mutex::const_new(());
+
+#[tokio::test]
+#[ignore = "needs the database in SCRAPER_TEST_DATABASE_URL"]
+async fn scraps_occurrences_from_mock_gbif() {
+    let mut db = connect().await;
+
+    let _lock = GBIF_ROOT_LOCK.lock().await;
+    let root = mock_gbif(vec![
+        ("/species/search", fixture("species_search.json")),
+        (
+            "/occurrence/search?taxonKey=1341976&offset=0&",
+            fixture("occurrences_page_0.json"),
+        ),
+        (
+            "/occurrence/search?taxonKey=1341976&offset=2&",
+            fixture("occurrences_page_1.json"),
+        ),
+        (
+            "/species/1341976/vernacularNames",
+            fixture("vernacular_names.json"),
+        ),
+    ]);
+    set_gbif_root(&root);
+
+    let (config, _data) = config("scrap");
+    let transaction = db.transaction().await.unwrap();
+    migrate(&transaction).await;
+
+    let species = Species::scrap_occurrences(apis_mellifera(), 10, &config, &transaction)
+        .await
+        .unwrap();
+
+    assert!(species.done);
+    assert!(species.updated_at.is_some());
+    assert_eq!(species.species_key, Some(1341976));
+    assert_eq!(species.available_occurrences, 4);
+    assert_eq!(
+        species.vernacular_name.as_deref(),
+        Some("Abeille domestique")
+    );
+
+    // Both pages were fetched, but the occurrence whose media was already scraped is left out.
+    let mut keys = species
+        .occurrences(&transaction)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|x| x.key)
+        .collect::<Vec<_>>();
+    keys.sort();
+    assert_eq!(keys, vec![4001, 4003, 4004]);
+
+    let occurrence = Occurrence::get_by_key(4001, &transaction)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(
+        (occurrence.latitude, occurrence.longitude),
+        (Some(48.85), Some(2.35))
+    );
+
+    for url in [
+        "https://example.org/4001.jpg",
+        "https://example.org/4003-a.jpg",
+        "https://example.org/4003-b.jpg",
+    ] {
+        assert!(
+            Media::get_by_url(url, &transaction)
+                .await
+                .unwrap()
+                .is_some()
+        );
+    }
+
+    // The raw responses of both pages are kept.
+    let json = fs::read_to_string(config.storage.species_dir().join("1341976.json")).unwrap();
+    let json: serde_json::Value = serde_json::from_str(&json).unwrap();
+    assert_eq!(json["results"].as_array().unwrap().len(), 4);
+
+    // Scraping a species that is done doesn't query GBIF again.
+    let again = Species::scrap_occurrences(apis_mellifera(), 10, &config, &transaction)
+        .await
+        .unwrap();
+    assert_eq!(again.id, species.id);
+
+    transaction.rollback().await.unwrap();
+}
+
+#[tokio::test]
+#[ignore = "needs the database in SCRAPER_TEST_DATABASE_URL"]
+async fn scraps_only_whitelisted_datasets() {
+    let mut db = connect().await;
+
+    let _lock = GBIF_ROOT_LOCK.lock().await;
+    let root = mock_gbif(vec![
+        ("/species/search", fixture("species_search.json")),
+        (
+            "/occurrence/search?taxonKey=1341976&offset=0&",
+            fixture("occurrences_page_0.json"),
+        ),
+        (
+            "/occurrence/search?taxonKey=1341976&offset=2&",
+            fixture("occurrences_page_1.json"),
+        ),
+        (
+            "/species/1341976/vernacularNames",
+            fixture("vernacular_names.json"),
+        ),
+    ]);
+    set_gbif_root(&root);
+
+    // Only the dataset of the second page is allowed.
+    let (mut config, _data) = config("whitelist");
+    config.allowed_datasets =
+        vec![Uuid::parse_str("7a3679ef-5582-4aaa-81f0-8c2545cafc81").unwrap()];
+    assert!(config.is_dataset_allowed(&config.allowed_datasets[0]));
+    assert!(!config.is_dataset_allowed(&Uuid::nil()));
+
+    let transaction = db.transaction().await.unwrap();
+    migrate(&transaction).await;
+
+    let species = Species::scrap_occurrences(apis_mellifera(), 10, &config, &transaction)
+        .await
+        .unwrap();
+
+    let mut keys = species
+        .occurrences(&transaction)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|x| x.key)
+        .collect::<Vec<_>>();
+    keys.sort();
+    assert_eq!(keys, vec![4003, 4004]);
+
+    // The blacklist takes precedence over the whitelist.
+    config.blacklisted_datasets = config.allowed_datasets.clone();
+    assert!(!config.is_dataset_allowed(&config.allowed_datasets[0]));
+
+    transaction.rollback().await.unwrap();
+}
+
+#[tokio::test]
+#[ignore = "needs the database in SCRAPER_TEST_DATABASE_URL"]
+async fn marks_species_done_and_pending() {
+    let mut db = connect().await;
+
+    let transaction = db.transaction().await.unwrap();
+    let valid_name = seed(&transaction, &[]).await.species.valid_name;
+
+    assert!(Species::mark_done(&valid_name, &transaction).await.unwrap());
+    let species = Species::get_by_valid_name(&valid_name, &transaction)
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(species.done);
+    assert!(species.updated_at.is_some());
+
+    assert!(
+        Species::mark_pending(&valid_name, &transaction)
+            .await
+            .unwrap()
+    );
+    let species = Species::get_by_valid_name(&valid_name, &transaction)
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(!species.done);
+
+    assert!(
+        !Species::mark_done("Apis unknown", &transaction)
+            .await
+            .unwrap()
+    );
+
+    transaction.rollback().await.unwrap();
+}
+
+#[tokio::test]
+#[ignore = "needs the database in SCRAPER_TEST_DATABASE_URL"]
+async fn dedups_identical_medias() {
+    let mut db = connect().await;
+
+    let (config, _data) = config("dedup");
+    let storage = &config.storage;
+    let transaction = db.transaction().await.unwrap();
+
+    // The first file is missing, so the second one is promoted.
+    let files = [
+        ("a.jpg", "same"),
+        ("b.jpg", "same"),
+        ("c.jpg", "same"),
+        ("d.jpg", "other"),
+    ];
+    let urls = files.map(|(path, _)| format!("https://example.org/{}", path));
+    let seed = seed(&transaction, &urls.each_ref().map(String::as_str)).await;
+    fs::create_dir_all(storage.medias_root()).unwrap();
+    fs::create_dir_all(storage.cropped_root()).unwrap();
+
+    let mut ids = vec![];
+    for ((path, sha256), mut media) in files.into_iter().zip(seed.medias) {
+        media.path = Some(path.to_owned());
+        media.sha256 = Some(sha256.to_owned());
+        media.save(&transaction).await.unwrap();
+        ids.push(media.id);
+
+        if path != "a.jpg" {
+            fs::write(storage.medias_root().join(path), "content").unwrap();
+        }
+    }
+    fs::write(storage.cropped_root().join("c.jpg"), "crop").unwrap();
+
+    let dedup = Media::dedup(storage, &transaction).await.unwrap();
+    assert_eq!(dedup.medias, 2);
+
+    for (id, path) in ids.into_iter().zip(["b.jpg", "b.jpg", "b.jpg", "d.jpg"]) {
+        let media = Media::get_by_id(id, &transaction).await.unwrap().unwrap();
+        assert_eq!(media.path.as_deref(), Some(path));
+    }
+
+    // The crop of a redundant file replaces the missing crop of the canonical one.
+    assert_eq!(dedup.apply(storage).unwrap(), "content".len() as u64);
+    assert!(!storage.medias_root().join("c.jpg").exists());
+    assert!(storage.medias_root().join("b.jpg").exists());
+    assert!(storage.cropped_root().join("b.jpg").exists());
+    assert!(!storage.cropped_root().join("c.jpg").exists());
+
+    transaction.rollback().await.unwrap();
+}
+
+#[tokio::test]
+#[ignore = "needs the database in SCRAPER_TEST_DATABASE_URL"]
+async fn chooses_example_media() {
+    let mut db = connect().await;
+
+    let transaction = db.transaction().await.unwrap();
+    let Seed {
+        mut species,
+        medias,
+        ..
+    } = seed(&transaction, &["a", "b", "c", "d"]).await;
+
+    // The last media is not downloaded, so it is never chosen even with the highest confidence.
+    for ((path, confidence), mut media) in [
+        (Some("a.jpg"), Some(0.5)),
+        (Some("b.jpg"), Some(0.9)),
+        (Some("c.jpg"), None),
+        (None, Some(1.0)),
+    ]
+    .into_iter()
+    .zip(medias)
+    {
+        media.path = path.map(String::from);
+        media.status_code = path.map(|_| 200);
+        media.confidence = confidence;
+        media.save(&transaction).await.unwrap();
+    }
+
+    let path = species
+        .choose_example_media(ExampleMediaStrategy::HighestConfidence, &[], &transaction)
+        .await
+        .unwrap();
+    assert_eq!(path.as_deref(), Some("b.jpg"));
+
+    let path = species
+        .choose_example_media(ExampleMediaStrategy::FirstDownloaded, &[], &transaction)
+        .await
+        .unwrap();
+    assert_eq!(path.as_deref(), Some("a.jpg"));
+
+    let species = Species::get_by_id(species.id, &transaction)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(species.example_media_path.as_deref(), Some("a.jpg"));
+
+    // Medias of blacklisted datasets are left out.
+    let mut species = species;
+    let path = species
+        .choose_example_media(ExampleMediaStrategy::Random, &[Uuid::nil()], &transaction)
+        .await
+        .unwrap();
+    assert_eq!(path, None);
+    assert_eq!(species.example_media_path, None);
+
+    transaction.rollback().await.unwrap();
+}
+
+#[tokio::test]
+#[ignore = "needs the database in SCRAPER_TEST_DATABASE_URL"]
+async fn prefers_manual_crop_box() {
+    let mut db = connect().await;
+
+    let transaction = db.transaction().await.unwrap();
+    let mut media = seed(&transaction, &["https://example.org/a.jpg"])
+        .await
+        .medias
+        .remove(0);
+
+    assert_eq!(media.crop_box(), None);
+
+    (media.x, media.y, media.width, media.height) = (Some(10.0), Some(20.0), Some(4.0), Some(6.0));
+    media.confidence = Some(0.8);
+    let crop_box = media.crop_box().unwrap();
+    assert!(!crop_box.manual);
+    assert_eq!((crop_box.x, crop_box.confidence), (10.0, Some(0.8)));
+
+    (
+        media.manual_x,
+        media.manual_y,
+        media.manual_width,
+        media.manual_height,
+    ) = (Some(12.0), Some(22.0), Some(5.0), Some(7.0));
+    let crop_box = media.crop_box().unwrap();
+    assert!(crop_box.manual);
+    assert_eq!((crop_box.x, crop_box.confidence), (12.0, None));
+
+    transaction.rollback().await.unwrap();
+}
+
+#[tokio::test]
+#[ignore = "needs the database in SCRAPER_TEST_DATABASE_URL"]
+async fn finds_and_resets_missing_crops() {
+    let mut db = connect().await;
+
+    let (config, _data) = config("verify-crops");
+    let storage = &config.storage;
+    let transaction = db.transaction().await.unwrap();
+
+    // A cropped media with its cropped file, one without it, one that is not cropped, and one without its cropped file
+    // whose box was drawn manually.
+    let medias = [
+        ("kept", true),
+        ("lost", true),
+        ("uncropped", false),
+        ("manual", true),
+    ];
+    let seed = seed(&transaction, &medias.map(|(name, _)| name)).await;
+
+    let mut ids = vec![];
+    for ((name, cropped), mut media) in medias.into_iter().zip(seed.medias) {
+        media.path = Some(format!("crops/{}.jpg", name));
+        media.cropped = cropped;
+        if cropped {
+            (media.x, media.y, media.width, media.height) =
+                (Some(10.0), Some(20.0), Some(4.0), Some(6.0));
+        }
+        if name == "manual" {
+            (
+                media.manual_x,
+                media.manual_y,
+                media.manual_width,
+                media.manual_height,
+            ) = (Some(20.0), Some(20.0), Some(10.0), Some(8.0));
+        }
+        media.save(&transaction).await.unwrap();
+        ids.push(media.id);
+    }
+
+    fs::create_dir_all(storage.cropped_root().join("crops")).unwrap();
+    fs::write(storage.cropped_root().join("crops/kept.jpg"), b"jpg").unwrap();
+    fs::create_dir_all(storage.medias_root().join("crops")).unwrap();
+    image::RgbImage::from_pixel(40, 40, image::Rgb([200, 100, 50]))
+        .save(storage.medias_root().join("crops/manual.jpg"))
+        .unwrap();
+
+    let missing = Media::missing_crops(storage, &transaction).await.unwrap();
+    assert_eq!(
+        missing.iter().map(|x| x.id).collect::<Vec<_>>(),
+        vec![ids[1], ids[3]]
+    );
+
+    for mut media in missing {
+        media.reset_crop(storage).unwrap();
+        media.save(&transaction).await.unwrap();
+    }
+
+    let media = Media::get_by_id(ids[1], &transaction)
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(!media.cropped);
+    assert_eq!(media.crop_box(), None);
+
+    // The manual box is kept, and the cropped file is written again from it.
+    let media = Media::get_by_id(ids[3], &transaction)
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(media.cropped);
+    assert_eq!(media.x, None);
+    assert!(media.crop_box().unwrap().manual);
+    let cropped = image::open(storage.cropped_root().join("crops/manual.jpg")).unwrap();
+    assert_eq!((cropped.width(), cropped.height()), (10, 8));
+    assert!(
+        Media::missing_crops(storage, &transaction)
+            .await
+            .unwrap()
+            .is_empty()
+    );
+
+    transaction.rollback().await.unwrap();
+}
+
+#[tokio::test]
+#[ignore = "needs the database in SCRAPER_TEST_DATABASE_URL"]
+async fn crops_boxes_past_the_edges() {
+    let mut db = connect().await;
+
+    let (config, _data) = config("edge-crop");
+    let storage = &config.storage;
+    let transaction = db.transaction().await.unwrap();
+
+    let mut media = seed(&transaction, &["edge"]).await.medias.remove(0);
+    media.path = Some(String::from("crops/edge.jpg"));
+
+    fs::create_dir_all(storage.medias_root().join("crops")).unwrap();
+    image::RgbImage::from_pixel(40, 30, image::Rgb([200, 100, 50]))
+        .save(storage.medias_root().join("crops/edge.jpg"))
+        .unwrap();
+
+    // The boxes go past the top left and the bottom right corners of the image.
+    for ((x, y), size) in [((2.0, 3.0), (7, 7)), ((38.0, 29.0), (7, 5))] {
+        let crop_box = CropBox {
+            x,
+            y,
+            width: 10.0,
+            height: 8.0,
+            confidence: None,
+            manual: true,
+        };
+        media.write_crop(&crop_box, storage).unwrap();
+
+        let cropped = image::open(storage.cropped_root().join("crops/edge.jpg")).unwrap();
+        assert_eq!((cropped.width(), cropped.height()), size);
+    }
+
+    transaction.rollback().await.unwrap();
+}
+
+#[tokio::test]
+#[ignore = "needs the database in SCRAPER_TEST_DATABASE_URL"]
+async fn links_ignored_species_to_their_canonical_species() {
+    let mut db = connect().await;
+
+    let transaction = db.transaction().await.unwrap();
+    let species = seed(&transaction, &[]).await.species;
+
+    // Ignored species saved before the link existed, and one whose species key matches no species.
+    let mut synonym = apis_mellifera();
+    synonym.valid_name = String::from("Apis mellifica Linnaeus, 1761");
+    IgnoredSpecies::from_taxref(synonym, Some(1341976))
+        .save(&transaction)
+        .await
+        .unwrap();
+
+    let mut unrelated = apis_mellifera();
+    unrelated.valid_name = String::from("Apis unknown");
+    IgnoredSpecies::from_taxref(unrelated, Some(1))
+        .save(&transaction)
+        .await
+        .unwrap();
+
+    assert!(species.synonyms(&transaction).await.unwrap().is_empty());
+
+    assert_eq!(
+        IgnoredSpecies::link_canonical(&transaction).await.unwrap(),
+        1
+    );
+    assert_eq!(
+        IgnoredSpecies::link_canonical(&transaction).await.unwrap(),
+        0
+    );
+
+    let synonyms = species.synonyms(&transaction).await.unwrap();
+    assert_eq!(synonyms.len(), 1);
+    assert_eq!(synonyms[0].valid_name, "Apis mellifica Linnaeus, 1761");
+    assert_eq!(synonyms[0].canonical_species_key, Some(1341976));
+
+    transaction.rollback().await.unwrap();
+}
+
+#[tokio::test]
+#[ignore = "needs the database in SCRAPER_TEST_DATABASE_URL"]
+async fn marks_medias_to_download() {
+    let mut db = connect().await;
+
+    let transaction = db.transaction().await.unwrap();
+
+    // Two occurrences with two medias each, the second one in a blacklisted dataset.
+    let seed = seed(&transaction, &["1-0", "1-1"]).await;
+    let mut ids = seed.medias.iter().map(|x| x.id).collect::<Vec<_>>();
+
+    let blacklisted = Uuid::from_u128(1);
+    let occurrence = Occurrence::create(2, blacklisted, &seed.species, None, None, None)
+        .save(&transaction)
+        .await
+        .unwrap();
+    for url in ["2-0", "2-1"] {
+        let media = Media::new(url, &occurrence)
+            .save(&transaction)
+            .await
+            .unwrap();
+        ids.push(media.id);
+    }
+
+    let marked = |expected: Vec<bool>| {
+        let transaction = &transaction;
+        let ids = ids.clone();
+        async move {
+            let mut marks = vec![];
+            for id in ids {
+                let media = Media::get_by_id(id, transaction).await.unwrap().unwrap();
+                marks.push(media.to_download);
+            }
+            assert_eq!(marks, expected);
+        }
+    };
+
+    // With enough occurrences, only the first media of each occurrence is downloaded.
+    let count = mark_to_download(
+        1,
+        0,
+        RepresentativeMedia::First,
+        &[blacklisted],
+        &transaction,
+    )
+    .await
+    .unwrap();
+    assert_eq!(count, 1);
+    marked(vec![true, false, false, false]).await;
+
+    // With too few occurrences, every media of the species is, and the blacklist is still respected.
+    let count = mark_to_download(
+        2,
+        0,
+        RepresentativeMedia::First,
+        &[blacklisted],
+        &transaction,
+    )
+    .await
+    .unwrap();
+    assert_eq!(count, 2);
+    marked(vec![true, true, false, false]).await;
+
+    // Marking again clears the previous marks.
+    let count = mark_to_download(1, 0, RepresentativeMedia::First, &[], &transaction)
+        .await
+        .unwrap();
+    assert_eq!(count, 2);
+    marked(vec![true, false, true, false]).await;
+
+    transaction.rollback().await.unwrap();
+}
+
+#[tokio::test]
+#[ignore = "needs the database in SCRAPER_TEST_DATABASE_URL"]
+async fn caps_medias_per_species() {
+    let mut db = connect().await;
+
+    let transaction = db.transaction().await.unwrap();
+
+    // Three occurrences with three medias each.
+    let seed = seed(&transaction, &["1-0", "1-1", "1-2"]).await;
+    let mut ids = seed.medias.iter().map(|x| x.id).collect::<Vec<_>>();
+
+    for key in 2..=3 {
+        let occurrence = Occurrence::create(key, Uuid::nil(), &seed.species, None, None, None)
+            .save(&transaction)
+            .await
+            .unwrap();
+
+        for i in 0..3 {
+            let url = format!("https://example.org/{}-{}.jpg", key, i);
+            let media = Media::new(&url, &occurrence)
+                .save(&transaction)
+                .await
+                .unwrap();
+            ids.push(media.id);
+        }
+    }
+
+    let marked = async || {
+        let mut marked = vec![];
+        for (i, id) in ids.iter().enumerate() {
+            let media = Media::get_by_id(*id, &transaction).await.unwrap().unwrap();
+            if media.to_download {
+                marked.push(i);
+            }
+        }
+        marked
+    };
+
+    // The species is sparse, so every media would be marked, but the first media of each occurrence come first.
+    let count = mark_to_download(10, 4, RepresentativeMedia::First, &[], &transaction)
+        .await
+        .unwrap();
+    assert_eq!(count, 4);
+    assert_eq!(marked().await, vec![0, 1, 3, 6]);
+
+    // A cap below the number of occurrences leaves out some occurrences.
+    let count = mark_to_download(1, 2, RepresentativeMedia::First, &[], &transaction)
+        .await
+        .unwrap();
+    assert_eq!(count, 2);
+    assert_eq!(marked().await, vec![0, 3]);
+
+    // Without a cap, every media is marked.
+    let count = mark_to_download(10, 0, RepresentativeMedia::First, &[], &transaction)
+        .await
+        .unwrap();
+    assert_eq!(count, 9);
+
+    transaction.rollback().await.unwrap();
+}
+
+#[tokio::test]
+#[ignore = "needs the database in SCRAPER_TEST_DATABASE_URL"]
+async fn counts_medias_by_dataset() {
+    let mut db = connect().await;
+
+    let (mut config, _data) = config("dataset-counts");
+    let transaction = db.transaction().await.unwrap();
+
+    // The seeded occurrence is in a dataset whose title is unknown, and has a downloaded media.
+    let Seed {
+        species,
+        occurrence,
+        mut medias,
+    } = seed(&transaction, &["1-0"]).await;
+    medias[0].status_code = Some(200);
+    medias[0].path = Some(String::from("1-0.jpg"));
+    medias[0].x = Some(1.0);
+    medias[0].save(&transaction).await.unwrap();
+
+    let known = Uuid::from_u128(1);
+    let unknown = occurrence.dataset_key;
+    let blacklisted = Uuid::from_u128(3);
+    config.blacklisted_datasets = vec![blacklisted];
+
+    Dataset::create(known, String::from("Known"), None)
+        .save(&transaction)
+        .await
+        .unwrap();
+
+    // Other occurrences with their numbers of downloaded medias and of medias that failed.
+    for (key, dataset_key, downloaded, failed) in
+        [(2, known, 2, 1), (3, known, 0, 1), (4, blacklisted, 3, 0)]
+    {
+        let occurrence = Occurrence::create(key, dataset_key, &species, None, None, None)
+            .save(&transaction)
+            .await
+            .unwrap();
+
+        for i in 0..downloaded + failed {
+            let url = format!("https://example.org/{}-{}.jpg", key, i);
+            let mut media = Media::new(&url, &occurrence)
+                .save(&transaction)
+                .await
+                .unwrap();
+            if i < downloaded {
+                media.status_code = Some(200);
+                media.path = Some(format!("{}-{}.jpg", key, i));
+            } else {
+                media.status_code = Some(404);
+            }
+            media.save(&transaction).await.unwrap();
+        }
+    }
+
+    let counts = species
+        .dataset_counts(false, &config, &transaction)
+        .await
+        .unwrap();
+    let counts = counts
+        .iter()
+        .map(|x| (x.key, x.title.as_deref(), x.occurrences, x.medias))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        counts,
+        vec![(known, Some("Known"), 2, 2), (unknown, None, 1, 1)]
+    );
+
+    // The blacklisted datasets can be included, flagged as such.
+    let counts = species
+        .dataset_counts(true, &config, &transaction)
+        .await
+        .unwrap();
+    let counts = counts
+        .iter()
+        .map(|x| (x.key, x.blacklisted, x.medias))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        counts,
+        vec![
+            (blacklisted, true, 3),
+            (known, false, 2),
+            (unknown, false, 1)
+        ]
+    );
+
+    // The numbers of medias of the species come from the same counts, without the blacklisted datasets.
+    let stats = species
+        .media_stats(false, &config, &transaction)
+        .await
+        .unwrap();
+    assert_eq!(
+        stats,
+        MediaStats {
+            total: 5,
+            downloaded: 3,
+            cropped: 1
+        }
+    );
+
+    let stats = species
+        .media_stats(true, &config, &transaction)
+        .await
+        .unwrap();
+    assert_eq!(
+        stats,
+        MediaStats {
+            total: 4,
+            downloaded: 2,
+            cropped: 0
+        }
+    );
+
+    transaction.rollback().await.unwrap();
+}
+
+#[tokio::test]
+#[ignore = "needs the database in SCRAPER_TEST_DATABASE_URL"]
+async fn selects_one_media_per_occurrence() {
+    let mut db = connect().await;
+
+    let transaction = db.transaction().await.unwrap();
+
+    // Occurrences with one, two and three medias, and one in a blacklisted dataset.
+    let seed = seed(&transaction, &["1-0"]).await;
+    let blacklisted = Uuid::from_u128(1);
+    let mut firsts = vec![seed.medias[0].id];
+    let mut ids = firsts.clone();
+
+    for (key, count, dataset_key) in [
+        (2, 2, Uuid::nil()),
+        (3, 3, Uuid::nil()),
+        (4, 2, blacklisted),
+    ] {
+        let occurrence = Occurrence::create(key, dataset_key, &seed.species, None, None, None)
+            .save(&transaction)
+            .await
+            .unwrap();
+
+        for i in 0..count {
+            let url = format!("https://example.org/{}-{}.jpg", key, i);
+            let media = Media::new(&url, &occurrence)
+                .save(&transaction)
+                .await
+                .unwrap();
+
+            if i == 0 && dataset_key != blacklisted {
+                firsts.push(media.id);
+            }
+            ids.push(media.id);
+        }
+    }
+
+    let mut chosen = select_representative_media_per_occurrence(
+        RepresentativeMedia::First,
+        &[blacklisted],
+        &transaction,
+    )
+    .await
+    .unwrap();
+    chosen.sort();
+    assert_eq!(chosen, firsts);
+
+    for id in ids {
+        let media = Media::get_by_id(id, &transaction).await.unwrap().unwrap();
+        assert_eq!(media.to_download, firsts.contains(&id));
+    }
+
+    transaction.rollback().await.unwrap();
+}
+
+#[tokio::test]
+#[ignore = "needs the database in SCRAPER_TEST_DATABASE_URL"]
+async fn selects_representative_media_by_policy() {
+    let mut db = connect().await;
+
+    let transaction = db.transaction().await.unwrap();
+
+    // The second media of the occurrence was downloaded by a previous scrap.
+    let mut seed = seed(&transaction, &["1-0", "1-1", "1-2"]).await;
+    seed.medias[1].path = Some(String::from("Apis mellifera/1-1.jpg"));
+    seed.medias[1].save(&transaction).await.unwrap();
+    let ids = seed.medias.iter().map(|media| media.id).collect::<Vec<_>>();
+
+    let chosen = select_representative_media_per_occurrence(
+        RepresentativeMedia::Downloaded,
+        &[],
+        &transaction,
+    )
+    .await
+    .unwrap();
+    assert_eq!(chosen, [ids[1]]);
+
+    let mut media = Media::get_by_id(ids[1], &transaction)
+        .await
+        .unwrap()
+        .unwrap();
+    media.to_download = false;
+    media.save(&transaction).await.unwrap();
+
+    let chosen =
+        select_representative_media_per_occurrence(RepresentativeMedia::Random, &[], &transaction)
+            .await
+            .unwrap();
+    assert_eq!(chosen.len(), 1);
+    assert!(ids.contains(&chosen[0]));
+
+    transaction.rollback().await.unwrap();
+}
+
+#[tokio::test]
+#[ignore = "needs the database in SCRAPER_TEST_DATABASE_URL"]
+async fn skips_attempted_medias() {
+    let mut db = connect().await;
+
+    let (config, _data) = config("skip-attempted");
+    let transaction = db.transaction().await.unwrap();
+
+    // The media was attempted before an interruption, its url would fail if it was downloaded again.
+    let mut media = seed(&transaction, &["http://127.0.0.1:1/a.jpg"])
+        .await
+        .medias
+        .remove(0);
+    media.to_download = true;
+    media.status_code = Some(200);
+    media.path = Some(String::from("a.jpg"));
+    media.save(&transaction).await.unwrap();
+
+    let client = download_client(0, Duration::from_secs(1), Duration::from_secs(1)).unwrap();
+    let status = media
+        .download(&client, &config, &transaction)
+        .await
+        .unwrap();
+    assert_eq!(status, 200);
+
+    let media = Media::get_by_id(media.id, &transaction)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(
+        (media.status_code, media.path.as_deref()),
+        (Some(200), Some("a.jpg"))
+    );
+
+    transaction.rollback().await.unwrap();
+}
+
+/// Encodes a small JPEG whose EXIF metadata only has a camera model.
+fn jpeg_with_exif() -> Vec<u8> {
+    // Header, then IFD0 at 8 with the model, which fits in the value of its entry.
+    let mut exif = b"II\x2a\x00\x08\x00\x00\x00".to_vec();
+    exif.extend(1u16.to_le_bytes());
+    exif.extend(0x0110u16.to_le_bytes());
+    exif.extend(2u16.to_le_bytes());
+    exif.extend(4u32.to_le_bytes());
+    exif.extend(b"X10\0");
+    exif.extend(0u32.to_le_bytes());
+
+    let mut jpeg = vec![];
+    let mut encoder = JpegEncoder::new(&mut jpeg);
+    encoder.set_exif_metadata(exif).unwrap();
+    encoder
+        .write_image(&[128; 4 * 3 * 3], 4, 3, ExtendedColorType::Rgb8)
+        .unwrap();
+
+    jpeg
+}
+
+#[tokio::test]
+#[ignore = "needs the database in SCRAPER_TEST_DATABASE_URL"]
+async fn reads_exif_before_converting_medias() {
+    let mut db = connect().await;
+
+    let jpeg = jpeg_with_exif();
+    let root = mock_gbif(vec![("/a.jpg", jpeg.clone())]);
+
+    let (mut config, data) = config("convert-exif");
+    config.convert_to = Some(ConvertFormat::Png);
+    fs::write(data.path().join("original.jpg"), &jpeg).unwrap();
+    let sha256 = sha256_file(data.path().join("original.jpg")).unwrap();
+
+    let transaction = db.transaction().await.unwrap();
+    let Seed {
+        species,
+        mut medias,
+        ..
+    } = seed(&transaction, &[&format!("{}/a.jpg", root)]).await;
+    fs::create_dir_all(config.storage.medias_dir(&species)).unwrap();
+
+    let client = download_client(0, Duration::from_secs(1), Duration::from_secs(1)).unwrap();
+    let media = &mut medias[0];
+    let status = media
+        .download(&client, &config, &transaction)
+        .await
+        .unwrap();
+    assert_eq!(status, 200);
+
+    // The file is converted, but its metadata and hash are those of the downloaded JPEG.
+    assert!(media.path.as_deref().unwrap().ends_with(".png"));
+    assert_eq!(media.content_type.as_deref(), Some("image/png"));
+    assert_eq!(media.camera_model.as_deref(), Some("X10"));
+    assert_eq!(media.sha256, Some(sha256));
+
+    transaction.rollback().await.unwrap();
+}
+
+#[tokio::test]
+#[ignore = "needs the database in SCRAPER_TEST_DATABASE_URL"]
+async fn downloads_marked_medias() {
+    let mut db = connect().await;
+
+    // The downloads use their own connections, so the tables are committed in a schema that is dropped at the end.
+    let schema = "scraper_download";
+    db.client
+        .batch_execute(&format!("DROP SCHEMA IF EXISTS {} CASCADE", schema))
+        .await
+        .unwrap();
+
+    let root = mock_gbif(vec![("/a.gif", String::from("GIF89a\x01\0\x01\0\0\0\0;"))]);
+
+    let (mut config, _data) = config("download");
+    config.databases.database.url = format!(
+        "{}?options=-csearch_path%3D{}",
+        config.databases.database.url, schema
+    );
+
+    let transaction = db.transaction().await.unwrap();
+
+    // The second media is missing from the server, and the last one is not marked.
+    let medias = [("/a.gif", true), ("/b.gif", true), ("/c.gif", false)];
+    let urls = medias.map(|(path, _)| format!("{}{}", root, path));
+    let Seed {
+        species,
+        medias: seeded,
+        ..
+    } = seed_schema(&transaction, schema, &urls.each_ref().map(String::as_str)).await;
+
+    let mut ids = vec![];
+    for ((_, to_download), mut media) in medias.into_iter().zip(seeded) {
+        media.to_download = to_download;
+        media.save(&transaction).await.unwrap();
+        ids.push(media.id);
+    }
+
+    // Another species already has a downloaded media but no example, which only pick-examples chooses.
+    let other = Species::from_taxref(
+        Entry {
+            valid_name: String::from("Apis cerana Fabricius, 1793"),
+            ..apis_mellifera()
+        },
+        Some(1341977),
+        0,
+    )
+    .save(&transaction)
+    .await
+    .unwrap();
+    let occurrence = Occurrence::create(2, Uuid::nil(), &other, None, None, None)
+        .save(&transaction)
+        .await
+        .unwrap();
+    let mut media = Media::new(&format!("{}/d.gif", root), &occurrence)
+        .save(&transaction)
+        .await
+        .unwrap();
+    media.status_code = Some(200);
+    media.path = Some(String::from("Apis cerana/d.gif"));
+    media.save(&transaction).await.unwrap();
+
+    transaction.commit().await.unwrap();
+    fs::create_dir_all(config.storage.medias_dir(&species)).unwrap();
+
+    let summary = download(false, &config).await;
+
+    db.client
+        .batch_execute(&format!("SET search_path TO {}", schema))
+        .await
+        .unwrap();
+
+    let mut statuses = vec![];
+    for id in &ids {
+        let media = Media::get_by_id(*id, &db).await;
+        statuses.push(media.map(|x| x.map(|x| (x.status_code, x.path.is_some()))));
+    }
+
+    let mut examples = vec![];
+    for id in [species.id, other.id] {
+        let species = Species::get_by_id(id, &db).await;
+        examples.push(species.map(|x| x.map(|x| x.example_media_path)));
+    }
+
+    db.client
+        .batch_execute(&format!(
+            "RESET search_path; DROP SCHEMA {} CASCADE",
+            schema
+        ))
+        .await
+        .unwrap();
+
+    let summary = summary.unwrap();
+    assert_eq!((summary.medias_downloaded, summary.medias_failed), (1, 1));
+    assert_eq!(
+        statuses
+            .into_iter()
+            .map(|x| x.unwrap().unwrap())
+            .collect::<Vec<_>>(),
+        vec![(Some(200), true), (Some(404), false), (None, false)]
+    );
+
+    // Only the species that got new medias had their example chosen.
+    let examples = examples
+        .into_iter()
+        .map(|x| x.unwrap().unwrap())
+        .collect::<Vec<_>>();
+    assert!(examples[0].is_some());
+    assert_eq!(examples[1], None);
+}
+
+#[tokio::test]
+#[ignore = "needs the database in SCRAPER_TEST_DATABASE_URL"]
+async fn backfills_example_medias() {
+    let mut db = connect().await;
+
+    let transaction = db.transaction().await.unwrap();
+    migrate(&transaction).await;
+
+    // The first species has a downloaded media but no example, the second one already has an example, and the last
+    // one has nothing downloaded.
+    let mut ids = vec![];
+    for (index, (example, downloaded)) in [(None, true), (Some("old.jpg"), true), (None, false)]
+        .into_iter()
+        .enumerate()
+    {
+        let mut entry = apis_mellifera();
+        entry.valid_name = format!("{} {}", entry.valid_name, index);
+
+        let mut species = Species::from_taxref(entry, Some(index as i64), 0)
+            .save(&transaction)
+            .await
+            .unwrap();
+        species.example_media_path = example.map(String::from);
+        species.save(&transaction).await.unwrap();
+
+        let occurrence = Occurrence::create(index as i64, Uuid::nil(), &species, None, None, None)
+            .save(&transaction)
+            .await
+            .unwrap();
+
+        let mut media = Media::new(&format!("https://example.org/{}", index), &occurrence)
+            .save(&transaction)
+            .await
+            .unwrap();
+        media.path = downloaded.then(|| format!("{}.jpg", index));
+        media.status_code = downloaded.then_some(200);
+        media.save(&transaction).await.unwrap();
+
+        ids.push(species.id);
+    }
+
+    let backfilled = Species::backfill_example_medias(
+        ExampleMediaStrategy::HighestConfidence,
+        &[],
+        &transaction,
+    )
+    .await
+    .unwrap();
+    assert_eq!(backfilled, 1);
+
+    let mut paths = vec![];
+    for id in ids {
+        let species = Species::get_by_id(id, &transaction).await.unwrap().unwrap();
+        paths.push(species.example_media_path);
+    }
+
+    assert_eq!(
+        paths,
+        vec![
+            Some(String::from("0.jpg")),
+            Some(String::from("old.jpg")),
+            None
+        ]
+    );
+
+    transaction.rollback().await.unwrap();
+}
+
+#[tokio::test]
+#[ignore = "needs the database in SCRAPER_TEST_DATABASE_URL"]
+async fn lists_and_clears_ignored_species() {
+    let mut db = connect().await;
+
+    let transaction = db.transaction().await.unwrap();
+    migrate(&transaction).await;
+
+    for (valid_name, species_key) in [("Apis b", 2), ("Apis a", 1), ("Apis c", 3)] {
+        let mut entry = apis_mellifera();
+        entry.valid_name = String::from(valid_name);
+        IgnoredSpecies::from_taxref(entry, Some(species_key))
+            .save(&transaction)
+            .await
+            .unwrap();
+    }
+
+    let names = |ignored: Vec<IgnoredSpecies>| {
+        ignored
+            .into_iter()
+            .map(|x| (x.valid_name, x.species_key))
+            .collect::<Vec<_>>()
+    };
+
+    assert_eq!(
+        names(IgnoredSpecies::list(&transaction).await.unwrap()),
+        vec![
+            (String::from("Apis a"), Some(1)),
+            (String::from("Apis b"), Some(2)),
+            (String::from("Apis c"), Some(3)),
+        ]
+    );
+
+    assert_eq!(
+        IgnoredSpecies::clear(Some("Apis b"), &transaction)
+            .await
+            .unwrap(),
+        1
+    );
+    assert_eq!(
+        IgnoredSpecies::clear(Some("Apis b"), &transaction)
+            .await
+            .unwrap(),
+        0
+    );
+    assert_eq!(
+        names(IgnoredSpecies::list(&transaction).await.unwrap()),
+        vec![
+            (String::from("Apis a"), Some(1)),
+            (String::from("Apis c"), Some(3)),
+        ]
+    );
+
+    assert_eq!(IgnoredSpecies::clear(None, &transaction).await.unwrap(), 2);
+    assert!(IgnoredSpecies::list(&transaction).await.unwrap().is_empty());
+
+    transaction.rollback().await.unwrap();
+}
+
+#[tokio::test]
+#[ignore = "needs the database in SCRAPER_TEST_DATABASE_URL"]
+async fn scraps_occurrences_by_species_key() {
+    let mut db = connect().await;
+
+    let _lock = GBIF_ROOT_LOCK.lock().await;
+    let root = mock_gbif(vec![
+        ("/species/1341976", fixture("species_details.json")),
+        (
+            "/occurrence/search?taxonKey=1341976&offset=0&",
+            fixture("occurrences_page_0.json"),
+        ),
+        (
+            "/occurrence/search?taxonKey=1341976&offset=2&",
+            fixture("occurrences_page_1.json"),
+        ),
+        (
+            "/species/1341976/vernacularNames",
+            fixture("vernacular_names.json"),
+        ),
+    ]);
+    set_gbif_root(&root);
+
+    let (config, _data) = config("scrap-key");
+    let transaction = db.transaction().await.unwrap();
+    migrate(&transaction).await;
+
+    let species = Species::scrap_occurrences_by_key(1341976, 10, &config, &transaction)
+        .await
+        .unwrap();
+
+    assert!(species.done);
+    assert_eq!(species.species_key, Some(1341976));
+    assert_eq!(species.valid_name, "Apis mellifera Linnaeus, 1758");
+    assert_eq!(
+        (species.reign.as_str(), species.family.as_str()),
+        ("Animalia", "Apidae")
+    );
+    assert_eq!(species.occurrences(&transaction).await.unwrap().len(), 3);
+
+    // The species is found by its valid name when scraped from taxref afterwards.
+    let again = Species::scrap_occurrences(apis_mellifera(), 10, &config, &transaction)
+        .await
+        .unwrap();
+    assert_eq!(again.id, species.id);
+
+    // A species key unknown to GBIF is not found.
+    match Species::scrap_occurrences_by_key(1, 10, &config, &transaction).await {
+        Err(Error::SpeciesNotFound(key)) => assert_eq!(key, "1"),
+        result => panic!("unexpected result: {:?}", result.map(|x| x.id)),
+    }
+
+    transaction.rollback().await.unwrap();
+}
@@ -0,0 +1,266 @@
+// Public so that the helpers only used by the other test files are not reported as dead code.
+pub mod common;
+
+use std::fs;
+
+use rocket::http::{Accept, ContentType, Header, Status};
+use rocket::local::asynchronous::Client as AsyncClient;
+use rocket::local::blocking::Client;
+use rocket::{get, routes};
+
+use serde_json::Value;
+
+use uuid::Uuid;
+
+use scraper::db::Dataset;
+use scraper::server::{ClientIp, DatasetTitles};
+use scraper::{Error, Result};
+
+use common::{config, connect, migrate_schema, seed_schema};
+
+#[get("/api/species")]
+fn missing_species() -> Result<&'static str> {
+    Err(Error::SpeciesNotFound(String::from("Apis mellifera")))
+}
+
+#[get("/ip")]
+fn ip(ip: ClientIp) -> String {
+    ip.0
+}
+
+#[get("/page")]
+fn broken_page() -> Result<&'static str> {
+    Err(Error::DbError)
+}
+
+fn client() -> Client {
+    Client::tracked(rocket::build().mount("/", routes![missing_species, broken_page])).unwrap()
+}
+
+#[test]
+fn api_errors_are_json() {
+    let client = client();
+    let response = client.get("/api/species").dispatch();
+
+    assert_eq!(response.status(), Status::NotFound);
+    let body: Value = response.into_json().unwrap();
+    assert_eq!(body["kind"], "species_not_found");
+    assert_eq!(
+        body["error"],
+        "species \"Apis mellifera\" was not found on GBIF"
+    );
+}
+
+#[test]
+fn internal_errors_hide_their_details() {
+    let client = client();
+    let response = client.get("/page").header(Accept::JSON).dispatch();
+
+    assert_eq!(response.status(), Status::InternalServerError);
+    let body: Value = response.into_json().unwrap();
+    assert_eq!(body["kind"], "db");
+    assert_eq!(body["error"], "internal server error");
+}
+
+#[test]
+fn page_errors_go_to_catchers() {
+    let client = client();
+    let response = client.get("/page").header(Accept::HTML).dispatch();
+
+    assert_eq!(response.status(), Status::InternalServerError);
+    assert_ne!(
+        response.content_type(),
+        Some(rocket::http::ContentType::JSON)
+    );
+}
+
+#[test]
+fn occurrence_files_are_still_served() {
+    let (config, _data) = config("routes");
+    fs::write(config.storage.species_dir().join("1341976.json"), "{}").unwrap();
+
+    // Both routes share their path, the files are served first and the rest is forwarded to the paginated one.
+    let rocket = rocket::build().manage(config).mount(
+        "/",
+        routes![
+            scraper::server::occurrences,
+            scraper::server::occurrences_json
+        ],
+    );
+
+    let client = Client::untracked(rocket).unwrap();
+    let response = client.get("/api/occurrences/1341976.json").dispatch();
+
+    assert_eq!(response.status(), Status::Ok);
+    assert_eq!(response.into_string().as_deref(), Some("{}"));
+}
+
+#[test]
+fn limits_apply_to_the_address_seen_by_the_proxy() {
+    let (mut config, _data) = config("client-ip");
+    config.trust_proxy = true;
+
+    let rocket = rocket::build().manage(config).mount("/", routes![ip]);
+    let client = Client::untracked(rocket).unwrap();
+
+    // The client sent the first address itself, the proxy appended the last one.
+    let response = client
+        .get("/ip")
+        .header(Header::new("X-Forwarded-For", "10.0.0.1, 192.0.2.7"))
+        .dispatch();
+
+    assert_eq!(response.into_string().as_deref(), Some("192.0.2.7"));
+}
+
+#[test]
+fn json_routes_set_content_type() {
+    let (mut config, _data) = config("blacklist");
+    config.blacklisted_datasets = vec![];
+
+    let rocket = rocket::build()
+        .manage(config)
+        .manage(DatasetTitles::default())
+        .mount("/", routes![scraper::server::blacklist]);
+
+    let client = Client::untracked(rocket).unwrap();
+    let response = client.get("/api/blacklist").dispatch();
+
+    assert_eq!(response.status(), Status::Ok);
+    assert_eq!(response.content_type(), Some(ContentType::JSON));
+    assert_eq!(
+        response.headers().get_one("Cache-Control"),
+        Some("public, max-age=86400")
+    );
+    assert_eq!(response.into_string().as_deref(), Some("[]"));
+}
+
+#[tokio::test]
+#[ignore = "needs the database in SCRAPER_TEST_DATABASE_URL"]
+async fn species_detail_counts_medias() {
+    let mut db = connect().await;
+
+    // The server uses its own connections, so the tables are committed in a schema that is dropped at the end.
+    let schema = "scraper_species_detail";
+    db.client
+        .batch_execute(&format!("DROP SCHEMA IF EXISTS {} CASCADE", schema))
+        .await
+        .unwrap();
+
+    let (mut config, _data) = config("species-detail");
+    config.databases.database.url = format!(
+        "{}?options=-csearch_path%3D{}",
+        config.databases.database.url, schema
+    );
+
+    let transaction = db.transaction().await.unwrap();
+    let seed = seed_schema(&transaction, schema, &["0", "1", "2"]).await;
+    Dataset::create(Uuid::nil(), String::from("Dataset"), None)
+        .save(&transaction)
+        .await
+        .unwrap();
+
+    // A cropped media, a downloaded one, and one whose download failed.
+    for ((i, status_code, cropped), mut media) in [(0, 200, true), (1, 200, false), (2, 404, false)]
+        .into_iter()
+        .zip(seed.medias)
+    {
+        media.status_code = Some(status_code);
+        media.path = Some(format!("{}.jpg", i)).filter(|_| status_code == 200);
+        if cropped {
+            (media.x, media.y, media.width, media.height) =
+                (Some(10.0), Some(20.0), Some(4.0), Some(6.0));
+        }
+        media.save(&transaction).await.unwrap();
+    }
+
+    transaction.commit().await.unwrap();
+
+    let pool = ergol::pool(&config.databases.database.url, 2).unwrap();
+    let rocket = rocket::build()
+        .manage(config)
+        .manage(pool)
+        .manage(DatasetTitles::default())
+        .mount("/", routes![scraper::server::species_detail]);
+    let client = AsyncClient::untracked(rocket).await.unwrap();
+
+    let response = client
+        .get("/api/species/Apis%20mellifera%20Linnaeus,%201758")
+        .dispatch()
+        .await;
+    let status = response.status();
+    let body = response.into_json::<Value>().await;
+
+    let response = client.get("/api/species/Apis%20cerana").dispatch().await;
+    let missing = (response.status(), response.into_json::<Value>().await);
+
+    db.client
+        .batch_execute(&format!("DROP SCHEMA {} CASCADE", schema))
+        .await
+        .unwrap();
+
+    assert_eq!(status, Status::Ok);
+    let body = body.unwrap();
+    assert_eq!(body["valid_name"], "Apis mellifera Linnaeus, 1758");
+    assert_eq!(
+        body["medias"],
+        serde_json::json!({"total": 3, "downloaded": 2, "cropped": 1})
+    );
+    assert_eq!(body["datasets"][0]["title"], "Dataset");
+    assert_eq!(body["datasets"][0]["medias"], 2);
+    assert_eq!(body["max_page"], 1);
+
+    assert_eq!(missing.0, Status::NotFound);
+    assert_eq!(missing.1.unwrap()["kind"], "species_not_found");
+}
+
+#[tokio::test]
+#[ignore = "needs the database in SCRAPER_TEST_DATABASE_URL"]
+async fn unknown_species_and_medias_are_not_found() {
+    let mut db = connect().await;
+
+    // The tables are empty, but the server uses its own connections so they are committed in their own schema.
+    let schema = "scraper_not_found";
+    db.client
+        .batch_execute(&format!("DROP SCHEMA IF EXISTS {} CASCADE", schema))
+        .await
+        .unwrap();
+
+    let transaction = db.transaction().await.unwrap();
+    migrate_schema(&transaction, schema).await;
+    transaction.commit().await.unwrap();
+
+    let (mut config, _data) = config("not-found");
+    config.databases.database.url = format!(
+        "{}?options=-csearch_path%3D{}",
+        config.databases.database.url, schema
+    );
+
+    let pool = ergol::pool(&config.databases.database.url, 2).unwrap();
+    let rocket = rocket::build()
+        .manage(config)
+        .manage(pool)
+        .manage(tera::Tera::default())
+        .mount(
+            "/",
+            routes![scraper::server::species, scraper::server::media],
+        );
+    let client = AsyncClient::untracked(rocket).await.unwrap();
+
+    let mut statuses = vec![];
+    for uri in ["/species/species/Apis%20cerana/1", "/media/1/1/1"] {
+        for accept in [Accept::HTML, Accept::JSON] {
+            let response = client.get(uri).header(accept).dispatch().await;
+            statuses.push(response.status());
+        }
+    }
+
+    db.client
+        .batch_execute(&format!(
+            "RESET search_path; DROP SCHEMA {} CASCADE",
+            schema
+        ))
+        .await
+        .unwrap();
+
+    assert_eq!(statuses, vec![Status::NotFound; 4]);
+}
@@ -0,0 +1,200 @@
+// Public so that the helpers only used by the other test files are not reported as dead code.
+pub mod common;
+
+use std::fs;
+use std::time::Instant;
+
+use scraper::Error;
+use scraper::config::TaxrefConfig;
+use scraper::taxref::{Columns, Entry, Taxon, download, path};
+
+use common::mock_gbif;
+
+const HEADER: &str = "REGNE\tPHYLUM\tCLASSE\tORDRE\tFAMILLE\tSOUS_FAMILLE\tTRIBU\tGROUP1_INPN\tGROUP2_INPN\tGROUP3_INPN\tCD_NOM\tCD_TAXSUP\tCD_SUP\tCD_REF\tRANG\tLB_NOM\tLB_AUTEUR\tNOM_COMPLET\tNOM_COMPLET_HTML\tNOM_VALIDE\tNOM_VERN\tNOM_VERN_ENG\tHABITAT\tFR";
+
+const LINE: &str = "Animalia\tArthropoda\tInsecta\tHymenoptera\tApidae\tApinae\tApini\t\t\t\t1\t2\t3\t1\tES\tApis mellifera\tLinnaeus, 1758\tApis mellifera Linnaeus, 1758\t\tApis mellifera Linnaeus, 1758\tAbeille\tHoney bee\t3\tP";
+
+#[test]
+fn parses_valid_line() {
+    let columns = Columns::from_header(HEADER).unwrap();
+    let entry = Entry::from_line(LINE, 2, &columns).unwrap();
+
+    assert_eq!(entry.family, "Apidae");
+    assert_eq!(entry.genus, "Apis");
+    assert_eq!(entry.valid_name, "Apis mellifera Linnaeus, 1758");
+    assert_eq!(entry.fr, "P");
+}
+
+#[test]
+fn rejects_truncated_line() {
+    let columns = Columns::from_header(HEADER).unwrap();
+    let truncated = LINE.split('\t').take(12).collect::<Vec<_>>().join("\t");
+
+    match Entry::from_line(&truncated, 42, &columns) {
+        Err(Error::TaxrefParseError(e)) => assert_eq!(e.line_number, 42),
+        Err(e) => panic!("unexpected error: {}", e),
+        Ok(_) => panic!("truncated line should not parse"),
+    }
+}
+
+#[test]
+fn navigates_taxa() {
+    let all = Taxon::all();
+
+    for (depth, taxon) in all.iter().enumerate() {
+        assert_eq!(taxon.depth(), depth);
+        assert_eq!(taxon.to_string().parse::<Taxon>().unwrap(), *taxon);
+    }
+
+    assert_eq!(Taxon::Reign.parent(), None);
+    assert_eq!(Taxon::Reign.child(), Some(Taxon::Phylum));
+    assert_eq!(Taxon::Species.parent(), Some(Taxon::Genus));
+    assert_eq!(Taxon::Species.child(), None);
+}
+
+#[test]
+fn round_trips_entry() {
+    let columns = Columns::from_header(HEADER).unwrap();
+    let entry = Entry::from_line(LINE, 2, &columns).unwrap();
+
+    let json = serde_json::to_string(&entry).unwrap();
+    assert_eq!(serde_json::from_str::<Entry>(&json).unwrap(), entry);
+}
+
+#[test]
+fn caches_entries_of_taxon() {
+    let config = TaxrefConfig {
+        url: format!("http://localhost/taxref-cached-{}.txt", std::process::id()),
+        ..TaxrefConfig::default()
+    };
+
+    let taxref = path(&config).unwrap();
+    fs::create_dir_all(taxref.parent().unwrap()).unwrap();
+    fs::write(&taxref, format!("{}\n{}\n", HEADER, LINE)).unwrap();
+
+    let entries = Entry::from_taxon(Taxon::Family, "Apidae", &config).unwrap();
+    let first = Entry::from_taxon_cached(Taxon::Family, "Apidae", &config).unwrap();
+    let second = Entry::from_taxon_cached(Taxon::Family, "apidae", &config).unwrap();
+
+    fs::remove_file(&taxref).unwrap();
+
+    assert_eq!(entries.len(), 1);
+    assert_eq!(first, entries);
+    assert_eq!(second, entries);
+}
+
+#[test]
+fn parses_taxref_in_parallel() {
+    // Enough lines for several chunks, each species appearing twice, with a broken line and an empty line.
+    let mut content = format!("{}\n", HEADER);
+    for i in 0..20_000 {
+        let line = LINE.replace("Apis mellifera", &format!("Apis {}", i % 15_000));
+        content.push_str(&line);
+        content.push('\n');
+    }
+    content.push_str("broken\n\n");
+
+    let mut entries = vec![];
+
+    for threads in [1, 4] {
+        let config = TaxrefConfig {
+            url: format!(
+                "http://localhost/taxref-threads-{}-{}.txt",
+                threads,
+                std::process::id()
+            ),
+            threads,
+            ..TaxrefConfig::default()
+        };
+
+        let taxref = path(&config).unwrap();
+        fs::create_dir_all(taxref.parent().unwrap()).unwrap();
+        fs::write(&taxref, &content).unwrap();
+
+        entries.push(Entry::from_taxon(Taxon::Family, "Apidae", &config).unwrap());
+        fs::remove_file(&taxref).unwrap();
+    }
+
+    assert_eq!(entries[0].len(), 15_000);
+    assert_eq!(entries[0], entries[1]);
+}
+
+#[test]
+#[ignore = "timing, run with --ignored --nocapture to compare the serial and parallel parsing"]
+fn times_serial_and_parallel_parsing() {
+    let mut content = format!("{}\n", HEADER);
+    for i in 0..500_000 {
+        content.push_str(&LINE.replace("Apis mellifera", &format!("Apis {}", i)));
+        content.push('\n');
+    }
+
+    let mut entries = vec![];
+
+    // 0 is one thread per core, so both are the same on a single core.
+    for threads in [1, 0] {
+        let config = TaxrefConfig {
+            url: format!(
+                "http://localhost/taxref-timing-{}-{}.txt",
+                threads,
+                std::process::id()
+            ),
+            threads,
+            ..TaxrefConfig::default()
+        };
+
+        let taxref = path(&config).unwrap();
+        fs::create_dir_all(taxref.parent().unwrap()).unwrap();
+        fs::write(&taxref, &content).unwrap();
+
+        let start = Instant::now();
+        entries.push(Entry::from_taxon(Taxon::Family, "Apidae", &config).unwrap());
+        println!(
+            "threads = {}: {:?} on {} cores",
+            threads,
+            start.elapsed(),
+            std::thread::available_parallelism().map_or(1, |x| x.get())
+        );
+
+        fs::remove_file(&taxref).unwrap();
+    }
+
+    assert_eq!(entries[0], entries[1]);
+}
+
+#[tokio::test]
+async fn downloads_taxref_in_place() {
+    let content = format!("{}\n{}\n", HEADER, LINE);
+    let root = mock_gbif(vec![("/", content.clone())]);
+
+    let mut config = TaxrefConfig {
+        url: format!("{}/taxref-download-{}.txt", root, std::process::id()),
+        min_size: 0,
+        ..TaxrefConfig::default()
+    };
+
+    let taxref = path(&config).unwrap();
+    let dir = taxref.parent().unwrap();
+    fs::create_dir_all(dir).unwrap();
+    let leftovers = || {
+        fs::read_dir(dir)
+            .unwrap()
+            .filter_map(|x| x.ok()?.file_name().into_string().ok())
+            .filter(|x| x.starts_with(&format!("taxref-download-{}", std::process::id())))
+            .filter(|x| x.ends_with(".part"))
+            .count()
+    };
+
+    download(&config).await.unwrap();
+    assert_eq!(fs::read_to_string(&taxref).unwrap(), content);
+    assert_eq!(leftovers(), 0);
+    fs::remove_file(&taxref).unwrap();
+
+    // A file that fails the checks is removed.
+    config.min_size = 1_000_000;
+    assert!(matches!(
+        download(&config).await,
+        Err(Error::CorruptedDownload(_))
+    ));
+    assert!(!taxref.exists());
+    assert_eq!(leftovers(), 0);
+}
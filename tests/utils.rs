@@ -0,0 +1,288 @@
+use std::cell::Cell;
+use std::path::PathBuf;
+use std::time::Duration;
+use std::{env, fs};
+
+use futures::executor::block_on;
+use futures::stream;
+
+use scraper::Error;
+use scraper::config::Storage;
+use scraper::db::SpeciesTrait;
+use scraper::gbif::preprocess;
+use scraper::utils::{
+    RateLimiter, RetryPolicy, create_dir_all, genus, parse_duration, pretty_finder, pretty_name,
+    retry, sanitize_dirname, sha256_file, sha256_stream,
+};
+
+#[test]
+fn splits_name_and_author() {
+    let name = "Apis mellifera Linnaeus, 1758";
+
+    assert_eq!(pretty_name(name), "Apis mellifera");
+    assert_eq!(pretty_finder(name), "Linnaeus, 1758");
+}
+
+#[test]
+fn splits_trinomial_and_author() {
+    let name = "Canis lupus familiaris Linnaeus, 1758";
+
+    assert_eq!(pretty_name(name), "Canis lupus familiaris");
+    assert_eq!(pretty_finder(name), "Linnaeus, 1758");
+}
+
+#[test]
+fn keeps_bare_binomial() {
+    assert_eq!(pretty_name(" Canis lupus "), "Canis lupus");
+    assert_eq!(pretty_finder(" Canis lupus "), "");
+}
+
+#[test]
+fn keeps_bare_trinomial() {
+    assert_eq!(
+        pretty_name("Canis lupus familiaris"),
+        "Canis lupus familiaris"
+    );
+    assert_eq!(pretty_finder("Canis lupus familiaris"), "");
+}
+
+#[test]
+fn keeps_hybrid_sign_in_name() {
+    let name = "Mentha × piperita L.";
+
+    assert_eq!(pretty_name(name), "Mentha × piperita");
+    assert_eq!(pretty_finder(name), "L.");
+    assert_eq!(pretty_name("Mentha ×piperita L."), "Mentha × piperita");
+    assert_eq!(pretty_name("Mentha x piperita L."), "Mentha × piperita");
+}
+
+#[test]
+fn keeps_hybrid_genus_in_name() {
+    let name = "× Triticosecale rimpaui Wittm.";
+
+    assert_eq!(pretty_name(name), "× Triticosecale rimpaui");
+    assert_eq!(pretty_finder(name), "Wittm.");
+}
+
+#[test]
+fn finds_genus_of_name() {
+    assert_eq!(genus("Apis mellifera Linnaeus, 1758"), "Apis");
+    assert_eq!(genus("Mentha × piperita L."), "Mentha");
+    assert_eq!(genus("× Triticosecale rimpaui Wittm."), "× Triticosecale");
+    assert_eq!(genus("×Triticosecale rimpaui Wittm."), "× Triticosecale");
+    assert_eq!(genus("x Triticosecale rimpaui Wittm."), "× Triticosecale");
+    assert_eq!(genus(""), "");
+}
+
+#[test]
+fn keeps_hybrid_sign_in_gbif_query() {
+    assert_eq!(preprocess("Mentha ×piperita L."), "mentha × piperita l.");
+    assert_eq!(preprocess("Mentha x piperita"), "mentha × piperita");
+}
+
+#[test]
+fn drops_rank_connector_from_name() {
+    let name = "Bufo bufo subsp. spinosus Daudin, 1803";
+
+    assert_eq!(pretty_name(name), "Bufo bufo spinosus");
+    assert_eq!(pretty_finder(name), "Daudin, 1803");
+    assert_eq!(pretty_name("Bufo bufo subsp. bufo"), "Bufo bufo bufo");
+}
+
+#[test]
+fn keeps_infraspecific_author() {
+    let name = "Salix alba L. var. vitellina (L.) Stokes";
+
+    assert_eq!(pretty_name(name), "Salix alba vitellina");
+    assert_eq!(pretty_finder(name), "L. Stokes");
+}
+
+#[test]
+fn keeps_filius_in_author() {
+    let name = "Ilex crenata Thunb. f.";
+
+    assert_eq!(pretty_name(name), "Ilex crenata");
+    assert_eq!(pretty_finder(name), "Thunb. f.");
+}
+
+#[test]
+fn sanitizes_dirnames() {
+    assert_eq!(sanitize_dirname("Canis  lupus"), "canis_lupus");
+    assert_eq!(sanitize_dirname("Écrevisse à pattes"), "ecrevisse_a_pattes");
+    assert_eq!(sanitize_dirname("Mentha × piperita"), "mentha_x_piperita");
+    assert_eq!(sanitize_dirname("../etc/passwd"), "etcpasswd");
+    assert_eq!(sanitize_dirname("(.)"), "unnamed");
+}
+
+/// A species that is only a valid name.
+struct Name(&'static str);
+
+impl SpeciesTrait for Name {
+    fn valid_name(&self) -> &str {
+        self.0
+    }
+}
+
+#[test]
+fn keeps_legacy_species_dirs() {
+    let dir = tempfile::tempdir().unwrap();
+    let storage = Storage {
+        data_path: dir.path().to_owned(),
+    };
+
+    let species = Name("Apis mellifera Linnaeus, 1758");
+    assert_eq!(
+        storage.medias_dir_local(&species),
+        PathBuf::from("apis_mellifera")
+    );
+
+    // A directory named before the names were sanitized is still used, until the sanitized one exists.
+    fs::create_dir_all(storage.medias_root().join("Apis mellifera")).unwrap();
+    assert_eq!(
+        storage.medias_dir_local(&species),
+        PathBuf::from("Apis mellifera")
+    );
+
+    fs::create_dir_all(storage.medias_root().join("apis_mellifera")).unwrap();
+    assert_eq!(
+        storage.medias_dir_local(&species),
+        PathBuf::from("apis_mellifera")
+    );
+
+    // An old name that is not a single directory is never used.
+    fs::create_dir_all(storage.medias_root().join("a/b")).unwrap();
+    assert_eq!(storage.medias_dir_local(&Name("a/b")), PathBuf::from("ab"));
+}
+
+#[test]
+fn sanitizes_dirnames_idempotently() {
+    for name in [
+        "Canis lupus",
+        "Mentha × piperita",
+        "Bufo bufo (spinosus)",
+        "a_ _b",
+    ] {
+        let sanitized = sanitize_dirname(name);
+        assert_eq!(sanitize_dirname(&sanitized), sanitized);
+    }
+}
+
+/// The SHA-256 of `abc`, from FIPS 180-2.
+const ABC_SHA256: &str = "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad";
+
+#[test]
+fn hashes_stream() {
+    let chunks = stream::iter(vec![Ok::<_, Error>("a"), Ok("bc")]);
+    assert_eq!(block_on(sha256_stream(chunks)).unwrap(), ABC_SHA256);
+
+    let empty = stream::iter(Vec::<Result<&str, Error>>::new());
+    assert_eq!(
+        block_on(sha256_stream(empty)).unwrap(),
+        "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+    );
+}
+
+#[test]
+fn hashes_file() {
+    let path = env::temp_dir().join(format!("scraper-sha256-{}", std::process::id()));
+    fs::write(&path, "abc").unwrap();
+
+    let hash = sha256_file(&path);
+    fs::remove_file(&path).unwrap();
+
+    assert_eq!(hash.unwrap(), ABC_SHA256);
+}
+
+#[tokio::test]
+async fn retries_until_success() {
+    let attempts = Cell::new(0);
+    let policy = RetryPolicy::new(5, Duration::ZERO);
+
+    let result = retry(
+        policy,
+        |attempt| {
+            attempts.set(attempt);
+            async move { Ok(attempt) }
+        },
+        |result| matches!(result, Ok(attempt) if *attempt < 3),
+    )
+    .await;
+
+    assert_eq!(result.unwrap(), 3);
+    assert_eq!(attempts.get(), 3);
+}
+
+#[tokio::test]
+async fn stops_retrying_after_last_attempt() {
+    let policy = RetryPolicy::new(2, Duration::ZERO);
+    let result = retry(policy, |attempt| async move { Ok(attempt) }, |_| true).await;
+    assert_eq!(result.unwrap(), 2);
+}
+
+#[test]
+fn jitters_backoff() {
+    let policy = RetryPolicy::new(5, Duration::from_secs(2));
+
+    for attempt in 1..5 {
+        let max = Duration::from_secs(2 << (attempt - 1));
+        let delay = policy.delay(attempt);
+        assert!(max / 2 <= delay && delay <= max);
+    }
+}
+
+#[test]
+fn parses_human_durations() {
+    assert_eq!(parse_duration("30d"), Some(Duration::from_secs(30 * 86400)));
+    assert_eq!(parse_duration("12h"), Some(Duration::from_secs(12 * 3600)));
+    assert_eq!(parse_duration("15m"), Some(Duration::from_secs(900)));
+    assert_eq!(parse_duration("d"), None);
+    assert_eq!(parse_duration("12"), None);
+    assert_eq!(parse_duration("-1d"), None);
+    assert_eq!(parse_duration("3y"), None);
+}
+
+#[test]
+fn rate_limiter_throttles_after_burst() {
+    let limiter = RateLimiter::new();
+    assert_eq!(limiter.reserve(), Duration::ZERO);
+
+    limiter.set_rate(Some(2.0));
+    assert_eq!(limiter.reserve(), Duration::ZERO);
+    assert_eq!(limiter.reserve(), Duration::ZERO);
+
+    let wait = limiter.reserve();
+    assert!(Duration::from_millis(400) < wait && wait <= Duration::from_millis(500));
+
+    limiter.set_rate(None);
+    assert_eq!(limiter.reserve(), Duration::ZERO);
+}
+
+#[tokio::test]
+async fn reports_paths_that_are_not_writable() {
+    // A directory can't be created inside a file, even by root which ignores permissions.
+    let file = env::temp_dir().join(format!("scraper-not-a-dir-{}", std::process::id()));
+    fs::write(&file, b"").unwrap();
+
+    let storage = Storage {
+        data_path: file.join("data"),
+    };
+
+    match storage.check_writable() {
+        Err(Error::NotWritable(path, _)) => assert_eq!(path, storage.data_path),
+        result => panic!("unexpected result: {:?}", result),
+    }
+
+    match create_dir_all(&storage.species_dir()).await {
+        Err(e @ Error::NotWritable(_, _)) => assert!(e.to_string().contains("data/species")),
+        result => panic!("unexpected result: {:?}", result),
+    }
+
+    let storage = Storage {
+        data_path: env::temp_dir().join(format!("scraper-writable-{}", std::process::id())),
+    };
+    storage.check_writable().unwrap();
+    assert_eq!(fs::read_dir(&storage.data_path).unwrap().count(), 0);
+
+    fs::remove_file(&file).unwrap();
+    fs::remove_dir(&storage.data_path).unwrap();
+}